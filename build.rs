@@ -1,3 +1,6 @@
+/// Fewest packets the BLE packet pool may hold, see `main`.
+const PACKET_POOL_SIZE_MIN: usize = 16;
+
 fn main() {
     let _ = dotenvy::from_filename(".env");
     println!("cargo:rerun-if-changed=.env");
@@ -17,6 +20,49 @@ fn main() {
 
     println!("cargo:rustc-env=MAC_ADDRESS={reversed_mac_str}");
 
+    // Wi-Fi is optional: leave WIFI_SSID empty to run BLE only
+    for (key, default) in [
+        ("WIFI_SSID", ""),
+        ("WIFI_PASSWORD", ""),
+        ("NTP_SERVER", "pool.ntp.org"),
+        ("STATUS_LED", "rgb"),
+        // peripherals to connect to as a central, see src/peer.rs
+        ("PEERS", ""),
+        // "on" to re-broadcast the status records of other boards, see src/relay.rs
+        ("RELAY", "off"),
+        // "on" to raise an alarm when the central walks away, see src/proximity.rs
+        ("LEASH", "off"),
+        // ADC pin of the battery divider (GPIO0-4, or none), its ratio and the cell
+        // chemistry, see src/battery.rs
+        ("BATTERY_PIN", "none"),
+        ("BATTERY_DIVIDER", "2"),
+        ("BATTERY_CHEMISTRY", "li-ion"),
+        // seconds without a connection before deep sleep (0: never), how long it lasts and
+        // the pin of a button that ends it early (GPIO0-4, or none), see src/power.rs
+        ("DEEP_SLEEP_SECS", "0"),
+        ("WAKE_SECS", "300"),
+        ("WAKE_PIN", "none"),
+        // PSM the central opens the L2CAP stream on, see src/stream.rs
+        ("L2CAP_PSM", "0x0080"),
+    ] {
+        let value = std::env::var(key).unwrap_or_else(|_| default.to_string());
+        println!("cargo:rustc-env={key}={value}");
+    }
+
+    // BLE packet pool from .cargo/config.toml: the 8 stream credits of src/stream.rs plus
+    // two packets for each of the 3 connections of src/task/ble.rs. The MTU is checked
+    // against what trouble-host was built with in src/task/ble.rs.
+    println!("cargo:rerun-if-env-changed=TROUBLE_HOST_DEFAULT_PACKET_POOL_SIZE");
+    let pool_size = std::env::var("TROUBLE_HOST_DEFAULT_PACKET_POOL_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<usize>().ok());
+    match pool_size {
+        Some(size) if size >= PACKET_POOL_SIZE_MIN => {}
+        _ => panic!(
+            "TROUBLE_HOST_DEFAULT_PACKET_POOL_SIZE must be at least {PACKET_POOL_SIZE_MIN}, got {pool_size:?}"
+        ),
+    }
+
     linker_be_nice();
     println!("cargo:rustc-link-arg-tests=-Tembedded-test.x");
     println!("cargo:rustc-link-arg=-Tdefmt.x");
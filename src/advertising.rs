@@ -0,0 +1,326 @@
+//! Advertising payload.
+//!
+//! Builds the advertising data and scan response from [`AdvertisedFields`]. The advertising
+//! data carries a compact [`Status`] record in the manufacturer specific data, so scanners
+//! get the temperature, battery and alarm state without connecting. The connectionless
+//! [`bthome`] and [`eddystone_tlm`] beacons follow public formats instead, so off-the-shelf
+//! receivers understand them. Everything is encoded here as plain AD structures (length,
+//! type, data) so it can be tested off target.
+
+use crate::alarm::AlarmState;
+use crate::settings::AdvertisedFields;
+
+/// Longest legacy advertising data or scan response.
+pub const MAX_AD_LEN: usize = 31;
+/// Longest extended advertising data we send, what fits in one HCI command.
+pub const MAX_EXT_AD_LEN: usize = 251;
+
+/// Company identifier in the manufacturer specific data.
+pub const COMPANY_ID: u16 = 0x0245;
+
+/// Version of the [`Status`] record, bumped whenever its layout changes.
+pub const STATUS_VERSION: u8 = 1;
+/// Size of the encoded [`Status`].
+pub const STATUS_LEN: usize = 5;
+
+/// AD types from the Bluetooth Assigned Numbers.
+pub mod ad_type {
+    pub const FLAGS: u8 = 0x01;
+    pub const COMPLETE_SERVICE_UUIDS_16: u8 = 0x03;
+    pub const COMPLETE_SERVICE_UUIDS_128: u8 = 0x07;
+    pub const SHORTENED_LOCAL_NAME: u8 = 0x08;
+    pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
+    pub const SERVICE_DATA_16: u8 = 0x16;
+    pub const MANUFACTURER_SPECIFIC_DATA: u8 = 0xFF;
+}
+
+/// 16-bit service UUID of BTHome service data.
+pub const BTHOME_UUID: u16 = 0xFCD2;
+/// 16-bit service UUID of Eddystone frames.
+pub const EDDYSTONE_UUID: u16 = 0xFEAA;
+
+/// BTHome v2 object IDs, see <https://bthome.io/format/>.
+pub mod bthome_id {
+    pub const PACKET_ID: u8 = 0x00;
+    pub const BATTERY: u8 = 0x01;
+    pub const TEMPERATURE: u8 = 0x02;
+    pub const HUMIDITY: u8 = 0x03;
+    pub const PRESSURE: u8 = 0x04;
+    pub const COLD: u8 = 0x18;
+    pub const HEAT: u8 = 0x1D;
+    pub const PROBLEM: u8 = 0x26;
+}
+
+/// BTHome device information: version 2, not encrypted, sent at regular intervals.
+const BTHOME_DEVICE_INFO: u8 = 0x40;
+
+/// Eddystone-TLM frame type and the unencrypted version.
+const TLM_FRAME: u8 = 0x20;
+const TLM_VERSION: u8 = 0x00;
+/// TLM temperature while there is no reading.
+const TLM_NO_TEMPERATURE: i16 = i16::MIN;
+
+/// LE General Discoverable, BR/EDR not supported.
+pub(crate) const DISCOVERABLE: u8 = 0x02 | 0x04;
+
+/// Temperature sent while there is no reading yet.
+const NO_TEMPERATURE: i16 = i16::MIN;
+/// Battery level sent while it is unknown.
+const NO_BATTERY: u8 = 0xFF;
+
+const STATUS_ALARM_HIGH: u8 = 1 << 0;
+const STATUS_ALARM_LOW: u8 = 1 << 1;
+const STATUS_FAULT: u8 = 1 << 2;
+
+/// What the board reports to scanners.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Status {
+    /// Temperature in 0.01 °C.
+    pub temperature: Option<i16>,
+    /// Battery level in percent.
+    pub battery: Option<u8>,
+    pub alarm: AlarmState,
+    /// Something needs attention, see [`crate::state::FAULT`].
+    pub fault: bool,
+}
+
+impl Status {
+    /// Version, temperature (i16, little-endian, `0x8000` if unknown), battery (`0xFF` if
+    /// unknown) and flags: bit 0 too hot, bit 1 too cold, bit 2 fault.
+    pub fn encode(&self) -> [u8; STATUS_LEN] {
+        let temperature = self.temperature.unwrap_or(NO_TEMPERATURE).to_le_bytes();
+        let mut flags = 0;
+        match self.alarm {
+            AlarmState::High => flags |= STATUS_ALARM_HIGH,
+            AlarmState::Low => flags |= STATUS_ALARM_LOW,
+            AlarmState::Normal => {}
+        }
+        if self.fault {
+            flags |= STATUS_FAULT;
+        }
+        [
+            STATUS_VERSION,
+            temperature[0],
+            temperature[1],
+            self.battery.unwrap_or(NO_BATTERY),
+            flags,
+        ]
+    }
+
+    /// Parse a record from another board. Rejects other versions and unknown flags.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; STATUS_LEN] = data.try_into().ok()?;
+        let flags = data[4];
+        if data[0] != STATUS_VERSION
+            || flags & !(STATUS_ALARM_HIGH | STATUS_ALARM_LOW | STATUS_FAULT) != 0
+        {
+            return None;
+        }
+        let temperature = i16::from_le_bytes([data[1], data[2]]);
+        let alarm = match flags & (STATUS_ALARM_HIGH | STATUS_ALARM_LOW) {
+            0 => AlarmState::Normal,
+            STATUS_ALARM_HIGH => AlarmState::High,
+            STATUS_ALARM_LOW => AlarmState::Low,
+            _ => return None,
+        };
+        Some(Self {
+            temperature: (temperature != NO_TEMPERATURE).then_some(temperature),
+            battery: (data[3] != NO_BATTERY).then_some(data[3]),
+            alarm,
+            fault: flags & STATUS_FAULT != 0,
+        })
+    }
+}
+
+/// AD structures for one advertising data or scan response packet, legacy by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdData<const N: usize = MAX_AD_LEN> {
+    data: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> AdData<N> {
+    pub const fn new() -> Self {
+        Self {
+            data: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Bytes still free, including the length and type of the next structure.
+    pub fn remaining(&self) -> usize {
+        N - self.len
+    }
+
+    /// Append an AD structure made of `parts`. Returns `false` and leaves the packet as it
+    /// was if it does not fit.
+    #[must_use]
+    pub fn push(&mut self, ad_type: u8, parts: &[&[u8]]) -> bool {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        // the length byte covers the type and data
+        if len + 2 > self.remaining() || len + 1 > u8::MAX as usize {
+            return false;
+        }
+        self.data[self.len] = (len + 1) as u8;
+        self.data[self.len + 1] = ad_type;
+        self.len += 2;
+        for part in parts {
+            self.data[self.len..self.len + part.len()].copy_from_slice(part);
+            self.len += part.len();
+        }
+        true
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl<const N: usize> Default for AdData<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Advertising data: flags, the service UUID and the status record, each if enabled. With
+/// everything enabled this takes 30 of the 31 bytes.
+pub fn advertising_data(
+    fields: &AdvertisedFields,
+    service_uuid: &[u8; 16],
+    status: &Status,
+) -> AdData {
+    let mut data = AdData::new();
+    // flags are mandatory for connectable advertising, the rest fits after them
+    let _ = data.push(ad_type::FLAGS, &[&[DISCOVERABLE]]);
+    if fields.service_uuid {
+        let _ = data.push(ad_type::COMPLETE_SERVICE_UUIDS_128, &[service_uuid]);
+    }
+    if fields.status {
+        let _ = data.push(
+            ad_type::MANUFACTURER_SPECIFIC_DATA,
+            &[&COMPANY_ID.to_le_bytes(), &status.encode()],
+        );
+    }
+    data
+}
+
+/// Extended advertising data, which cannot have a scan response when connectable: the
+/// legacy advertising data followed by the complete device name if enabled.
+pub fn extended_data(
+    fields: &AdvertisedFields,
+    name: &str,
+    service_uuid: &[u8; 16],
+    status: &Status,
+) -> AdData<MAX_EXT_AD_LEN> {
+    let legacy = advertising_data(fields, service_uuid, status);
+    let mut data = AdData::new();
+    data.data[..legacy.len].copy_from_slice(legacy.as_bytes());
+    data.len = legacy.len;
+    if fields.name {
+        let _ = data.push(ad_type::COMPLETE_LOCAL_NAME, &[name.as_bytes()]);
+    }
+    data
+}
+
+/// Scan response: the device name if enabled, shortened if it does not fit.
+pub fn scan_response(fields: &AdvertisedFields, name: &str) -> AdData {
+    let mut data = AdData::new();
+    if fields.name {
+        let max = data.remaining() - 2;
+        let (ad_type, name) = if name.len() > max {
+            (ad_type::SHORTENED_LOCAL_NAME, &name.as_bytes()[..max])
+        } else {
+            (ad_type::COMPLETE_LOCAL_NAME, name.as_bytes())
+        };
+        let _ = data.push(ad_type, &[name]);
+    }
+    data
+}
+
+/// BTHome v2 broadcast: flags and BTHome service data with the packet ID, battery,
+/// temperature, humidity and pressure as far as known, and heat, cold and problem binary
+/// sensors for the alarm and fault. Objects are sorted by ID as the format asks. Receivers
+/// drop repeated packet IDs, so `packet_id` should change whenever the values do.
+pub fn bthome(
+    status: &Status,
+    humidity: Option<u16>,
+    pressure: Option<u32>,
+    packet_id: u8,
+) -> AdData {
+    let mut objects: heapless::Vec<u8, 24> = heapless::Vec::new();
+    let mut object = |id: u8, value: &[u8]| {
+        // 24 bytes hold every object once
+        let _ = objects.push(id);
+        let _ = objects.extend_from_slice(value);
+    };
+    object(bthome_id::PACKET_ID, &[packet_id]);
+    if let Some(battery) = status.battery {
+        object(bthome_id::BATTERY, &[battery]);
+    }
+    if let Some(temperature) = status.temperature {
+        // 0.01 °C, same as ours
+        object(bthome_id::TEMPERATURE, &temperature.to_le_bytes());
+    }
+    if let Some(humidity) = humidity {
+        // 0.01 %, same as ours
+        object(bthome_id::HUMIDITY, &humidity.to_le_bytes());
+    }
+    if let Some(pressure) = pressure {
+        // 0.01 hPa as uint24, ours is 0.1 Pa
+        let pa = (pressure / 10).min(0xFF_FFFF);
+        object(bthome_id::PRESSURE, &pa.to_le_bytes()[..3]);
+    }
+    object(bthome_id::COLD, &[(status.alarm == AlarmState::Low) as u8]);
+    object(bthome_id::HEAT, &[(status.alarm == AlarmState::High) as u8]);
+    object(bthome_id::PROBLEM, &[status.fault as u8]);
+
+    let mut data = AdData::new();
+    let _ = data.push(ad_type::FLAGS, &[&[DISCOVERABLE]]);
+    let _ = data.push(
+        ad_type::SERVICE_DATA_16,
+        &[&BTHOME_UUID.to_le_bytes(), &[BTHOME_DEVICE_INFO], &objects],
+    );
+    data
+}
+
+/// What an Eddystone-TLM frame reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Telemetry {
+    /// Battery voltage in mV.
+    pub battery_mv: Option<u16>,
+    /// Temperature in 0.01 °C.
+    pub temperature: Option<i16>,
+    /// Advertising PDUs sent since boot.
+    pub adv_count: u32,
+    /// Time since boot in 0.1 s.
+    pub uptime_ds: u32,
+}
+
+/// Unencrypted Eddystone-TLM frame: flags, the Eddystone service UUID and the frame with
+/// battery voltage (0 if unknown), temperature in signed 8.8 fixed point (`0x8000` if
+/// unknown), advertising count and uptime, all big-endian.
+pub fn eddystone_tlm(telemetry: &Telemetry) -> AdData {
+    let temperature = match telemetry.temperature {
+        // 0.01 °C to 1/256 °C, the 8.8 range ends at 128 °C
+        Some(t) => (t as i32 * 256 / 100).clamp(-(i16::MAX as i32), i16::MAX as i32) as i16,
+        None => TLM_NO_TEMPERATURE,
+    };
+    let mut data = AdData::new();
+    let _ = data.push(ad_type::FLAGS, &[&[DISCOVERABLE]]);
+    let _ = data.push(
+        ad_type::COMPLETE_SERVICE_UUIDS_16,
+        &[&EDDYSTONE_UUID.to_le_bytes()],
+    );
+    let _ = data.push(
+        ad_type::SERVICE_DATA_16,
+        &[
+            &EDDYSTONE_UUID.to_le_bytes(),
+            &[TLM_FRAME, TLM_VERSION],
+            &telemetry.battery_mv.unwrap_or(0).to_be_bytes(),
+            &temperature.to_be_bytes(),
+            &telemetry.adv_count.to_be_bytes(),
+            &telemetry.uptime_ds.to_be_bytes(),
+        ],
+    );
+    data
+}
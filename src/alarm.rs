@@ -0,0 +1,193 @@
+//! High/low temperature alarm with hysteresis.
+//!
+//! Values are in hundredths of a degree Celsius like [`crate::history`]. An alarm is raised as
+//! soon as a threshold is reached and only cleared once the value has moved back past the
+//! threshold by at least the hysteresis, so a reading hovering around a threshold does not
+//! toggle the alarm on every sample.
+
+/// ESS Trigger Setting conditions (Bluetooth Environmental Sensing Service, 0x290D) we act on.
+pub mod condition {
+    pub const INACTIVE: u8 = 0x00;
+    pub const LESS_THAN_OR_EQUAL: u8 = 0x05;
+    pub const GREATER_THAN_OR_EQUAL: u8 = 0x07;
+}
+
+/// ES Configuration (0x290B) value: the triggers are combined with a boolean OR, the only
+/// combination we support.
+pub const CONFIGURATION_OR: u8 = 0x01;
+
+/// Size of an ES Trigger Setting value with an i16 operand.
+pub const TRIGGER_LEN: usize = 3;
+
+/// The threshold an ES Trigger Setting descriptor holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Trigger {
+    High,
+    Low,
+}
+
+impl Trigger {
+    /// The condition that raises the alarm.
+    pub fn condition(self) -> u8 {
+        match self {
+            Trigger::High => condition::GREATER_THAN_OR_EQUAL,
+            Trigger::Low => condition::LESS_THAN_OR_EQUAL,
+        }
+    }
+}
+
+/// Why a trigger setting was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum TriggerError {
+    /// Not [`TRIGGER_LEN`] bytes.
+    Length,
+    /// A condition other than inactive or the one of the threshold.
+    Condition,
+    /// The low threshold would be at or above the high one.
+    Range,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum AlarmState {
+    Normal,
+    High,
+    Low,
+}
+
+impl AlarmState {
+    pub fn is_active(&self) -> bool {
+        *self != AlarmState::Normal
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Thresholds {
+    /// Raise [`AlarmState::High`] at or above this value.
+    pub high: Option<i16>,
+    /// Raise [`AlarmState::Low`] at or below this value.
+    pub low: Option<i16>,
+    /// How far the value has to move back past a threshold to clear the alarm.
+    pub hysteresis: u16,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            high: Some(60_00),
+            low: None,
+            hysteresis: 2_00,
+        }
+    }
+}
+
+impl Thresholds {
+    /// ES Trigger Setting value of one threshold: its condition and the i16 operand, or
+    /// inactive with a zero operand.
+    pub fn encode_trigger(&self, trigger: Trigger) -> [u8; TRIGGER_LEN] {
+        let threshold = match trigger {
+            Trigger::High => self.high,
+            Trigger::Low => self.low,
+        };
+        let Some(threshold) = threshold else {
+            return [condition::INACTIVE, 0, 0];
+        };
+        let operand = threshold.to_le_bytes();
+        [trigger.condition(), operand[0], operand[1]]
+    }
+
+    /// These thresholds with a trigger setting written to the descriptor of `trigger`
+    /// applied. Rejects other conditions and a low threshold at or above the high one.
+    pub fn with_trigger(&self, trigger: Trigger, data: &[u8]) -> Result<Self, TriggerError> {
+        let &[cond, lo, hi] = data else {
+            return Err(TriggerError::Length);
+        };
+        let threshold = match cond {
+            condition::INACTIVE => None,
+            c if c == trigger.condition() => Some(i16::from_le_bytes([lo, hi])),
+            _ => return Err(TriggerError::Condition),
+        };
+        let thresholds = match trigger {
+            Trigger::High => Self {
+                high: threshold,
+                ..*self
+            },
+            Trigger::Low => Self {
+                low: threshold,
+                ..*self
+            },
+        };
+        if let (Some(high), Some(low)) = (thresholds.high, thresholds.low) {
+            if low >= high {
+                return Err(TriggerError::Range);
+            }
+        }
+        Ok(thresholds)
+    }
+}
+
+pub struct Alarm {
+    thresholds: Thresholds,
+    state: AlarmState,
+}
+
+impl Alarm {
+    pub const fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            state: AlarmState::Normal,
+        }
+    }
+
+    pub fn state(&self) -> AlarmState {
+        self.state
+    }
+
+    pub fn thresholds(&self) -> Thresholds {
+        self.thresholds
+    }
+
+    /// Replace the thresholds. The current state is kept and re-evaluated on the next sample.
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+        self.thresholds = thresholds;
+    }
+
+    /// Feed a new sample, returns the new state if it changed.
+    pub fn update(&mut self, value: i16) -> Option<AlarmState> {
+        let hysteresis = self.thresholds.hysteresis as i32;
+        let value = value as i32;
+        let at_or_above_high = |h: i16| value >= h as i32;
+        let at_or_below_low = |l: i16| value <= l as i32;
+
+        let next = match self.state {
+            AlarmState::Normal => {
+                if self.thresholds.high.is_some_and(at_or_above_high) {
+                    AlarmState::High
+                } else if self.thresholds.low.is_some_and(at_or_below_low) {
+                    AlarmState::Low
+                } else {
+                    AlarmState::Normal
+                }
+            }
+            AlarmState::High => match self.thresholds.high {
+                Some(high) if value >= high as i32 || value > high as i32 - hysteresis => {
+                    AlarmState::High
+                }
+                _ if self.thresholds.low.is_some_and(at_or_below_low) => AlarmState::Low,
+                _ => AlarmState::Normal,
+            },
+            AlarmState::Low => match self.thresholds.low {
+                Some(low) if value <= low as i32 || value < low as i32 + hysteresis => {
+                    AlarmState::Low
+                }
+                _ if self.thresholds.high.is_some_and(at_or_above_high) => AlarmState::High,
+                _ => AlarmState::Normal,
+            },
+        };
+
+        if next == self.state {
+            return None;
+        }
+        self.state = next;
+        Some(next)
+    }
+}
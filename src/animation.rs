@@ -0,0 +1,365 @@
+//! Data-driven cow animations.
+//!
+//! Animations are plain text, included at compile time and read in place without
+//! allocating:
+//!
+//! ```text
+//! # comment
+//! anim idle loop
+//! frame 1000
+//! |  ^__^
+//! :  ..##..
+//! on connect blink
+//! ```
+//!
+//! `anim` starts an animation, `loop` or `once`, and `frame` one of its frames with its
+//! duration in ms. `|` rows are text drawn with the display font, 7 px apart; `:` rows are
+//! pixels, `#` lit, 1 px high. `on` picks the animation for an [`Event`].
+//!
+//! A looping animation started by an event replaces the current one, a `once` animation
+//! plays on top of it and then hands back. Times are milliseconds since boot.
+
+/// The cow shipped with the firmware.
+pub const COW: &str = include_str!("../assets/cow.anim");
+
+/// Animation played from boot until an event picks another one.
+pub const IDLE: &str = "idle";
+
+/// Something the cow reacts to, named `on <event>` in the asset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Event {
+    Connect,
+    Disconnect,
+    Hot,
+    Cold,
+    Normal,
+}
+
+impl Event {
+    pub const ALL: [Event; 5] = [
+        Event::Connect,
+        Event::Disconnect,
+        Event::Hot,
+        Event::Cold,
+        Event::Normal,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::Connect => "connect",
+            Event::Disconnect => "disconnect",
+            Event::Hot => "hot",
+            Event::Cold => "cold",
+            Event::Normal => "normal",
+        }
+    }
+}
+
+/// Why an asset was rejected, with the 1-based line it was found on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ParseError {
+    /// 0 for problems with the asset as a whole.
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+/// One row of a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Row<'a> {
+    Text(&'a str),
+    Pixels(&'a str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Line<'a> {
+    Anim { name: &'a str, looping: bool },
+    Frame(u32),
+    Row(Row<'a>),
+    On { event: &'a str, anim: &'a str },
+    Blank,
+}
+
+fn parse_line(line: &str) -> Result<Line<'_>, &'static str> {
+    // rows are taken verbatim, leading spaces matter
+    if let Some(text) = line.strip_prefix('|') {
+        return Ok(Line::Row(Row::Text(text)));
+    }
+    if let Some(pixels) = line.strip_prefix(':') {
+        return Ok(Line::Row(Row::Pixels(pixels)));
+    }
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(Line::Blank);
+    }
+    let mut words = line.split_whitespace();
+    let parsed = match (words.next(), words.next(), words.next()) {
+        (Some("anim"), Some(name), Some(mode)) => Line::Anim {
+            name,
+            looping: match mode {
+                "loop" => true,
+                "once" => false,
+                _ => return Err("expected `loop` or `once`"),
+            },
+        },
+        (Some("frame"), Some(ms), None) => match ms.parse() {
+            Ok(ms) if ms > 0 => Line::Frame(ms),
+            _ => return Err("bad frame duration"),
+        },
+        (Some("on"), Some(event), Some(anim)) => Line::On { event, anim },
+        _ => return Err("unknown line"),
+    };
+    if words.next().is_some() {
+        return Err("trailing words");
+    }
+    Ok(parsed)
+}
+
+/// Lines from the 0-based line `first` on. Only used on validated sources, so anything
+/// unreadable can be skipped.
+fn lines_from(source: &str, first: usize) -> impl Iterator<Item = Line<'_>> {
+    source
+        .lines()
+        .skip(first)
+        .map(|line| parse_line(line).unwrap_or(Line::Blank))
+}
+
+/// A validated animation asset.
+#[derive(Clone, Copy, Debug)]
+pub struct Library<'a> {
+    source: &'a str,
+}
+
+impl<'a> Library<'a> {
+    /// Check the whole asset once, so that playing it never has to deal with errors.
+    pub fn parse(source: &'a str) -> Result<Self, ParseError> {
+        let library = Self { source };
+        // (line, frames) of the animation being read
+        let mut current: Option<(usize, usize)> = None;
+        let mut in_frame = false;
+
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            let error = |reason| ParseError {
+                line: number,
+                reason,
+            };
+            match parse_line(line).map_err(error)? {
+                Line::Anim { name, .. } => {
+                    end_animation(current)?;
+                    if library.find(name, index).is_some() {
+                        return Err(error("duplicate animation"));
+                    }
+                    current = Some((number, 0));
+                    in_frame = false;
+                }
+                Line::Frame(_) => match &mut current {
+                    Some((_, frames)) => {
+                        *frames += 1;
+                        in_frame = true;
+                    }
+                    None => return Err(error("frame outside of an animation")),
+                },
+                Line::Row(_) if !in_frame => return Err(error("row outside of a frame")),
+                Line::Row(_) | Line::Blank => {}
+                Line::On { event, anim } => {
+                    end_animation(current.take())?;
+                    in_frame = false;
+                    if !Event::ALL.iter().any(|known| known.name() == event) {
+                        return Err(error("unknown event"));
+                    }
+                    if library.animation(anim).is_none() {
+                        return Err(error("unknown animation"));
+                    }
+                }
+            }
+        }
+        end_animation(current)?;
+
+        match library.animation(IDLE) {
+            Some(idle) if idle.looping => Ok(library),
+            // `first` is the 0-based line after the header, i.e. the header's line number
+            Some(idle) => Err(ParseError {
+                line: idle.first,
+                reason: "idle animation must loop",
+            }),
+            None => Err(ParseError {
+                line: 0,
+                reason: "no idle animation",
+            }),
+        }
+    }
+
+    pub fn animation(&self, name: &str) -> Option<Animation<'a>> {
+        self.find(name, usize::MAX)
+    }
+
+    /// The animation to play on `event`, if the asset has one.
+    pub fn on(&self, event: Event) -> Option<Animation<'a>> {
+        lines_from(self.source, 0).find_map(|line| match line {
+            Line::On { event: name, anim } if name == event.name() => self.animation(anim),
+            _ => None,
+        })
+    }
+
+    /// Look `name` up among the animations declared before the 0-based line `before`.
+    fn find(&self, name: &str, before: usize) -> Option<Animation<'a>> {
+        lines_from(self.source, 0)
+            .take(before)
+            .enumerate()
+            .find_map(|(index, line)| match line {
+                Line::Anim {
+                    name: found,
+                    looping,
+                } if found == name => Some(Animation {
+                    name: found,
+                    looping,
+                    source: self.source,
+                    first: index + 1,
+                }),
+                _ => None,
+            })
+    }
+}
+
+fn end_animation(current: Option<(usize, usize)>) -> Result<(), ParseError> {
+    match current {
+        Some((line, 0)) => Err(ParseError {
+            line,
+            reason: "animation without frames",
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Animation<'a> {
+    pub name: &'a str,
+    pub looping: bool,
+    source: &'a str,
+    /// 0-based line after the `anim` header.
+    first: usize,
+}
+
+impl PartialEq for Animation<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self.source, other.source) && self.first == other.first
+    }
+}
+
+impl<'a> Animation<'a> {
+    pub fn frames(&self) -> impl Iterator<Item = Frame<'a>> {
+        let (source, first) = (self.source, self.first);
+        lines_from(source, first)
+            .take_while(|line| !matches!(line, Line::Anim { .. } | Line::On { .. }))
+            .enumerate()
+            .filter_map(move |(index, line)| match line {
+                Line::Frame(duration_ms) => Some(Frame {
+                    duration_ms,
+                    source,
+                    first: first + index + 1,
+                }),
+                _ => None,
+            })
+    }
+
+    /// Length of one pass in milliseconds.
+    pub fn duration_ms(&self) -> u64 {
+        self.frames().map(|frame| frame.duration_ms as u64).sum()
+    }
+
+    /// Frame shown `elapsed` ms after the start and how long it stays up, `None` once a
+    /// `once` animation is over.
+    pub fn frame_at(&self, elapsed: u64) -> Option<(Frame<'a>, u64)> {
+        let mut elapsed = elapsed;
+        if self.looping {
+            elapsed %= self.duration_ms();
+        }
+        let mut end = 0;
+        for frame in self.frames() {
+            end += frame.duration_ms as u64;
+            if elapsed < end {
+                return Some((frame, end - elapsed));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a> {
+    pub duration_ms: u32,
+    source: &'a str,
+    /// 0-based line after the `frame` header.
+    first: usize,
+}
+
+impl<'a> Frame<'a> {
+    pub fn rows(&self) -> impl Iterator<Item = Row<'a>> {
+        lines_from(self.source, self.first)
+            .take_while(|line| matches!(line, Line::Row(_) | Line::Blank))
+            .filter_map(|line| match line {
+                Line::Row(row) => Some(row),
+                _ => None,
+            })
+    }
+}
+
+/// Plays the idle animation and whatever the events pick.
+pub struct Animator<'a> {
+    library: Library<'a>,
+    base: (Animation<'a>, u64),
+    overlay: Option<(Animation<'a>, u64)>,
+}
+
+impl<'a> Animator<'a> {
+    pub fn new(library: Library<'a>, now: u64) -> Self {
+        let idle = library
+            .animation(IDLE)
+            .expect("Library::parse checks for the idle animation");
+        Self {
+            library,
+            base: (idle, now),
+            overlay: None,
+        }
+    }
+
+    /// Play the animation the asset has for `event`, if any.
+    pub fn handle(&mut self, event: Event, now: u64) {
+        if let Some(animation) = self.library.on(event) {
+            self.play(animation, now);
+        }
+    }
+
+    /// Looping animations replace the running one, unless it is already playing; `once`
+    /// animations restart on top of it.
+    pub fn play(&mut self, animation: Animation<'a>, now: u64) {
+        if !animation.looping {
+            self.overlay = Some((animation, now));
+        } else if animation != self.base.0 {
+            self.base = (animation, now);
+        }
+    }
+
+    /// Name of the animation on screen.
+    pub fn playing(&mut self, now: u64) -> &'a str {
+        self.current(now).0.name
+    }
+
+    /// Frame to show now and milliseconds until it changes.
+    pub fn frame(&mut self, now: u64) -> (Frame<'a>, u64) {
+        let (animation, start) = self.current(now);
+        animation
+            .frame_at(now.saturating_sub(start))
+            .expect("looping animations always have a frame")
+    }
+
+    fn current(&mut self, now: u64) -> (Animation<'a>, u64) {
+        if let Some((overlay, start)) = self.overlay {
+            if overlay.frame_at(now.saturating_sub(start)).is_some() {
+                return (overlay, start);
+            }
+            self.overlay = None;
+        }
+        self.base
+    }
+}
@@ -0,0 +1,179 @@
+//! Battery monitor.
+//!
+//! The battery voltage is read through a resistor divider on an ADC pin, averaged over the
+//! last [`AVERAGE_LEN`] samples so radio bursts do not make the level jump, and mapped to a
+//! charge level with the discharge curve of the cell chemistry. Curves are resting voltages
+//! at light load, interpolated linearly between the points.
+
+use heapless::HistoryBuffer;
+
+/// Samples averaged into one reading.
+pub const AVERAGE_LEN: usize = 8;
+
+/// Level at and below which the battery counts as low.
+pub const LOW_PERCENT: u8 = 15;
+
+/// Below this the divider is taken to be unconnected, e.g. a board powered over USB.
+pub const PRESENT_MIN_MV: u16 = 500;
+
+/// Millivolts and percent, highest voltage first.
+type Curve = &'static [(u16, u8)];
+
+/// One Li-ion or LiPo cell, 4.2 V full.
+const LI_ION: Curve = &[
+    (4200, 100),
+    (4100, 90),
+    (4000, 79),
+    (3900, 65),
+    (3800, 41),
+    (3750, 25),
+    (3700, 13),
+    (3600, 5),
+    (3300, 0),
+];
+
+/// One LiFePO4 cell, flat between 3.25 and 3.3 V for most of the charge.
+const LI_FE_PO4: Curve = &[
+    (3400, 100),
+    (3350, 95),
+    (3320, 90),
+    (3300, 70),
+    (3270, 40),
+    (3250, 20),
+    (3200, 10),
+    (3000, 5),
+    (2500, 0),
+];
+
+/// Two alkaline AA cells in series.
+const ALKALINE_2XAA: Curve = &[
+    (3200, 100),
+    (2900, 80),
+    (2700, 60),
+    (2500, 40),
+    (2400, 25),
+    (2300, 15),
+    (2200, 8),
+    (2000, 0),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Chemistry {
+    LiIon,
+    LiFePo4,
+    Alkaline2xAa,
+}
+
+impl Chemistry {
+    /// `li-ion`, `lifepo4` or `2xaa`, as in the `BATTERY_CHEMISTRY` build variable.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "li-ion" => Some(Chemistry::LiIon),
+            "lifepo4" => Some(Chemistry::LiFePo4),
+            "2xaa" => Some(Chemistry::Alkaline2xAa),
+            _ => None,
+        }
+    }
+
+    fn curve(self) -> Curve {
+        match self {
+            Chemistry::LiIon => LI_ION,
+            Chemistry::LiFePo4 => LI_FE_PO4,
+            Chemistry::Alkaline2xAa => ALKALINE_2XAA,
+        }
+    }
+
+    /// Charge level at `mv`, 100 above the curve and 0 below it.
+    pub fn percent(self, mv: u16) -> u8 {
+        let curve = self.curve();
+        let (full_mv, _) = curve[0];
+        if mv >= full_mv {
+            return 100;
+        }
+        curve
+            .windows(2)
+            .find(|points| mv >= points[1].0)
+            .map(|points| {
+                let ((high_mv, high), (low_mv, low)) = (points[0], points[1]);
+                let span = (high - low) as u32 * (mv - low_mv) as u32;
+                low + (span / (high_mv - low_mv) as u32) as u8
+            })
+            .unwrap_or(0)
+    }
+}
+
+/// Divider ratio from the `BATTERY_DIVIDER` build variable in thousandths, `2` or `1.5`
+/// style with up to three decimals. `None` below 1.
+pub fn parse_ratio(s: &str) -> Option<u32> {
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut ratio = whole.parse::<u32>().ok()?.checked_mul(1000)?;
+    for (digit, scale) in fraction.bytes().zip([100, 10, 1]) {
+        ratio += (digit - b'0') as u32 * scale;
+    }
+    (ratio >= 1000).then_some(ratio)
+}
+
+/// Battery voltage for `pin_mv` measured behind a divider of `ratio` thousandths.
+pub fn battery_mv(pin_mv: u16, ratio: u32) -> u16 {
+    (pin_mv as u32 * ratio / 1000).min(u16::MAX as u32) as u16
+}
+
+/// Mean of the last [`AVERAGE_LEN`] samples.
+#[derive(Default)]
+pub struct Average {
+    samples: HistoryBuffer<u16, AVERAGE_LEN>,
+}
+
+impl Average {
+    pub const fn new() -> Self {
+        Self {
+            samples: HistoryBuffer::new(),
+        }
+    }
+
+    /// Add a sample and return the mean.
+    pub fn add(&mut self, mv: u16) -> u16 {
+        self.samples.write(mv);
+        let sum: u32 = self.samples.as_slice().iter().map(|&mv| mv as u32).sum();
+        (sum / self.samples.len() as u32) as u16
+    }
+}
+
+/// A battery reading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Battery {
+    pub mv: u16,
+    pub percent: u8,
+}
+
+impl Battery {
+    pub fn new(mv: u16, chemistry: Chemistry) -> Self {
+        Self {
+            mv,
+            percent: chemistry.percent(mv),
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.mv >= PRESENT_MIN_MV
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.is_present() && self.percent <= LOW_PERCENT
+    }
+
+    /// Battery Power State: present, discharging unknown, charging not supported (the
+    /// charger is not wired to a pin) and good or critically low level.
+    pub fn power_state(&self) -> u8 {
+        const CHARGING_NOT_SUPPORTED: u8 = 0b01 << 4;
+        if !self.is_present() {
+            // not present, level unknown
+            return 0b10 | CHARGING_NOT_SUPPORTED;
+        }
+        let level = if self.is_low() { 0b11 } else { 0b10 };
+        0b11 | CHARGING_NOT_SUPPORTED | level << 6
+    }
+}
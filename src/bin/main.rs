@@ -8,34 +8,60 @@
 
 use defmt::{info, warn};
 use embassy_executor::Spawner;
+use embassy_net::StackResources;
+use embassy_sync::mutex::Mutex;
 use esp_hal::clock::CpuClock;
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
 use esp_hal::i2c::master::{Config as I2cConfig, I2c};
+use esp_hal::rmt::{Rmt, TxChannelConfig, TxChannelCreator};
+use esp_hal::rtc_cntl::Rtc;
 use esp_hal::time::Rate;
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::timer::timg::TimerGroup;
 use esp_wifi::ble::controller::BleConnector;
 use panic_rtt_target as _;
 use ssd1306::I2CDisplayInterface;
+use static_cell::StaticCell;
 
-use ssd1306::prelude::*;
+use ssd1306::command::AddrMode;
 use ssd1306::rotation::DisplayRotation;
 use ssd1306::size::DisplaySize128x64;
-use ssd1306::Ssd1306;
+use ssd1306::Ssd1306Async;
 
 use trouble_host::prelude::ExternalController;
 
 use esp_hal::tsens::{Config as TsensConfig, TemperatureSensor};
+use esp_storage::FlashStorage;
 
 extern crate alloc;
 
+use alloc::boxed::Box;
+
+use coa_gatt::i2c_bus::{self, I2cBus, I2cDevice};
 use coa_gatt::mock::create_mock_display;
-use coa_gatt::task::{ble, display_task, DisplayWrapper};
+use coa_gatt::qr;
+use coa_gatt::settings;
+use coa_gatt::state::{FAULT, I2C_DEVICES, SETTINGS, SHOW_PAIRING};
+use coa_gatt::task::{battery_adc, battery_task, ble, button_task, display_task, power_task};
+use coa_gatt::task::{settings_task, DisplayWrapper};
+use coa_gatt::task::WakePin;
+use coa_gatt::task::{status_led_task, GpioLed, RgbLed, StatusLed};
 use coa_gatt::task::temp_task;
+use coa_gatt::task::net;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// What is on GPIO8: `rgb`, `gpio`, `gpio-low` or `none`, see `.env.example`.
+const STATUS_LED: &str = env!("STATUS_LED");
+
+/// ADC pin of the battery divider: `0` to `4` for GPIO0-4, or `none`.
+const BATTERY_PIN: &str = env!("BATTERY_PIN");
+
+/// Pin of a button that wakes the board from deep sleep: `0` to `4` for GPIO0-4, or `none`.
+const WAKE_PIN: &str = env!("WAKE_PIN");
+
 #[esp_hal_embassy::main]
 async fn main(spawner: Spawner) {
     // generator version: 0.5.0
@@ -55,13 +81,125 @@ async fn main(spawner: Spawner) {
 
     info!("Embassy initialized!");
 
-    let rng = esp_hal::rng::Rng::new(peripherals.RNG);
+    // Settings go first, the display and BLE tasks pick them up when they start
+    let mut flash = FlashStorage::new();
+    let settings = settings::load(&mut flash).unwrap_or_else(|| {
+        // settings_task saves the defaults, so this only happens once
+        info!("No saved settings, first boot: using defaults and showing the pairing QR code");
+        SHOW_PAIRING.signal(());
+        Default::default()
+    });
+    info!("Settings: {:?}", settings);
+    SETTINGS.sender().send(settings);
+    spawner.must_spawn(settings_task(flash));
+
+    // BOOT button, only a strapping pin during reset and free to use afterwards
+    let button = Input::new(
+        peripherals.GPIO9,
+        InputConfig::default().with_pull(Pull::Up),
+    );
+    spawner.must_spawn(button_task(button));
+
+    // ADC1 channels and RTC pins that are on both the C3 and the C6 and not taken by I2C,
+    // for the battery divider and the wake button
+    let mut gpio = (
+        Some(peripherals.GPIO0),
+        Some(peripherals.GPIO1),
+        Some(peripherals.GPIO2),
+        Some(peripherals.GPIO3),
+        Some(peripherals.GPIO4),
+    );
+    let battery = match BATTERY_PIN {
+        "0" => gpio.0.take().map(|pin| battery_adc(peripherals.ADC1, pin)),
+        "1" => gpio.1.take().map(|pin| battery_adc(peripherals.ADC1, pin)),
+        "2" => gpio.2.take().map(|pin| battery_adc(peripherals.ADC1, pin)),
+        "3" => gpio.3.take().map(|pin| battery_adc(peripherals.ADC1, pin)),
+        "4" => gpio.4.take().map(|pin| battery_adc(peripherals.ADC1, pin)),
+        "none" => None,
+        _ => {
+            warn!("Invalid BATTERY_PIN {}, no battery monitor", BATTERY_PIN);
+            None
+        }
+    };
+    match battery {
+        Some(read_mv) => spawner.must_spawn(battery_task(read_mv)),
+        None => info!("No battery monitor configured"),
+    }
+
+    // Idle and deep sleep, see src/power.rs
+    let wake: Option<WakePin> = match WAKE_PIN {
+        "0" => gpio.0.take().map(|pin| Box::new(pin) as WakePin),
+        "1" => gpio.1.take().map(|pin| Box::new(pin) as WakePin),
+        "2" => gpio.2.take().map(|pin| Box::new(pin) as WakePin),
+        "3" => gpio.3.take().map(|pin| Box::new(pin) as WakePin),
+        "4" => gpio.4.take().map(|pin| Box::new(pin) as WakePin),
+        _ => None,
+    };
+    if wake.is_none() && WAKE_PIN != "none" {
+        warn!(
+            "Invalid or taken WAKE_PIN {}, waking on the timer only",
+            WAKE_PIN
+        );
+    }
+    spawner.must_spawn(power_task(Rtc::new(peripherals.LPWR), wake));
+
+    let status_led = match STATUS_LED {
+        "rgb" => {
+            let rmt = Rmt::new(peripherals.RMT, Rate::from_mhz(80))
+                .expect("Failed to initialize RMT")
+                .into_async();
+            let config = TxChannelConfig::default()
+                .with_clk_divider(1)
+                .with_idle_output_level(Level::Low)
+                .with_idle_output(true);
+            let channel = rmt
+                .channel0
+                .configure_tx(peripherals.GPIO8, config)
+                .expect("Failed to configure RMT channel");
+            Some(StatusLed::Rgb(RgbLed::new(channel)))
+        }
+        "gpio" | "gpio-low" => {
+            let active_low = STATUS_LED == "gpio-low";
+            let pin = Output::new(
+                peripherals.GPIO8,
+                Level::from(active_low),
+                OutputConfig::default(),
+            );
+            Some(StatusLed::Gpio(GpioLed::new(pin, active_low)))
+        }
+        _ => None,
+    };
+    match status_led {
+        Some(led) => spawner.must_spawn(status_led_task(led)),
+        None => info!("No status LED configured"),
+    }
+
+    let mut rng = esp_hal::rng::Rng::new(peripherals.RNG);
     let timer1 = TimerGroup::new(peripherals.TIMG0);
-    let wifi_init =
-        esp_wifi::init(timer1.timer0, rng).expect("Failed to initialize WIFI/BLE controller");
-    let (mut _wifi_controller, _interfaces) = esp_wifi::wifi::new(&wifi_init, peripherals.WIFI)
+    static WIFI_INIT: StaticCell<esp_wifi::EspWifiController<'static>> = StaticCell::new();
+    let wifi_init = WIFI_INIT
+        .init(esp_wifi::init(timer1.timer0, rng).expect("Failed to initialize WIFI/BLE controller"));
+    let (wifi_controller, interfaces) = esp_wifi::wifi::new(wifi_init, peripherals.WIFI)
         .expect("Failed to initialize WIFI controller");
-    let connector = BleConnector::new(&wifi_init, peripherals.BT);
+    let connector = BleConnector::new(wifi_init, peripherals.BT);
+
+    if net::WIFI_SSID.is_empty() {
+        info!("No WIFI_SSID configured, skipping Wi-Fi and SNTP");
+    } else {
+        static RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
+        let seed = ((rng.random() as u64) << 32) | rng.random() as u64;
+        let (stack, runner) = embassy_net::new(
+            interfaces.sta,
+            embassy_net::Config::dhcpv4(Default::default()),
+            RESOURCES.init(StackResources::new()),
+            seed,
+        );
+        spawner.must_spawn(net::connection_task(wifi_controller));
+        spawner.must_spawn(net::net_task(runner));
+        spawner.must_spawn(net::sntp_task(stack));
+        spawner.must_spawn(net::mdns_task(stack));
+        spawner.must_spawn(net::status_task(stack));
+    }
 
     let i2c = I2c::new(
         peripherals.I2C0,
@@ -69,15 +207,33 @@ async fn main(spawner: Spawner) {
     )
     .expect("Failed to create I2C instance")
     .with_sda(peripherals.GPIO5)
-    .with_scl(peripherals.GPIO6);
+    .with_scl(peripherals.GPIO6)
+    .into_async();
+
+    static I2C_BUS: StaticCell<I2cBus> = StaticCell::new();
+    let i2c_bus: &'static I2cBus = I2C_BUS.init(Mutex::new(i2c));
+
+    // Scan before anything else uses the bus, so wiring problems show up in the log
+    let devices = i2c_bus::scan(&mut I2cDevice::new(i2c_bus)).await;
+    if devices.is_empty() {
+        warn!("I2C scan: no devices found, check SDA (GPIO5) / SCL (GPIO6)");
+    }
+    for address in devices.iter() {
+        info!("I2C scan: device at {=u8:#04x}", address);
+    }
+    I2C_DEVICES.sender().send(devices);
 
-    let interface = I2CDisplayInterface::new(i2c);
-    let mut real_disp = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
-        .into_buffered_graphics_mode();
+    let interface = I2CDisplayInterface::new(I2cDevice::new(i2c_bus));
+    let mut real_disp = Ssd1306Async::new(interface, DisplaySize128x64, DisplayRotation::Rotate0);
 
     // Try to initialize the real display, use mock display if it fails
-    let display_wrapper = if real_disp.init().is_err() {
+    let display_wrapper = if real_disp
+        .init_with_addr_mode(AddrMode::Horizontal)
+        .await
+        .is_err()
+    {
         warn!("Failed to initialize display, using mock display instead");
+        FAULT.sender().send(true);
         DisplayWrapper::Mock(create_mock_display())
     } else {
         DisplayWrapper::Real(real_disp)
@@ -86,9 +242,10 @@ async fn main(spawner: Spawner) {
     let tsens = TemperatureSensor::new(peripherals.TSENS, TsensConfig::default())
         .expect("TSENS init failed");
 
-    spawner.must_spawn(display_task(display_wrapper));
-    spawner
-        .must_spawn(temp_task(tsens));
+    let pairing = qr::pairing_payload(ble::DEVICE_NAME, &ble::mac_address(), rng.random())
+        .expect("Pairing payload too long");
+    spawner.must_spawn(display_task(display_wrapper, pairing));
+    spawner.must_spawn(temp_task(tsens, i2c_bus));
 
     info!("Running BLE...");
     // TODO as task
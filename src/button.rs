@@ -0,0 +1,159 @@
+//! Button gesture recognition.
+//!
+//! A pure state machine fed with the raw button level and the time in milliseconds. The
+//! caller calls [`Recognizer::update`] on every edge and again at [`Recognizer::deadline`],
+//! so gestures that are recognised by something *not* happening (the second press of a
+//! double press, releasing before the long press time) are reported without an edge.
+
+/// Press or release needs to be stable this long to count.
+pub const DEBOUNCE_MS: u64 = 20;
+/// A second press starting less than this after the release makes a double press.
+pub const DOUBLE_GAP_MS: u64 = 300;
+/// Holding at least this long is a long press, reported while still held.
+pub const LONG_MS: u64 = 5000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Gesture {
+    Short,
+    Double,
+    Long,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Timings {
+    pub debounce_ms: u64,
+    pub double_gap_ms: u64,
+    pub long_ms: u64,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            debounce_ms: DEBOUNCE_MS,
+            double_gap_ms: DOUBLE_GAP_MS,
+            long_ms: LONG_MS,
+        }
+    }
+}
+
+/// What the debounced button is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    /// Held since, and whether it is the second press of a double press.
+    Pressed {
+        since: u64,
+        second: bool,
+    },
+    /// The long press was reported, nothing more until released.
+    LongHeld,
+    /// Released after a short press, a second press would make it a double press.
+    Released {
+        at: u64,
+    },
+}
+
+pub struct Recognizer {
+    timings: Timings,
+    /// Last raw level and when it last changed.
+    raw: bool,
+    raw_since: u64,
+    /// Debounced level.
+    pressed: bool,
+    phase: Phase,
+}
+
+impl Recognizer {
+    pub const fn new(timings: Timings) -> Self {
+        Self {
+            timings,
+            raw: false,
+            raw_since: 0,
+            pressed: false,
+            phase: Phase::Idle,
+        }
+    }
+
+    /// Feed the current raw level, `true` while pressed. Returns the gesture completed
+    /// by now, if any.
+    pub fn update(&mut self, pressed: bool, now: u64) -> Option<Gesture> {
+        if pressed != self.raw {
+            self.raw = pressed;
+            self.raw_since = now;
+        }
+        let mut gesture = None;
+        if self.raw != self.pressed
+            && now.saturating_sub(self.raw_since) >= self.timings.debounce_ms
+        {
+            self.pressed = self.raw;
+            // the edge happened when the level started to be stable
+            gesture = self.edge(self.raw_since);
+        }
+        gesture.or_else(|| self.timeout(now))
+    }
+
+    /// When to call [`update`](Self::update) next even if the level does not change.
+    pub fn deadline(&self) -> Option<u64> {
+        let debounce =
+            (self.raw != self.pressed).then_some(self.raw_since + self.timings.debounce_ms);
+        let phase = match self.phase {
+            Phase::Pressed { since, .. } => Some(since + self.timings.long_ms),
+            Phase::Released { at } => Some(at + self.timings.double_gap_ms),
+            Phase::Idle | Phase::LongHeld => None,
+        };
+        match (debounce, phase) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn edge(&mut self, at: u64) -> Option<Gesture> {
+        match (self.pressed, self.phase) {
+            (true, phase) => {
+                let gap = match phase {
+                    Phase::Released { at: released } => Some(at.saturating_sub(released)),
+                    _ => None,
+                };
+                let second = gap.is_some_and(|gap| gap < self.timings.double_gap_ms);
+                self.phase = Phase::Pressed { since: at, second };
+                // the gap ran out in the same update that debounced this press
+                (gap.is_some() && !second).then_some(Gesture::Short)
+            }
+            (false, Phase::Pressed { since, second }) => {
+                self.phase = Phase::Idle;
+                if at.saturating_sub(since) >= self.timings.long_ms {
+                    Some(Gesture::Long)
+                } else if second {
+                    Some(Gesture::Double)
+                } else {
+                    self.phase = Phase::Released { at };
+                    None
+                }
+            }
+            (false, _) => {
+                self.phase = Phase::Idle;
+                None
+            }
+        }
+    }
+
+    fn timeout(&mut self, now: u64) -> Option<Gesture> {
+        match self.phase {
+            Phase::Pressed { since, .. } if now.saturating_sub(since) >= self.timings.long_ms => {
+                self.phase = Phase::LongHeld;
+                Some(Gesture::Long)
+            }
+            Phase::Released { at } if now.saturating_sub(at) >= self.timings.double_gap_ms => {
+                self.phase = Phase::Idle;
+                Some(Gesture::Short)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for Recognizer {
+    fn default() -> Self {
+        Self::new(Timings::default())
+    }
+}
@@ -0,0 +1,194 @@
+//! Wall clock on top of the monotonic `embassy_time` boot clock.
+//!
+//! Nothing on the chip keeps real time across resets, so the clock starts unsynced and is
+//! set either by SNTP (see [`crate::sntp`]) or by a central writing the GATT Current Time
+//! characteristic. Internally we only store the offset between boot time and the Unix epoch.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+
+/// Unix time in milliseconds at the moment `Instant` was zero, `None` until synced.
+static EPOCH_OFFSET_MS: Mutex<CriticalSectionRawMutex, Cell<Option<u64>>> =
+    Mutex::new(Cell::new(None));
+
+/// Signalled when SNTP set the clock, the Current Time characteristic notifies it.
+pub static TIME_SYNCED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+// Log lines carry UTC once synced; before that the same field shows time since boot
+// (rendered as 1970-01-01, which is easy to spot).
+defmt::timestamp!(
+    "{=u64:iso8601ms}",
+    now_unix_ms().unwrap_or_else(|| Instant::now().as_millis())
+);
+
+/// Where the current time came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum TimeSource {
+    Sntp,
+    Gatt,
+}
+
+/// Set the wall clock to the given Unix time in milliseconds.
+pub fn set_unix_ms(unix_ms: u64, source: TimeSource) {
+    let boot_ms = Instant::now().as_millis();
+    EPOCH_OFFSET_MS.lock(|offset| offset.set(Some(unix_ms.saturating_sub(boot_ms))));
+    defmt::info!("[clock] set from {:?} to {=u64} ms", source, unix_ms);
+}
+
+/// Current Unix time in milliseconds, `None` if the clock was never set.
+pub fn now_unix_ms() -> Option<u64> {
+    EPOCH_OFFSET_MS
+        .lock(|offset| offset.get())
+        .map(|offset| offset + Instant::now().as_millis())
+}
+
+/// Current Unix time in seconds, `None` if the clock was never set.
+pub fn now_unix_secs() -> Option<u64> {
+    now_unix_ms().map(|ms| ms / 1000)
+}
+
+/// Unix time in seconds of a moment given as seconds since boot, `None` if never set.
+pub fn unix_secs_at_uptime(uptime_secs: u32) -> Option<u64> {
+    EPOCH_OFFSET_MS
+        .lock(|offset| offset.get())
+        .map(|offset| offset / 1000 + uptime_secs as u64)
+}
+
+/// Inverse of [`unix_secs_at_uptime`], saturating at boot.
+pub fn uptime_secs_at_unix(unix_secs: u64) -> Option<u32> {
+    EPOCH_OFFSET_MS
+        .lock(|offset| offset.get())
+        .map(|offset| unix_secs.saturating_sub(offset / 1000).min(u32::MAX as u64) as u32)
+}
+
+pub fn is_synced() -> bool {
+    EPOCH_OFFSET_MS.lock(|offset| offset.get().is_some())
+}
+
+/// Broken-down UTC time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// 1 = Monday ... 7 = Sunday, as used by the Current Time Service.
+    pub weekday: u8,
+}
+
+impl DateTime {
+    pub fn from_unix_secs(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let rem = secs % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday
+        let weekday = ((days + 3).rem_euclid(7) + 1) as u8;
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+            weekday,
+        }
+    }
+
+    pub fn to_unix_secs(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        days as u64 * 86_400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+/// Adjust Reason flag of a clock set from an external reference, i.e. SNTP.
+pub const ADJUST_EXTERNAL_REFERENCE: u8 = 0b10;
+
+/// Encode the Current Time characteristic (0x2A2B): Exact Time 256 followed by Adjust Reason.
+pub fn encode_current_time(unix_ms: u64, adjust_reason: u8) -> [u8; 10] {
+    let dt = DateTime::from_unix_secs(unix_ms / 1000);
+    let year = dt.year.to_le_bytes();
+    [
+        year[0],
+        year[1],
+        dt.month,
+        dt.day,
+        dt.hour,
+        dt.minute,
+        dt.second,
+        dt.weekday,
+        // Fractions256
+        ((unix_ms % 1000) * 256 / 1000) as u8,
+        adjust_reason,
+    ]
+}
+
+/// Decode a Current Time characteristic value written by a central into Unix milliseconds.
+pub fn decode_current_time(data: &[u8]) -> Option<u64> {
+    if data.len() < 7 {
+        return None;
+    }
+    let dt = DateTime {
+        year: u16::from_le_bytes([data[0], data[1]]),
+        month: data[2],
+        day: data[3],
+        hour: data[4],
+        minute: data[5],
+        second: data[6],
+        weekday: data.get(7).copied().unwrap_or(0),
+    };
+    if dt.year < 1970
+        || !(1..=12).contains(&dt.month)
+        || !(1..=days_in_month(dt.year, dt.month)).contains(&dt.day)
+        || dt.hour > 23
+        || dt.minute > 59
+        || dt.second > 59
+    {
+        return None;
+    }
+    let fractions = data.get(8).copied().unwrap_or(0) as u64;
+    Some(dt.to_unix_secs() * 1000 + fractions * 1000 / 256)
+}
+
+/// Number of days in `month` (1 to 12) of `year`.
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Howard Hinnant's days <-> civil date algorithms, see
+// <https://howardhinnant.github.io/date_algorithms.html>
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,101 @@
+//! OLED power policy.
+//!
+//! The display is on after activity, dims and then switches off when nothing happens for
+//! the configured times, and can be kept on while a central is connected. All times are
+//! seconds since boot.
+
+use crate::settings::DisplaySettings;
+
+/// How long the cow stays at one position when pixel shifting.
+pub const SHIFT_PERIOD_SECS: u64 = 60;
+
+/// Offsets visited in turn, at most 2 px from the home position so the art stays legible.
+const SHIFT_PATTERN: [(i32, i32); 8] = [
+    (0, 0),
+    (2, 0),
+    (2, 2),
+    (0, 2),
+    (-2, 2),
+    (-2, 0),
+    (-2, -2),
+    (0, -2),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum PowerState {
+    On,
+    Dimmed,
+    Off,
+}
+
+pub struct DisplayPower {
+    settings: DisplaySettings,
+    last_activity: u64,
+    connected: bool,
+}
+
+impl DisplayPower {
+    pub const fn new(settings: DisplaySettings, now: u64) -> Self {
+        Self {
+            settings,
+            last_activity: now,
+            connected: false,
+        }
+    }
+
+    pub fn settings(&self) -> &DisplaySettings {
+        &self.settings
+    }
+
+    /// Apply new settings. Counts as activity so the result is visible right away.
+    pub fn set_settings(&mut self, settings: DisplaySettings, now: u64) {
+        self.settings = settings;
+        self.activity(now);
+    }
+
+    /// Restart the inactivity timeouts.
+    pub fn activity(&mut self, now: u64) {
+        self.last_activity = now;
+    }
+
+    /// Connecting and disconnecting both count as activity.
+    pub fn set_connected(&mut self, connected: bool, now: u64) {
+        if connected != self.connected {
+            self.connected = connected;
+            self.activity(now);
+        }
+    }
+
+    pub fn state(&self, now: u64) -> PowerState {
+        if self.connected && self.settings.on_while_connected {
+            return PowerState::On;
+        }
+        let idle = now.saturating_sub(self.last_activity);
+        let elapsed = |timeout: u16| timeout != 0 && idle >= timeout as u64;
+        if elapsed(self.settings.off_after_secs) {
+            PowerState::Off
+        } else if elapsed(self.settings.dim_after_secs) {
+            PowerState::Dimmed
+        } else {
+            PowerState::On
+        }
+    }
+
+    /// Contrast for the current state, `None` while off.
+    pub fn contrast(&self, now: u64) -> Option<u8> {
+        match self.state(now) {
+            PowerState::On => Some(self.settings.contrast),
+            PowerState::Dimmed => Some(self.settings.dim_contrast),
+            PowerState::Off => None,
+        }
+    }
+
+    /// Offset to draw the cow at, moving every [`SHIFT_PERIOD_SECS`] if pixel shifting is on.
+    pub fn shift(&self, now: u64) -> (i32, i32) {
+        if !self.settings.pixel_shift {
+            return (0, 0);
+        }
+        let step = (now / SHIFT_PERIOD_SECS) as usize % SHIFT_PATTERN.len();
+        SHIFT_PATTERN[step]
+    }
+}
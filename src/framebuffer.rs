@@ -0,0 +1,185 @@
+//! Off-screen SSD1306 framebuffer that only sends what changed.
+//!
+//! Frames are drawn in full into a [`Framebuffer`], which also keeps a copy of what the
+//! panel last received. [`Framebuffer::flush`] compares the two page by page and writes
+//! only the column runs that differ, so redrawing an unchanged screen costs no I2C
+//! traffic at all and the ticking counter costs a few dozen bytes instead of 1 KiB.
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+/// Each page is a row of bytes covering 8 pixel lines, LSB on top.
+pub const PAGES: usize = HEIGHT / 8;
+pub const BUFFER_LEN: usize = WIDTH * PAGES;
+
+/// Runs of unchanged columns shorter than this are resent rather than splitting the write,
+/// since addressing a new run takes two 3 byte commands.
+pub const MERGE_GAP: usize = 6;
+
+/// Where a flush goes, normally the SSD1306 in horizontal addressing mode.
+#[allow(
+    async_fn_in_trait,
+    reason = "only used with concrete types on a single-threaded executor"
+)]
+pub trait PageWriter {
+    type Error;
+
+    /// Write `data` into `page`, starting at `column`.
+    async fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Columns `start..end` of one page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Span {
+    pub page: u8,
+    pub start: u8,
+    pub end: u8,
+}
+
+impl Span {
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn range(&self) -> core::ops::Range<usize> {
+        let base = self.page as usize * WIDTH;
+        base + self.start as usize..base + self.end as usize
+    }
+}
+
+pub struct Framebuffer {
+    pixels: [u8; BUFFER_LEN],
+    flushed: [u8; BUFFER_LEN],
+    /// False while the panel content is unknown, e.g. right after init.
+    in_sync: bool,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    pub const fn new() -> Self {
+        Self {
+            pixels: [0; BUFFER_LEN],
+            flushed: [0; BUFFER_LEN],
+            in_sync: false,
+        }
+    }
+
+    /// Resend the whole frame on the next flush.
+    pub fn invalidate(&mut self) {
+        self.in_sync = false;
+    }
+
+    pub fn pixels(&self) -> &[u8; BUFFER_LEN] {
+        &self.pixels
+    }
+
+    /// The writes the next flush would make.
+    pub fn spans(&self) -> impl Iterator<Item = Span> + '_ {
+        let mut position = 0;
+        core::iter::from_fn(move || {
+            let span = self.next_span(position)?;
+            position = span.range().end;
+            Some(span)
+        })
+    }
+
+    /// Send the changed spans to `writer` and return the number of data bytes written.
+    ///
+    /// On error the spans not yet written stay dirty and go out with the next flush.
+    pub async fn flush<W: PageWriter>(&mut self, writer: &mut W) -> Result<usize, W::Error> {
+        let mut position = 0;
+        let mut sent = 0;
+        while let Some(span) = self.next_span(position) {
+            let range = span.range();
+            writer
+                .write_page(span.page, span.start, &self.pixels[range.clone()])
+                .await?;
+            self.flushed[range.clone()].copy_from_slice(&self.pixels[range.clone()]);
+            sent += span.len();
+            position = range.end;
+        }
+        self.in_sync = true;
+        Ok(sent)
+    }
+
+    fn is_dirty(&self, index: usize) -> bool {
+        !self.in_sync || self.pixels[index] != self.flushed[index]
+    }
+
+    /// First span at or after buffer index `position`.
+    fn next_span(&self, position: usize) -> Option<Span> {
+        let start = (position..BUFFER_LEN).find(|&i| self.is_dirty(i))?;
+        let page = start / WIDTH;
+        let page_end = (page + 1) * WIDTH;
+
+        let end = if self.in_sync {
+            let mut end = start + 1;
+            let mut index = end;
+            while index < page_end && index - end < MERGE_GAP {
+                if self.is_dirty(index) {
+                    end = index + 1;
+                }
+                index += 1;
+            }
+            end
+        } else {
+            page_end
+        };
+
+        let base = page * WIDTH;
+        Some(Span {
+            page: page as u8,
+            start: (start - base) as u8,
+            end: (end - base) as u8,
+        })
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                continue;
+            };
+            if x >= WIDTH || y >= HEIGHT {
+                continue;
+            }
+            let index = (y / 8) * WIDTH + x;
+            let bit = 1 << (y % 8);
+            if color.is_on() {
+                self.pixels[index] |= bit;
+            } else {
+                self.pixels[index] &= !bit;
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(if color.is_on() { 0xFF } else { 0x00 });
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+//! On-device temperature history.
+//!
+//! Samples are folded into per-interval min/avg/max summaries which are kept in a fixed-size
+//! ring buffer, so the oldest interval is dropped once the buffer is full. Temperatures are
+//! stored in hundredths of a degree Celsius, the same unit as the ESS Temperature
+//! characteristic.
+
+use heapless::HistoryBuffer;
+
+/// Length of one summary interval.
+pub const INTERVAL_SECS: u32 = 60;
+/// Number of intervals kept, 12 hours at the default interval.
+pub const CAPACITY: usize = 720;
+
+/// Size of an encoded [`Summary`] notification.
+pub const RECORD_LEN: usize = 12;
+
+/// Convert degrees Celsius to the 0.01 °C fixed-point representation, saturating.
+pub fn centi_celsius(celsius: f32) -> i16 {
+    let scaled = celsius * 100.0;
+    if scaled >= i16::MAX as f32 {
+        i16::MAX
+    } else if scaled <= i16::MIN as f32 {
+        i16::MIN
+    } else {
+        scaled as i16
+    }
+}
+
+/// Statistics for one interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Summary {
+    /// Start of the interval, seconds since boot.
+    pub start: u32,
+    pub min: i16,
+    pub avg: i16,
+    pub max: i16,
+    pub count: u16,
+}
+
+impl Summary {
+    /// Wire format: start (u32), min, avg, max (i16), sample count (u16), all little-endian.
+    /// `timestamp` replaces `start` so callers can send wall-clock time.
+    pub fn encode(&self, timestamp: u32) -> [u8; RECORD_LEN] {
+        let mut out = [0u8; RECORD_LEN];
+        out[0..4].copy_from_slice(&timestamp.to_le_bytes());
+        out[4..6].copy_from_slice(&self.min.to_le_bytes());
+        out[6..8].copy_from_slice(&self.avg.to_le_bytes());
+        out[8..10].copy_from_slice(&self.max.to_le_bytes());
+        out[10..12].copy_from_slice(&self.count.to_le_bytes());
+        out
+    }
+}
+
+/// Folds samples of the current interval.
+#[derive(Default)]
+struct Accumulator {
+    start: u32,
+    sum: i32,
+    min: i16,
+    max: i16,
+    count: u16,
+}
+
+impl Accumulator {
+    fn add(&mut self, value: i16) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.sum += value as i32;
+        self.count += 1;
+    }
+
+    fn summary(&self) -> Option<Summary> {
+        (self.count > 0).then(|| Summary {
+            start: self.start,
+            min: self.min,
+            avg: (self.sum / self.count as i32) as i16,
+            max: self.max,
+            count: self.count,
+        })
+    }
+}
+
+pub struct History<const N: usize> {
+    intervals: HistoryBuffer<Summary, N>,
+    current: Accumulator,
+    interval_secs: u32,
+}
+
+impl<const N: usize> History<N> {
+    pub const fn new(interval_secs: u32) -> Self {
+        Self {
+            intervals: HistoryBuffer::new(),
+            current: Accumulator {
+                start: 0,
+                sum: 0,
+                min: 0,
+                max: 0,
+                count: 0,
+            },
+            interval_secs,
+        }
+    }
+
+    /// Record a sample taken `now` seconds after boot. Closes the current interval once
+    /// `now` has moved past its end.
+    pub fn record(&mut self, now: u32, value: i16) {
+        let start = now - now % self.interval_secs;
+        if start != self.current.start {
+            if let Some(summary) = self.current.summary() {
+                self.intervals.write(summary);
+            }
+            self.current = Accumulator {
+                start,
+                ..Default::default()
+            };
+        }
+        self.current.add(value);
+    }
+
+    /// Number of completed intervals.
+    pub fn len(&self) -> usize {
+        self.intervals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.len() == 0
+    }
+
+    /// Completed intervals starting within `from..=to`, oldest first.
+    pub fn range(&self, from: u32, to: u32) -> impl Iterator<Item = &Summary> {
+        self.intervals
+            .oldest_ordered()
+            .filter(move |s| (from..=to).contains(&s.start))
+    }
+
+    /// The oldest completed interval starting within `from..=to`. Intervals are kept in
+    /// start order, so this is a binary search; step through a range by asking again from
+    /// just after the start of the last one.
+    pub fn first_in_range(&self, from: u32, to: u32) -> Option<Summary> {
+        let (older, newer) = self.intervals.as_slices();
+        [older, newer]
+            .into_iter()
+            .find_map(|slice| slice.get(slice.partition_point(|s| s.start < from)))
+            .filter(|s| s.start <= to)
+            .copied()
+    }
+}
+
+/// Record Access Control Point op codes and response values (Bluetooth RACP, 0x2A52).
+pub mod racp {
+    pub const REPORT_RECORDS: u8 = 0x01;
+    pub const ABORT: u8 = 0x03;
+    pub const REPORT_COUNT: u8 = 0x04;
+    pub const COUNT_RESPONSE: u8 = 0x05;
+    pub const RESPONSE_CODE: u8 = 0x06;
+
+    pub const OPERATOR_NULL: u8 = 0x00;
+    pub const OPERATOR_ALL: u8 = 0x01;
+    pub const OPERATOR_RANGE: u8 = 0x04;
+
+    /// Filter on the record timestamp, in the same time base the records are sent in.
+    pub const FILTER_TIME: u8 = 0x02;
+
+    pub const SUCCESS: u8 = 0x01;
+    pub const OP_CODE_NOT_SUPPORTED: u8 = 0x02;
+    pub const INVALID_OPERATOR: u8 = 0x03;
+    pub const INVALID_OPERAND: u8 = 0x05;
+    pub const NO_RECORDS: u8 = 0x06;
+    pub const PROCEDURE_NOT_COMPLETED: u8 = 0x08;
+}
+
+/// A request written to the record access control point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum RecordRequest {
+    /// Stream records with timestamps in `from..=to`.
+    Report {
+        from: u32,
+        to: u32,
+    },
+    /// Reply with the number of records in `from..=to`.
+    Count {
+        from: u32,
+        to: u32,
+    },
+    Abort,
+}
+
+impl RecordRequest {
+    /// Parse a control point write. On error returns the RACP response value to send back.
+    pub fn parse(data: &[u8]) -> Result<Self, u8> {
+        let (&op, rest) = data.split_first().ok_or(racp::INVALID_OPERAND)?;
+        if op == racp::ABORT {
+            return Ok(Self::Abort);
+        }
+        if op != racp::REPORT_RECORDS && op != racp::REPORT_COUNT {
+            return Err(racp::OP_CODE_NOT_SUPPORTED);
+        }
+        let (from, to) = match rest {
+            [racp::OPERATOR_ALL] => (0, u32::MAX),
+            [racp::OPERATOR_RANGE, racp::FILTER_TIME, operands @ ..] if operands.len() == 8 => {
+                let from = u32::from_le_bytes([operands[0], operands[1], operands[2], operands[3]]);
+                let to = u32::from_le_bytes([operands[4], operands[5], operands[6], operands[7]]);
+                if from > to {
+                    return Err(racp::INVALID_OPERAND);
+                }
+                (from, to)
+            }
+            [racp::OPERATOR_RANGE, ..] => return Err(racp::INVALID_OPERAND),
+            _ => return Err(racp::INVALID_OPERATOR),
+        };
+        Ok(if op == racp::REPORT_RECORDS {
+            Self::Report { from, to }
+        } else {
+            Self::Count { from, to }
+        })
+    }
+
+    pub fn op_code(&self) -> u8 {
+        match self {
+            Self::Report { .. } => racp::REPORT_RECORDS,
+            Self::Count { .. } => racp::REPORT_COUNT,
+            Self::Abort => racp::ABORT,
+        }
+    }
+}
+
+/// Response code notification: `[0x06, 0x00, request op code, value]`.
+pub fn racp_response(request_op: u8, value: u8) -> [u8; 4] {
+    [racp::RESPONSE_CODE, racp::OPERATOR_NULL, request_op, value]
+}
+
+/// Number of records response: `[0x05, 0x00, count (u16 LE)]`.
+pub fn racp_count(count: u16) -> [u8; 4] {
+    let count = count.to_le_bytes();
+    [
+        racp::COUNT_RESPONSE,
+        racp::OPERATOR_NULL,
+        count[0],
+        count[1],
+    ]
+}
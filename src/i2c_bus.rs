@@ -0,0 +1,65 @@
+//! Shared I2C0 bus.
+//!
+//! The bus lives behind an async mutex so the display and the sensor drivers can share it
+//! through [`I2cDevice`] handles, each transaction taking the lock for its duration. A
+//! display flush is split into many short writes, so a sensor read waits at most for one
+//! of them rather than for the whole frame.
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal_async::i2c::I2c as AsyncI2c;
+use esp_hal::i2c::master::I2c;
+use esp_hal::Async;
+
+/// Only one executor runs, so a NoopRawMutex is enough.
+pub type I2cBus = Mutex<NoopRawMutex, I2c<'static, Async>>;
+pub type I2cDevice = embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice<
+    'static,
+    NoopRawMutex,
+    I2c<'static, Async>,
+>;
+
+/// First and last non-reserved 7-bit addresses.
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// Set of addresses that acknowledged during a scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct ScanResult {
+    /// Bit `n` set if address `n` answered.
+    pub bitmap: [u8; 16],
+}
+
+impl ScanResult {
+    pub fn insert(&mut self, address: u8) {
+        self.bitmap[(address / 8) as usize % 16] |= 1 << (address % 8);
+    }
+
+    pub fn contains(&self, address: u8) -> bool {
+        self.bitmap[(address / 8) as usize % 16] & (1 << (address % 8)) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..128u8).filter(|a| self.contains(*a))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bitmap.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Probe every non-reserved address with a one byte read.
+pub async fn scan<I: AsyncI2c>(i2c: &mut I) -> ScanResult {
+    let mut found = ScanResult::default();
+    let mut buf = [0u8];
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        if i2c.read(address, &mut buf).await.is_ok() {
+            found.insert(address);
+        }
+    }
+    found
+}
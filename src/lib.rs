@@ -2,26 +2,72 @@
 
 extern crate alloc;
 
+pub mod advertising;
+pub mod alarm;
+pub mod animation;
+pub mod battery;
+pub mod button;
+pub mod clock;
+pub mod display_power;
+pub mod framebuffer;
+pub mod history;
+pub mod i2c_bus;
+pub mod link;
+pub mod mdns;
+pub mod peer;
+pub mod power;
+pub mod proximity;
+pub mod qr;
+pub mod relay;
+pub mod scan;
+pub mod sensor;
+pub mod settings;
+pub mod sntp;
+pub mod state;
+pub mod status_led;
+pub mod stream;
 pub mod task;
+pub mod throughput;
 
 pub mod mock {
     use alloc::vec;
     use alloc::vec::Vec;
-    use core::cell::RefCell;
+    use core::cell::{Cell, RefCell};
     use embedded_graphics::{geometry::OriginDimensions, pixelcolor::BinaryColor, prelude::*};
-    // No imports needed from ssd1306
+
+    use crate::framebuffer::{PageWriter, WIDTH};
+    use crate::peer::{PeerClient, PeerUuid, Target};
+    use crate::status_led::{Color, LedWriter};
+
+    // A page write as it would have gone over I2C
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct MockWrite {
+        pub page: u8,
+        pub column: u8,
+        pub data: Vec<u8>,
+    }
 
     // Mock implementation of the display
     pub struct MockDisplay {
         buffer: RefCell<Vec<u8>>,
+        writes: Vec<MockWrite>,
         width: u32,
         height: u32,
     }
 
+    impl Default for MockDisplay {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    // mirrors the unit error of the display driver
+    #[allow(clippy::result_unit_err)]
     impl MockDisplay {
         pub fn new() -> Self {
             Self {
                 buffer: RefCell::new(vec![0; (128 * 64 / 8) as usize]),
+                writes: Vec::new(),
                 width: 128,
                 height: 64,
             }
@@ -40,6 +86,35 @@ pub mod mock {
             // For the mock, we just return success
             Ok(())
         }
+
+        // Panel memory as written through PageWriter
+        pub fn buffer(&self) -> Vec<u8> {
+            self.buffer.borrow().clone()
+        }
+
+        // Page writes received since the last call
+        pub fn take_writes(&mut self) -> Vec<MockWrite> {
+            core::mem::take(&mut self.writes)
+        }
+    }
+
+    impl PageWriter for MockDisplay {
+        type Error = ();
+
+        async fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), ()> {
+            let start = page as usize * WIDTH + column as usize;
+            let mut buffer = self.buffer.borrow_mut();
+            buffer
+                .get_mut(start..start + data.len())
+                .ok_or(())?
+                .copy_from_slice(data);
+            self.writes.push(MockWrite {
+                page,
+                column,
+                data: data.to_vec(),
+            });
+            Ok(())
+        }
     }
 
     impl OriginDimensions for MockDisplay {
@@ -71,6 +146,102 @@ pub mod mock {
         }
     }
 
+    // Status LED that records every colour written to it
+    #[derive(Default)]
+    pub struct MockLed {
+        writes: Vec<Color>,
+    }
+
+    impl MockLed {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Colours written since the last call
+        pub fn take_writes(&mut self) -> Vec<Color> {
+            core::mem::take(&mut self.writes)
+        }
+    }
+
+    impl LedWriter for MockLed {
+        type Error = ();
+
+        async fn write(&mut self, color: Color) -> Result<(), ()> {
+            self.writes.push(color);
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+    pub enum MockGattError {
+        NotFound,
+        ReadNotPermitted,
+        Disconnected,
+    }
+
+    // A peripheral with one characteristic, as the central sees it over GATT
+    pub struct MockPeripheral {
+        pub service: PeerUuid,
+        pub characteristic: PeerUuid,
+        // `None` for a characteristic that only notifies
+        pub value: Option<&'static [u8]>,
+        // Sent in order once subscribed, then the link drops
+        pub notifications: &'static [&'static [u8]],
+        pub subscribed: Cell<bool>,
+    }
+
+    impl MockPeripheral {
+        pub fn new(target: &Target) -> Self {
+            Self {
+                service: target.service,
+                characteristic: target.characteristic,
+                value: None,
+                notifications: &[],
+                subscribed: Cell::new(false),
+            }
+        }
+    }
+
+    impl PeerClient for MockPeripheral {
+        type Error = MockGattError;
+        type Characteristic = ();
+
+        async fn find(
+            &self,
+            service: PeerUuid,
+            characteristic: PeerUuid,
+        ) -> Result<Option<()>, MockGattError> {
+            if service != self.service {
+                return Ok(None);
+            }
+            if characteristic != self.characteristic {
+                return Err(MockGattError::NotFound);
+            }
+            Ok(Some(()))
+        }
+
+        async fn read(&self, _: &(), buf: &mut [u8]) -> Result<usize, MockGattError> {
+            let value = self.value.ok_or(MockGattError::ReadNotPermitted)?;
+            let len = value.len().min(buf.len());
+            buf[..len].copy_from_slice(&value[..len]);
+            Ok(len)
+        }
+
+        async fn subscribe(
+            &self,
+            _: &(),
+            subscribed: impl FnOnce(),
+            mut on_value: impl FnMut(&[u8]),
+        ) -> Result<(), MockGattError> {
+            self.subscribed.set(true);
+            subscribed();
+            for notification in self.notifications {
+                on_value(notification);
+            }
+            Err(MockGattError::Disconnected)
+        }
+    }
+
     // Type alias for the mock display that matches the real display type
     pub type MockDisplayType = MockDisplay;
 
@@ -82,85 +253,299 @@ pub mod mock {
 
 // Re-export the display task function for both main and testing
 pub mod display {
+    use core::fmt::Write;
+
     use embedded_graphics::{
         mono_font::MonoTextStyle, pixelcolor::BinaryColor, prelude::*, text::Text,
     };
 
-    // Function to update the display with the given counter value
-    pub fn update_display<D>(
+    use crate::alarm::AlarmState;
+    use crate::animation::{Frame, Row};
+    use crate::battery::Battery;
+    use crate::link::Link;
+    use crate::qr::{self, QrError};
+    use crate::scan::Neighbor;
+
+    /// What the display shows, stepped through with short presses of the BOOT button.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+    pub enum Page {
+        #[default]
+        Cow,
+        Readings,
+        Status,
+        Nearby,
+    }
+
+    impl Page {
+        pub fn next(self) -> Self {
+            match self {
+                Page::Cow => Page::Readings,
+                Page::Readings => Page::Status,
+                Page::Status => Page::Nearby,
+                Page::Nearby => Page::Cow,
+            }
+        }
+    }
+
+    // Write a value in hundredths with two decimals, e.g. -1.05
+    fn write_hundredths<W: Write>(out: &mut W, value: i32) -> core::fmt::Result {
+        let sign = if value < 0 { "-" } else { "" };
+        let abs = value.unsigned_abs();
+        write!(out, "{}{}.{:02}", sign, abs / 100, abs % 100)
+    }
+
+    // Function to show the sensor readings, humidity and pressure only if a sensor has them
+    // and the battery only with a battery monitor
+    pub fn update_readings_display<D>(
         display: &mut D,
-        counter: u32,
-        x_offset: i32,
-        y_offset: i32,
+        temperature: i16,
+        humidity: Option<u16>,
+        pressure: Option<u32>,
+        battery: Option<Battery>,
         text_style: MonoTextStyle<'_, BinaryColor>,
     ) -> Result<(), D::Error>
     where
         D: DrawTarget<Color = BinaryColor>,
     {
-        // Clear display first
         display.clear(BinaryColor::Off)?;
 
-        // Determine which version of the speech bubble to display based on counter % 5
-        let cow_art = match counter % 5 {
-            1 => [
-                r"       ",
-                r"  ^__^",
-                r"B (oo)\____",
-                r"L (__)\       )\/\",
-                r"E     ||--w ||",
-                r"      ||       ||",
-            ], // 1st iteration: "BLE"
-            2 => [
-                r"       ",
-                r"  ^__^",
-                r"  (oo)\____",
-                r"  (__)\       )\/\",
-                r"       ||--w ||",
-                r"      ||       ||",
-            ], // 2nd iteration: "" (empty)
-            3 => [
-                r"       ",
-                r"  ^__^",
-                r"B (oo)\____",
-                r"  (__)\       )\/\",
-                r"       ||--w ||",
-                r"      ||       ||",
-            ], // 3rd iteration: "B"
-            4 => [
-                r"       ",
-                r"  ^__^",
-                r"B (oo)\____",
-                r"L (__)\       )\/\",
-                r"       ||--w ||",
-                r"      ||       ||",
-            ], // 4th iteration: "BL"
-            0 => [
-                r"       ",
-                r"  ^__^",
-                r"B (oo)\____",
-                r"L (__)\       )\/\",
-                r"E     ||--w ||",
-                r"      ||       ||",
-            ], // 5th iteration: "BLE" (same as 1st)
-            _ => unreachable!(),
-        };
+        let mut line: heapless::String<21> = heapless::String::new();
+        let _ = write!(line, "Temp  ");
+        let _ = write_hundredths(&mut line, temperature as i32);
+        let _ = write!(line, " C");
+        Text::new(&line, Point::new(4, 20), text_style).draw(display)?;
 
-        // Draw the cow ASCII art with smaller vertical spacing
-        for (i, line) in cow_art.iter().enumerate() {
-            // If this is the line with the eyes (index 2) and counter is divisible by 10,
-            // replace "(oo)" with "(X.)"
-            let display_line = if i == 2 && counter % 10 == 0 {
-                "B (X.)\\____"
+        if let Some(humidity) = humidity {
+            line.clear();
+            let _ = write!(line, "Hum   ");
+            let _ = write_hundredths(&mut line, humidity as i32);
+            let _ = write!(line, " %");
+            Text::new(&line, Point::new(4, 34), text_style).draw(display)?;
+        }
+        if let Some(pressure) = pressure {
+            // 0.1 Pa to hPa with one decimal
+            line.clear();
+            let _ = write!(
+                line,
+                "Press {}.{} hPa",
+                pressure / 1000,
+                pressure / 100 % 10
+            );
+            Text::new(&line, Point::new(4, 48), text_style).draw(display)?;
+        }
+        if let Some(battery) = battery {
+            line.clear();
+            let (volts, hundredths) = (battery.mv / 1000, battery.mv / 10 % 100);
+            let _ = if battery.is_present() {
+                write!(
+                    line,
+                    "Batt  {}.{:02}V {}%",
+                    volts, hundredths, battery.percent
+                )
             } else {
-                *line
+                write!(line, "Batt  none")
             };
+            Text::new(&line, Point::new(4, 62), text_style).draw(display)?;
+        }
+
+        Ok(())
+    }
+
+    // Function to show the connection state, uptime and detected sensors
+    // `link` is `None` while no central is connected
+    pub fn update_status_display<D>(
+        display: &mut D,
+        link: Option<&Link>,
+        leash: bool,
+        uptime_secs: u64,
+        sensors: &str,
+        text_style: MonoTextStyle<'_, BinaryColor>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        display.clear(BinaryColor::Off)?;
+
+        let mut line: heapless::String<21> = heapless::String::new();
+        let _ = match link {
+            _ if leash => write!(line, "BLE   leash alarm"),
+            Some(Link {
+                rssi: Some(rssi),
+                proximity,
+                ..
+            }) => write!(line, "BLE   {}dBm {}", rssi, proximity.label()),
+            Some(_) => write!(line, "BLE   connected"),
+            None => write!(line, "BLE   advertising"),
+        };
+        Text::new(&line, Point::new(4, 12), text_style).draw(display)?;
+
+        // interval in ms, peripheral latency and ATT MTU
+        line.clear();
+        let _ = match link {
+            Some(link) if link.interval_us != 0 => {
+                let (ms, tenths) = (link.interval_us / 1000, link.interval_us % 1000 / 100);
+                write!(
+                    line,
+                    "Link  {}.{}ms L{} M{}",
+                    ms, tenths, link.latency, link.mtu
+                )
+            }
+            Some(link) => write!(line, "Link  M{}", link.mtu),
+            None => write!(line, "Link  -"),
+        };
+        Text::new(&line, Point::new(4, 26), text_style).draw(display)?;
+
+        line.clear();
+        let (hours, minutes) = (uptime_secs / 3600, uptime_secs / 60 % 60);
+        let _ = write!(line, "Up    {}h {:02}m", hours, minutes);
+        Text::new(&line, Point::new(4, 40), text_style).draw(display)?;
+
+        // a long list just runs off the edge
+        let sensors = if sensors.is_empty() { "none" } else { sensors };
+        Text::new("Sens", Point::new(4, 54), text_style).draw(display)?;
+        Text::new(sensors, Point::new(40, 54), text_style).draw(display)?;
+
+        Ok(())
+    }
+
+    // Function to list nearby sensors, one line each: the end of the address, temperature
+    // and humidity as far as they report them
+    pub fn update_nearby_display<D>(
+        display: &mut D,
+        neighbors: &[Neighbor],
+        text_style: MonoTextStyle<'_, BinaryColor>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        display.clear(BinaryColor::Off)?;
+
+        if neighbors.is_empty() {
+            Text::new("No sensors nearby", Point::new(4, 34), text_style).draw(display)?;
+            return Ok(());
+        }
+        let mut y = 10;
+        // five lines of the 9 px font fit
+        for neighbor in neighbors.iter().take(5) {
+            let mut line: heapless::String<21> = heapless::String::new();
+            let address = neighbor.address;
+            let _ = write!(line, "{:02X}{:02X}", address[1], address[0]);
+            if let Some(temperature) = neighbor.reading.temperature {
+                let _ = write!(line, " ");
+                let _ = write_hundredths(&mut line, temperature as i32);
+                let _ = write!(line, "C");
+            }
+            if let Some(humidity) = neighbor.reading.humidity {
+                let _ = write!(line, " {}%", humidity / 100);
+            }
+            Text::new(&line, Point::new(4, y), text_style).draw(display)?;
+            y += 12;
+        }
+
+        Ok(())
+    }
+
+    // Function to show the pairing QR code on the left half, with a hint next to it
+    pub fn update_pairing_display<D>(
+        display: &mut D,
+        payload: &str,
+        text_style: MonoTextStyle<'_, BinaryColor>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        display.clear(BinaryColor::Off)?;
+
+        let hint = match qr::draw(display, payload, Point::zero(), 64) {
+            Ok(_) => ["Scan to", "pair"],
+            Err(QrError::TooLong) => ["No QR", "code"],
+            Err(QrError::Draw(e)) => return Err(e),
+        };
+        Text::new(hint[0], Point::new(72, 28), text_style).draw(display)?;
+        Text::new(hint[1], Point::new(72, 40), text_style).draw(display)?;
+
+        Ok(())
+    }
+
+    // Function to show the temperature alarm in a banner above the cow
+    pub fn draw_alert_banner<D>(
+        display: &mut D,
+        state: AlarmState,
+        temperature: i16,
+        text_style: MonoTextStyle<'_, BinaryColor>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let title = match state {
+            AlarmState::High => "TOO HOT",
+            AlarmState::Low => "TOO COLD",
+            AlarmState::Normal => return Ok(()),
+        };
+        let mut banner: heapless::String<24> = heapless::String::new();
+        let _ = write!(banner, "{} ", title);
+        let _ = write_hundredths(&mut banner, temperature as i32);
+        let _ = write!(banner, " C");
 
-            Text::new(
-                display_line,
-                Point::new(x_offset, y_offset + (i as i32 * 7)),
-                text_style,
-            )
-            .draw(display)?;
+        // centred on the 6 px wide font
+        let x = (128 - banner.len() as i32 * 6) / 2;
+        Text::new(&banner, Point::new(x, 10), text_style).draw(display)?;
+
+        Ok(())
+    }
+
+    // Function to show a low battery in a banner above the cow
+    pub fn draw_battery_banner<D>(
+        display: &mut D,
+        battery: &Battery,
+        text_style: MonoTextStyle<'_, BinaryColor>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        if !battery.is_low() {
+            return Ok(());
+        }
+        let mut banner: heapless::String<24> = heapless::String::new();
+        let _ = write!(banner, "LOW BATTERY {}%", battery.percent);
+
+        let x = (128 - banner.len() as i32 * 6) / 2;
+        Text::new(&banner, Point::new(x, 10), text_style).draw(display)?;
+
+        Ok(())
+    }
+
+    // Function to draw one animation frame of the cow. Text rows are drawn with their
+    // baseline at the cursor and move it down 7 px, pixel rows start 6 px above it, where
+    // the letters of a text row would, and move it down 1 px.
+    pub fn update_display<D>(
+        display: &mut D,
+        frame: &Frame<'_>,
+        x_offset: i32,
+        y_offset: i32,
+        text_style: MonoTextStyle<'_, BinaryColor>,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        // Clear display first
+        display.clear(BinaryColor::Off)?;
+
+        let mut y = y_offset;
+        for row in frame.rows() {
+            match row {
+                Row::Text(text) => {
+                    Text::new(text, Point::new(x_offset, y), text_style).draw(display)?;
+                    y += 7;
+                }
+                Row::Pixels(pixels) => {
+                    let lit = pixels.bytes().enumerate().filter(|&(_, c)| c == b'#');
+                    display.draw_iter(lit.map(|(x, _)| {
+                        Pixel(Point::new(x_offset + x as i32, y - 6), BinaryColor::On)
+                    }))?;
+                    y += 1;
+                }
+            }
         }
 
         Ok(())
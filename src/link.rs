@@ -0,0 +1,133 @@
+//! Connection parameter policy.
+//!
+//! While a central is connected the link runs on [`FAST`] parameters as long as there is
+//! activity (GATT requests, history downloads) and drops to [`IDLE`] after
+//! [`IDLE_AFTER_MS`] without any, which lets the radio sleep through most connection
+//! events. The central has the last word; what it settles on is tracked as a [`Link`]. Times
+//! are milliseconds since boot.
+
+use crate::proximity::Proximity;
+
+/// How long the link stays fast after the last activity.
+pub const IDLE_AFTER_MS: u64 = 10_000;
+
+/// Size of the encoded [`Link`].
+pub const LINK_LEN: usize = 9;
+
+/// Connection parameters to request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Profile {
+    pub interval_min_us: u32,
+    pub interval_max_us: u32,
+    /// Connection events the peripheral may skip.
+    pub latency: u16,
+    pub timeout_ms: u32,
+}
+
+/// Short intervals for transfers.
+pub const FAST: Profile = Profile {
+    interval_min_us: 15_000,
+    interval_max_us: 30_000,
+    latency: 0,
+    timeout_ms: 4_000,
+};
+
+/// Long intervals and some latency while nothing happens.
+pub const IDLE: Profile = Profile {
+    interval_min_us: 200_000,
+    interval_max_us: 400_000,
+    latency: 4,
+    timeout_ms: 6_000,
+};
+
+impl Profile {
+    /// Within the ranges of the Bluetooth Core spec: interval 7.5 ms to 4 s, latency up to
+    /// 499, supervision timeout 100 ms to 32 s and longer than two effective intervals.
+    pub fn is_valid(&self) -> bool {
+        let intervals = 7_500..=4_000_000;
+        intervals.contains(&self.interval_min_us)
+            && intervals.contains(&self.interval_max_us)
+            && self.interval_min_us <= self.interval_max_us
+            && self.latency <= 499
+            && (100..=32_000).contains(&self.timeout_ms)
+            && self.timeout_ms as u64 * 1000
+                > 2 * (1 + self.latency as u64) * self.interval_max_us as u64
+    }
+}
+
+/// Decides which [`Profile`] to request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LinkPolicy {
+    last_activity: u64,
+    requested: Option<Profile>,
+}
+
+impl LinkPolicy {
+    /// Policy for a connection made at `now`, which counts as activity.
+    pub const fn new(now: u64) -> Self {
+        Self {
+            last_activity: now,
+            requested: None,
+        }
+    }
+
+    pub fn activity(&mut self, now: u64) {
+        self.last_activity = self.last_activity.max(now);
+    }
+
+    /// The profile that fits the activity at `now`.
+    pub fn wanted(&self, now: u64) -> Profile {
+        if now.saturating_sub(self.last_activity) < IDLE_AFTER_MS {
+            FAST
+        } else {
+            IDLE
+        }
+    }
+
+    /// The profile to request now, `None` if it was requested already.
+    pub fn poll(&mut self, now: u64) -> Option<Profile> {
+        let wanted = self.wanted(now);
+        if self.requested == Some(wanted) {
+            return None;
+        }
+        self.requested = Some(wanted);
+        Some(wanted)
+    }
+
+    /// Milliseconds until [`poll`](Self::poll) has something new without further activity,
+    /// `None` once the link is idle.
+    pub fn due_in(&self, now: u64) -> Option<u64> {
+        if self.requested != Some(self.wanted(now)) {
+            return Some(0);
+        }
+        let idle_at = self.last_activity + IDLE_AFTER_MS;
+        (now < idle_at).then(|| idle_at - now)
+    }
+}
+
+/// The link as negotiated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Link {
+    /// 0 until the central reports the parameters.
+    pub interval_us: u32,
+    pub latency: u16,
+    pub timeout_ms: u32,
+    pub mtu: u16,
+    /// Smoothed, see [`crate::proximity`].
+    pub rssi: Option<i8>,
+    pub proximity: Proximity,
+}
+
+impl Link {
+    /// Interval in 1.25 ms units, latency, supervision timeout in 10 ms units and ATT MTU,
+    /// all u16 little-endian, then RSSI (`0x7F` if unknown).
+    pub fn encode(&self) -> [u8; LINK_LEN] {
+        let mut out = [0u8; LINK_LEN];
+        out[0..2].copy_from_slice(&((self.interval_us / 1250) as u16).to_le_bytes());
+        out[2..4].copy_from_slice(&self.latency.to_le_bytes());
+        out[4..6].copy_from_slice(&((self.timeout_ms / 10) as u16).to_le_bytes());
+        out[6..8].copy_from_slice(&self.mtu.to_le_bytes());
+        out[8] = self.rssi.unwrap_or(i8::MAX) as u8;
+        out
+    }
+}
@@ -0,0 +1,322 @@
+//! mDNS / DNS-SD responder (RFC 6762, RFC 6763).
+//!
+//! Answers queries for `<hostname>.local` and for the `_coagatt._tcp` service so host tools
+//! can find boards without knowing their IP. This module only deals with packets; the UDP
+//! socket lives in [`crate::task::net`].
+
+use core::fmt::Write;
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_GROUP: [u8; 4] = [224, 0, 0, 251];
+
+/// Port advertised in the SRV record, where [`crate::task::net::status_task`] serves the
+/// readings.
+pub const SERVICE_PORT: u16 = 7070;
+
+const SERVICE: [&str; 3] = ["_coagatt", "_tcp", "local"];
+const SERVICES_META: [&str; 4] = ["_services", "_dns-sd", "_udp", "local"];
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+/// Set on records we are the only owner of, so caches replace rather than merge them.
+const CACHE_FLUSH: u16 = 0x8000;
+
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+/// TTL in replies to legacy unicast queries, RFC 6762 section 6.7.
+const LEGACY_TTL: u32 = 10;
+
+const HEADER_LEN: usize = 12;
+const FLAGS_RESPONSE: u16 = 0x8400; // QR + AA
+
+/// `cow-gatt-e4ff` style host name derived from the last two bytes of the MAC address.
+pub fn hostname_from_mac(mac: &[u8; 6]) -> heapless::String<16> {
+    let mut name = heapless::String::new();
+    let _ = write!(name, "cow-gatt-{:02x}{:02x}", mac[4], mac[5]);
+    name
+}
+
+/// Everything the responder announces about this device.
+pub struct Responder<'a> {
+    /// Host label, without `.local`.
+    pub hostname: &'a str,
+    /// Service instance label, e.g. `COW GATT e4ff`.
+    pub instance: &'a str,
+    pub ipv4: [u8; 4],
+    pub port: u16,
+    /// TXT record entries, already in `key=value` form.
+    pub txt: &'a [&'a str],
+}
+
+/// Which records a query asked for.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Answers {
+    pub a: bool,
+    pub ptr: bool,
+    pub srv: bool,
+    pub txt: bool,
+    pub services: bool,
+}
+
+impl Answers {
+    const ALL: Self = Self {
+        a: true,
+        ptr: true,
+        srv: true,
+        txt: true,
+        services: false,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        !(self.a || self.ptr || self.srv || self.txt || self.services)
+    }
+}
+
+impl Responder<'_> {
+    /// Work out which of our records `packet` asks for. Responses and malformed packets
+    /// yield no answers.
+    pub fn match_query(&self, packet: &[u8]) -> Answers {
+        let mut answers = Answers::default();
+        if packet.len() < HEADER_LEN || be16(packet, 2) & 0x8000 != 0 {
+            return answers;
+        }
+        let questions = be16(packet, 4);
+        let mut pos = HEADER_LEN;
+        for _ in 0..questions {
+            let Some(end) = skip_name(packet, pos) else {
+                return Answers::default();
+            };
+            if end + 4 > packet.len() {
+                return Answers::default();
+            }
+            let qtype = be16(packet, end);
+            let qclass = be16(packet, end + 2) & !CACHE_FLUSH;
+            if qclass == CLASS_IN || qclass == TYPE_ANY {
+                let any = qtype == TYPE_ANY;
+                if name_eq(packet, pos, &[self.hostname, "local"]) {
+                    answers.a |= any || qtype == TYPE_A;
+                } else if name_eq(packet, pos, &SERVICE) {
+                    answers.ptr |= any || qtype == TYPE_PTR;
+                } else if name_eq(
+                    packet,
+                    pos,
+                    &[self.instance, SERVICE[0], SERVICE[1], SERVICE[2]],
+                ) {
+                    answers.srv |= any || qtype == TYPE_SRV;
+                    answers.txt |= any || qtype == TYPE_TXT;
+                } else if name_eq(packet, pos, &SERVICES_META) {
+                    answers.services |= any || qtype == TYPE_PTR;
+                }
+            }
+            pos = end + 4;
+        }
+        answers
+    }
+
+    /// Build the response to `packet` into `out`, or `None` if there is nothing to answer.
+    pub fn respond(&self, packet: &[u8], out: &mut [u8]) -> Option<usize> {
+        let answers = self.match_query(packet);
+        if answers.is_empty() {
+            return None;
+        }
+        self.encode(answers, None, out)
+    }
+
+    /// Build the response to a legacy unicast query, one sent by a plain DNS resolver from a
+    /// port other than 5353. It repeats the query ID and questions, and its records have a
+    /// short TTL and no cache flush bit.
+    pub fn respond_unicast(&self, packet: &[u8], out: &mut [u8]) -> Option<usize> {
+        let answers = self.match_query(packet);
+        if answers.is_empty() {
+            return None;
+        }
+        self.encode(answers, Some(packet), out)
+    }
+
+    /// Unsolicited announcement of all records, sent on startup and on IP changes.
+    pub fn announce(&self, out: &mut [u8]) -> Option<usize> {
+        self.encode(Answers::ALL, None, out)
+    }
+
+    /// `query` is the legacy unicast query being answered, `None` for multicast.
+    fn encode(&self, answers: Answers, query: Option<&[u8]>, out: &mut [u8]) -> Option<usize> {
+        let mut w = Writer { buf: out, pos: 0 };
+        let count = [
+            answers.a,
+            answers.ptr,
+            answers.srv,
+            answers.txt,
+            answers.services,
+        ]
+        .iter()
+        .filter(|a| **a)
+        .count() as u16;
+        // PTR/SRV answers are useless without the address, send it as an additional record
+        let additional_a = !answers.a && (answers.ptr || answers.srv);
+        let (flush, host_ttl, service_ttl) = match query {
+            Some(_) => (0, LEGACY_TTL, LEGACY_TTL),
+            None => (CACHE_FLUSH, HOST_TTL, SERVICE_TTL),
+        };
+        // the id is always 0 for multicast responses
+        w.u16(query.map_or(0, |query| be16(query, 0)))?;
+        w.u16(FLAGS_RESPONSE)?;
+        w.u16(query.map_or(0, |query| be16(query, 4)))?;
+        w.u16(count)?;
+        w.u16(0)?;
+        w.u16(additional_a as u16)?;
+        if let Some(query) = query {
+            // copied as is: at the same offset, compression pointers stay valid
+            w.bytes(&query[HEADER_LEN..questions_end(query)?])?;
+        }
+
+        let instance = [self.instance, SERVICE[0], SERVICE[1], SERVICE[2]];
+        let host = [self.hostname, "local"];
+
+        if answers.services {
+            w.record(&SERVICES_META, TYPE_PTR, CLASS_IN, service_ttl)?;
+            w.rdata(|w| w.name(&SERVICE))?;
+        }
+        if answers.ptr {
+            w.record(&SERVICE, TYPE_PTR, CLASS_IN, service_ttl)?;
+            w.rdata(|w| w.name(&instance))?;
+        }
+        if answers.srv {
+            w.record(&instance, TYPE_SRV, CLASS_IN | flush, host_ttl)?;
+            w.rdata(|w| {
+                w.u16(0)?; // priority
+                w.u16(0)?; // weight
+                w.u16(self.port)?;
+                w.name(&host)
+            })?;
+        }
+        if answers.txt {
+            w.record(&instance, TYPE_TXT, CLASS_IN | flush, service_ttl)?;
+            w.rdata(|w| {
+                for entry in self.txt {
+                    w.label(entry)?;
+                }
+                Some(())
+            })?;
+        }
+        // last, so that as an additional record it follows the answer section
+        if answers.a || additional_a {
+            w.record(&host, TYPE_A, CLASS_IN | flush, host_ttl)?;
+            w.rdata(|w| w.bytes(&self.ipv4))?;
+        }
+        Some(w.pos)
+    }
+}
+
+fn be16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([buf[pos], buf[pos + 1]])
+}
+
+/// Return the offset just past the question section of `packet`.
+fn questions_end(packet: &[u8]) -> Option<usize> {
+    let mut pos = HEADER_LEN;
+    for _ in 0..be16(packet, 4) {
+        pos = skip_name(packet, pos)? + 4;
+    }
+    (pos <= packet.len()).then_some(pos)
+}
+
+/// Return the offset just past the (possibly compressed) name starting at `pos`.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xC0 == 0xC0 => return (pos + 2 <= packet.len()).then_some(pos + 2),
+            l => pos += 1 + l,
+        }
+    }
+}
+
+/// Compare the name at `pos` against `labels`, ASCII case-insensitively, following
+/// compression pointers.
+fn name_eq(packet: &[u8], mut pos: usize, labels: &[&str]) -> bool {
+    let mut labels = labels.iter();
+    // bound the number of pointer jumps so a malicious loop cannot hang us
+    let mut jumps = 0;
+    loop {
+        let Some(&len) = packet.get(pos) else {
+            return false;
+        };
+        if len & 0xC0 == 0xC0 {
+            let Some(&low) = packet.get(pos + 1) else {
+                return false;
+            };
+            jumps += 1;
+            if jumps > 8 {
+                return false;
+            }
+            pos = (((len & 0x3F) as usize) << 8) | low as usize;
+            continue;
+        }
+        let len = len as usize;
+        if len == 0 {
+            return labels.next().is_none();
+        }
+        let Some(label) = packet.get(pos + 1..pos + 1 + len) else {
+            return false;
+        };
+        match labels.next() {
+            Some(expected) if expected.as_bytes().eq_ignore_ascii_case(label) => pos += 1 + len,
+            _ => return false,
+        }
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, data: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + data.len())?
+            .copy_from_slice(data);
+        self.pos += data.len();
+        Some(())
+    }
+
+    fn u16(&mut self, v: u16) -> Option<()> {
+        self.bytes(&v.to_be_bytes())
+    }
+
+    fn label(&mut self, label: &str) -> Option<()> {
+        let len = u8::try_from(label.len()).ok().filter(|l| *l < 64)?;
+        self.bytes(&[len])?;
+        self.bytes(label.as_bytes())
+    }
+
+    fn name(&mut self, labels: &[&str]) -> Option<()> {
+        for label in labels {
+            self.label(label)?;
+        }
+        self.bytes(&[0])
+    }
+
+    fn record(&mut self, name: &[&str], rtype: u16, class: u16, ttl: u32) -> Option<()> {
+        self.name(name)?;
+        self.u16(rtype)?;
+        self.u16(class)?;
+        self.bytes(&ttl.to_be_bytes())
+    }
+
+    /// Write RDATA produced by `f`, prefixed by its length.
+    fn rdata(&mut self, f: impl FnOnce(&mut Self) -> Option<()>) -> Option<()> {
+        let len_pos = self.pos;
+        self.u16(0)?;
+        f(self)?;
+        let len = (self.pos - len_pos - 2) as u16;
+        self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Peripherals the board connects to as a central.
+//!
+//! Targets come from the `PEERS` build variable, separated by commas or spaces, each as
+//! `ADDRESS/SERVICE/CHARACTERISTIC[/random]`, for example
+//! `C8:5C:A2:11:22:33/180D/2A37` for a heart-rate strap. UUIDs are 16-bit hex or the full
+//! 128-bit form. The central subscribes to the characteristic and keeps its latest value;
+//! lost connections are retried with a growing delay. Times are milliseconds since boot.
+
+use defmt::warn;
+
+/// Most bytes of a peer's value that are kept, so a record fits in one notification.
+pub const PEER_VALUE_LEN: usize = 17;
+
+/// Size of the encoded [`PeerValue`].
+pub const PEER_RECORD_LEN: usize = 3 + PEER_VALUE_LEN;
+
+/// Heart Rate Measurement characteristic.
+pub const HEART_RATE_MEASUREMENT: u16 = 0x2A37;
+
+/// First and longest delay before reconnecting.
+pub const RETRY_MIN_MS: u64 = 1_000;
+pub const RETRY_MAX_MS: u64 = 60_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum AddressKind {
+    Public,
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum PeerUuid {
+    Short(u16),
+    /// Least significant byte first, as on air.
+    Long([u8; 16]),
+}
+
+impl PeerUuid {
+    /// `180D` or `0000180d-0000-1000-8000-00805f9b34fb` style.
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.len() == 4 {
+            return u16::from_str_radix(s, 16).ok().map(PeerUuid::Short);
+        }
+        let dashes = [8, 13, 18, 23];
+        if s.len() != 36 || dashes.iter().any(|&i| s.as_bytes()[i] != b'-') {
+            return None;
+        }
+        let mut bytes = [0u8; 16];
+        let mut digits = s
+            .char_indices()
+            .filter(|(i, _)| !dashes.contains(i))
+            .map(|(_, c)| c);
+        for byte in bytes.iter_mut().rev() {
+            let high = digits.next()?.to_digit(16)?;
+            let low = digits.next()?.to_digit(16)?;
+            *byte = (high << 4 | low) as u8;
+        }
+        Some(PeerUuid::Long(bytes))
+    }
+}
+
+/// A characteristic to subscribe to on a peripheral.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Target {
+    /// Least significant byte first, as the controller expects it.
+    pub address: [u8; 6],
+    pub kind: AddressKind,
+    pub service: PeerUuid,
+    pub characteristic: PeerUuid,
+}
+
+impl Target {
+    /// One `ADDRESS/SERVICE/CHARACTERISTIC[/random]` entry, `None` if malformed.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('/');
+        let address = parse_address(parts.next()?)?;
+        let service = PeerUuid::parse(parts.next()?)?;
+        let characteristic = PeerUuid::parse(parts.next()?)?;
+        let kind = match parts.next() {
+            None | Some("public") => AddressKind::Public,
+            Some("random") => AddressKind::Random,
+            Some(_) => return None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            address,
+            kind,
+            service,
+            characteristic,
+        })
+    }
+}
+
+/// The entries of a target list.
+pub fn entries(list: &str) -> impl Iterator<Item = &str> {
+    list.split([',', ' ']).filter(|entry| !entry.is_empty())
+}
+
+/// `AA:BB:CC:DD:EE:FF`, returned least significant byte first.
+fn parse_address(s: &str) -> Option<[u8; 6]> {
+    let mut address = [0u8; 6];
+    let mut parts = s.split(':');
+    for byte in address.iter_mut().rev() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then_some(address)
+}
+
+/// Delay before the next connection attempt, doubling after every failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Backoff {
+    delay_ms: u64,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self {
+            delay_ms: RETRY_MIN_MS,
+        }
+    }
+
+    /// The delay to wait now; the following one is twice as long, up to [`RETRY_MAX_MS`].
+    pub fn next_delay(&mut self) -> u64 {
+        let delay = self.delay_ms;
+        self.delay_ms = (delay * 2).min(RETRY_MAX_MS);
+        delay
+    }
+
+    /// Start over after a connection that worked.
+    pub fn reset(&mut self) {
+        self.delay_ms = RETRY_MIN_MS;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The GATT client side of a connection to a target: trouble-host's `GattClient` in the
+/// firmware, [`crate::mock::MockPeripheral`] in tests.
+#[allow(async_fn_in_trait)]
+pub trait PeerClient {
+    type Error;
+    type Characteristic;
+
+    /// Find `characteristic` in the first `service`, `None` if the service is missing.
+    async fn find(
+        &self,
+        service: PeerUuid,
+        characteristic: PeerUuid,
+    ) -> Result<Option<Self::Characteristic>, Self::Error>;
+
+    /// Read the value into `buf`, returning its length.
+    async fn read(
+        &self,
+        characteristic: &Self::Characteristic,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+
+    /// Enable notifications, call `subscribed` once they are on, then pass every notified
+    /// value to `on_value`. Only returns when the connection fails.
+    async fn subscribe(
+        &self,
+        characteristic: &Self::Characteristic,
+        subscribed: impl FnOnce(),
+        on_value: impl FnMut(&[u8]),
+    ) -> Result<(), Self::Error>;
+}
+
+/// Read the target characteristic, subscribe to it and pass every value to `on_value`. The
+/// backoff starts over once the subscription is on. Returns `Ok` if the service is missing,
+/// otherwise only when the connection fails.
+pub async fn listen<C: PeerClient>(
+    client: &C,
+    target: &Target,
+    backoff: &mut Backoff,
+    mut on_value: impl FnMut(&[u8]),
+) -> Result<(), C::Error> {
+    let Some(characteristic) = client.find(target.service, target.characteristic).await? else {
+        warn!("[peer] service {:?} not found", target.service);
+        return Ok(());
+    };
+    // characteristics that only notify cannot be read, their value comes with the first
+    // notification
+    let mut value = [0u8; PEER_VALUE_LEN];
+    if let Ok(len) = client.read(&characteristic, &mut value).await {
+        on_value(&value[..len]);
+    }
+    client
+        .subscribe(&characteristic, || backoff.reset(), on_value)
+        .await
+}
+
+/// Beats per minute from a Heart Rate Measurement, 8 or 16 bit depending on flag bit 0.
+pub fn heart_rate(data: &[u8]) -> Option<u16> {
+    let (&flags, value) = data.split_first()?;
+    if flags & 0x01 == 0 {
+        value.first().map(|&bpm| bpm as u16)
+    } else {
+        Some(u16::from_le_bytes([*value.first()?, *value.get(1)?]))
+    }
+}
+
+/// What we know about one target.
+#[derive(Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct PeerValue {
+    pub connected: bool,
+    /// Latest value read or notified, cut to [`PEER_VALUE_LEN`] bytes.
+    pub value: heapless::Vec<u8, PEER_VALUE_LEN>,
+}
+
+impl PeerValue {
+    pub const fn new() -> Self {
+        Self {
+            connected: false,
+            value: heapless::Vec::new(),
+        }
+    }
+
+    /// Keep the start of `data` as the latest value.
+    pub fn set(&mut self, data: &[u8]) {
+        let len = data.len().min(PEER_VALUE_LEN);
+        self.value.clear();
+        let _ = self.value.extend_from_slice(&data[..len]);
+    }
+
+    /// Index in the target list, connected flag, value length and the value padded with
+    /// zeros.
+    pub fn encode(&self, index: u8) -> [u8; PEER_RECORD_LEN] {
+        let mut out = [0u8; PEER_RECORD_LEN];
+        out[0] = index;
+        out[1] = self.connected as u8;
+        out[2] = self.value.len() as u8;
+        out[3..3 + self.value.len()].copy_from_slice(&self.value);
+        out
+    }
+}
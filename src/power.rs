@@ -0,0 +1,113 @@
+//! Board power policy.
+//!
+//! While a central is connected, or shortly after a button press or (dis)connect, the board
+//! is [`Mode::Active`]. Left alone without a connection it goes [`Mode::Idle`]: it keeps
+//! advertising, but sensors are sampled less often. After the configured time without a
+//! connection it goes to deep sleep and wakes up, as from a reset, when the wake-up timer
+//! runs out or the wake button is pressed. Something that needs attention, like a
+//! temperature alarm or the pairing QR code, keeps it active, and so do connections to
+//! peripherals and relaying. Times are seconds since boot.
+
+/// How long the board stays active after activity without a connection.
+pub const IDLE_AFTER_SECS: u64 = 30;
+
+/// Sensor sampling interval while active and while idle.
+pub const ACTIVE_SAMPLE_SECS: u64 = 2;
+pub const IDLE_SAMPLE_SECS: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Mode {
+    Active,
+    Idle,
+    /// Going to deep sleep: tasks switch off what they drive.
+    Sleep,
+}
+
+impl Mode {
+    /// How often the sensors are sampled.
+    pub fn sample_secs(self) -> u64 {
+        match self {
+            Mode::Active => ACTIVE_SAMPLE_SECS,
+            Mode::Idle | Mode::Sleep => IDLE_SAMPLE_SECS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct PowerConfig {
+    /// Time without a connection or activity before deep sleep, 0 to never sleep.
+    pub sleep_after_secs: u64,
+    /// How long a deep sleep lasts.
+    pub wake_after_secs: u64,
+}
+
+impl PowerConfig {
+    /// From the `DEEP_SLEEP_SECS` and `WAKE_SECS` build variables, `None` if either is not a
+    /// number or the wake-up time is 0 while sleep is enabled.
+    pub fn parse(sleep_after: &str, wake_after: &str) -> Option<Self> {
+        let config = Self {
+            sleep_after_secs: sleep_after.parse().ok()?,
+            wake_after_secs: wake_after.parse().ok()?,
+        };
+        (config.sleep_after_secs == 0 || config.wake_after_secs > 0).then_some(config)
+    }
+}
+
+pub struct PowerPolicy {
+    config: PowerConfig,
+    last_activity: u64,
+    connected: bool,
+    busy: bool,
+}
+
+impl PowerPolicy {
+    /// Policy for a board that booted or woke up at `now`, which counts as activity.
+    pub const fn new(config: PowerConfig, now: u64) -> Self {
+        Self {
+            config,
+            last_activity: now,
+            connected: false,
+            busy: false,
+        }
+    }
+
+    pub fn config(&self) -> &PowerConfig {
+        &self.config
+    }
+
+    pub fn activity(&mut self, now: u64) {
+        self.last_activity = self.last_activity.max(now);
+    }
+
+    /// Connecting and disconnecting both count as activity.
+    pub fn set_connected(&mut self, connected: bool, now: u64) {
+        if connected != self.connected {
+            self.connected = connected;
+            self.activity(now);
+        }
+    }
+
+    /// Whether something needs attention and keeps the board active. Counts as activity
+    /// when it ends, so the idle and sleep times start over.
+    pub fn set_busy(&mut self, busy: bool, now: u64) {
+        if self.busy && !busy {
+            self.activity(now);
+        }
+        self.busy = busy;
+    }
+
+    pub fn mode(&self, now: u64) -> Mode {
+        if self.connected || self.busy {
+            return Mode::Active;
+        }
+        let idle = now.saturating_sub(self.last_activity);
+        let sleep_after = self.config.sleep_after_secs;
+        if sleep_after != 0 && idle >= sleep_after {
+            Mode::Sleep
+        } else if idle >= IDLE_AFTER_SECS {
+            Mode::Idle
+        } else {
+            Mode::Active
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Signal strength of the connection to the central and how close that puts it.
+//!
+//! RSSI samples jump around by several dB from one connection event to the next, so they
+//! are smoothed with an exponential moving average before being sorted into a coarse
+//! [`Proximity`]. The zone boundaries move [`HYSTERESIS_DB`] away from the current zone,
+//! so a phone sitting right at a boundary does not flip back and forth. The optional
+//! [`Leash`] goes off when the phone stays far away or the link is lost to a timeout.
+//! Times are milliseconds since boot.
+
+/// Size of the encoded proximity value.
+pub const PROXIMITY_LEN: usize = 3;
+
+/// Weight of a new sample in the average, 1 in `SMOOTHING`.
+pub const SMOOTHING: i32 = 4;
+
+/// Smoothed RSSI at and above which the central counts as immediate.
+pub const IMMEDIATE_DBM: i8 = -55;
+/// Smoothed RSSI at and above which the central counts as near, below is far.
+pub const NEAR_DBM: i8 = -75;
+/// How far past a boundary the RSSI has to go to change zones.
+pub const HYSTERESIS_DB: i8 = 3;
+
+/// How long the central has to stay far before the leash goes off.
+pub const LEASH_AFTER_MS: u64 = 10_000;
+
+/// Reported by controllers when the RSSI is not available.
+const RSSI_UNAVAILABLE: i8 = 127;
+
+/// Exponential moving average of RSSI samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Rssi {
+    /// In 1/16 dB, `None` before the first sample.
+    average: Option<i32>,
+}
+
+impl Rssi {
+    pub const fn new() -> Self {
+        Self { average: None }
+    }
+
+    /// Add a sample in dBm and return the new average. The first sample is taken as is,
+    /// unavailable readings are skipped.
+    pub fn update(&mut self, sample: i8) -> Option<i8> {
+        if sample != RSSI_UNAVAILABLE {
+            let sample = sample as i32 * 16;
+            self.average = Some(match self.average {
+                Some(average) => average + (sample - average) / SMOOTHING,
+                None => sample,
+            });
+        }
+        self.value()
+    }
+
+    /// The average in dBm, rounded.
+    pub fn value(&self) -> Option<i8> {
+        self.average
+            .map(|average| (average + 8).div_euclid(16) as i8)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum Proximity {
+    /// No RSSI yet.
+    #[default]
+    Unknown,
+    /// Within about a metre.
+    Immediate,
+    /// In the same room.
+    Near,
+    Far,
+}
+
+impl Proximity {
+    /// The zone for a smoothed `rssi`, staying in `current` until the RSSI is
+    /// [`HYSTERESIS_DB`] past its boundary.
+    pub fn classify(rssi: i8, current: Proximity) -> Self {
+        let (immediate, near) = match current {
+            Proximity::Unknown => (IMMEDIATE_DBM, NEAR_DBM),
+            Proximity::Immediate => (IMMEDIATE_DBM - HYSTERESIS_DB, NEAR_DBM - HYSTERESIS_DB),
+            Proximity::Near => (IMMEDIATE_DBM + HYSTERESIS_DB, NEAR_DBM - HYSTERESIS_DB),
+            Proximity::Far => (IMMEDIATE_DBM + HYSTERESIS_DB, NEAR_DBM + HYSTERESIS_DB),
+        };
+        if rssi >= immediate {
+            Proximity::Immediate
+        } else if rssi >= near {
+            Proximity::Near
+        } else {
+            Proximity::Far
+        }
+    }
+
+    pub fn code(self) -> u8 {
+        match self {
+            Proximity::Unknown => 0,
+            Proximity::Immediate => 1,
+            Proximity::Near => 2,
+            Proximity::Far => 3,
+        }
+    }
+
+    /// Short name for the display.
+    pub fn label(self) -> &'static str {
+        match self {
+            Proximity::Unknown => "?",
+            Proximity::Immediate => "immediate",
+            Proximity::Near => "near",
+            Proximity::Far => "far",
+        }
+    }
+}
+
+/// Smoothed RSSI (`0x7F` if unknown), proximity and whether the leash went off.
+pub fn encode(rssi: Option<i8>, proximity: Proximity, leash: bool) -> [u8; PROXIMITY_LEN] {
+    [rssi.unwrap_or(i8::MAX) as u8, proximity.code(), leash as u8]
+}
+
+/// Goes off when the central walks away: [`LEASH_AFTER_MS`] far, or the link lost to a
+/// supervision timeout. It stays tripped until the central is back close or the alarm is
+/// acknowledged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Leash {
+    far_since: Option<u64>,
+    tripped: bool,
+}
+
+impl Leash {
+    pub const fn new() -> Self {
+        Self {
+            far_since: None,
+            tripped: false,
+        }
+    }
+
+    /// The proximity of the central at `now`. Returns whether the leash is tripped.
+    pub fn update(&mut self, proximity: Proximity, now: u64) -> bool {
+        match proximity {
+            Proximity::Far => {
+                let since = *self.far_since.get_or_insert(now);
+                if now.saturating_sub(since) >= LEASH_AFTER_MS {
+                    self.tripped = true;
+                }
+            }
+            Proximity::Immediate | Proximity::Near => {
+                self.far_since = None;
+                self.tripped = false;
+            }
+            Proximity::Unknown => {}
+        }
+        self.tripped
+    }
+
+    /// The link timed out, the central is out of range.
+    pub fn lost(&mut self) {
+        self.far_since = None;
+        self.tripped = true;
+    }
+
+    /// Silence the alarm. It goes off again if the central stays far for another
+    /// [`LEASH_AFTER_MS`].
+    pub fn acknowledge(&mut self) {
+        self.far_since = None;
+        self.tripped = false;
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+}
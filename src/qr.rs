@@ -0,0 +1,118 @@
+//! Pairing QR code for the OLED.
+//!
+//! The code carries a `cowgatt://` URL with the device name, the BLE address and a nonce
+//! drawn at boot, so the onboarding app can pick the right board among many identical
+//! "COW GATT" devices and tell a fresh code from an old photo of the screen.
+
+use core::fmt::Write;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use qrcodegen_no_heap::{QrCode, QrCodeEcc, Version};
+
+/// Largest version (29x29 modules) that still fits the 64 px display at 2 px per module.
+pub const MAX_VERSION: u8 = 3;
+/// Light modules around the code. The spec asks for 4, but phone readers cope with 1
+/// and it is the difference between 2 px and 1 px modules.
+pub const QUIET_ZONE: u32 = 1;
+
+const BUFFER_LEN: usize = Version::new(MAX_VERSION).buffer_len();
+
+pub type Payload = heapless::String<64>;
+
+/// `cowgatt://p?n=<name>&a=<address>&k=<nonce>`, with the name percent-encoded, the
+/// address as 12 hex digits most significant byte first and the nonce as 8 hex digits.
+///
+/// `address` is in controller byte order, least significant byte first.
+pub fn pairing_payload(name: &str, address: &[u8; 6], nonce: u32) -> Option<Payload> {
+    let mut payload = Payload::new();
+    payload.push_str("cowgatt://p?n=").ok()?;
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            payload.push(byte as char).ok()?;
+        } else {
+            write!(payload, "%{:02X}", byte).ok()?;
+        }
+    }
+    payload.push_str("&a=").ok()?;
+    for byte in address.iter().rev() {
+        write!(payload, "{:02X}", byte).ok()?;
+    }
+    write!(payload, "&k={:08X}", nonce).ok()?;
+    Some(payload)
+}
+
+/// Where the modules of a drawn code are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Layout {
+    /// Top left pixel of the top left module.
+    pub top_left: Point,
+    /// Pixels per module side.
+    pub scale: u32,
+    /// Modules per side.
+    pub modules: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum QrError<E> {
+    /// The text does not fit [`MAX_VERSION`] or the available space.
+    TooLong,
+    Draw(E),
+}
+
+/// Draw `text` as dark modules on a lit square at `origin`, as large as fits in a
+/// `max_size` pixel square.
+pub fn draw<D>(
+    display: &mut D,
+    text: &str,
+    origin: Point,
+    max_size: u32,
+) -> Result<Layout, QrError<D::Error>>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let mut temp = [0; BUFFER_LEN];
+    let mut out = [0; BUFFER_LEN];
+    let qr = QrCode::encode_text(
+        text,
+        &mut temp,
+        &mut out,
+        QrCodeEcc::Low,
+        Version::MIN,
+        Version::new(MAX_VERSION),
+        None,
+        true,
+    )
+    .map_err(|_| QrError::TooLong)?;
+
+    let modules = qr.size() as u32;
+    let scale = max_size / (modules + 2 * QUIET_ZONE);
+    if scale == 0 {
+        return Err(QrError::TooLong);
+    }
+    let margin = (QUIET_ZONE * scale) as i32;
+    let layout = Layout {
+        top_left: origin + Point::new(margin, margin),
+        scale,
+        modules,
+    };
+
+    let outer = (modules + 2 * QUIET_ZONE) * scale;
+    Rectangle::new(origin, Size::new_equal(outer))
+        .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+        .draw(display)
+        .map_err(QrError::Draw)?;
+
+    let dark = PrimitiveStyle::with_fill(BinaryColor::Off);
+    for y in 0..modules {
+        for x in (0..modules).filter(|&x| qr.get_module(x as i32, y as i32)) {
+            let offset = Point::new((x * scale) as i32, (y * scale) as i32);
+            Rectangle::new(layout.top_left + offset, Size::new_equal(scale))
+                .into_styled(dark)
+                .draw(display)
+                .map_err(QrError::Draw)?;
+        }
+    }
+    Ok(layout)
+}
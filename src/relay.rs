@@ -0,0 +1,171 @@
+//! Relaying the status records of other COW GATT boards.
+//!
+//! A relay re-broadcasts every status record it hears, its own excepted, as a relay frame
+//! carrying the origin's address and a hop count, so a gateway out of range of some boards
+//! still hears all of them. Frames that went [`MAX_HOPS`] hops are not relayed further, and
+//! a record already relayed is skipped for [`DEDUP_SECS`] however it arrives, which stops
+//! frames from bouncing between relays. Times are seconds since boot.
+
+use crate::advertising::{ad_type, AdData, Status, COMPANY_ID, DISCOVERABLE, STATUS_LEN};
+use crate::scan::{ad_structures, Kind, Reading};
+
+/// First byte of a relay frame, told apart from the status record by the high bit.
+pub const FRAME_VERSION: u8 = 0x81;
+
+/// Version, origin address, hops and the status record.
+pub const FRAME_LEN: usize = 1 + 6 + 1 + STATUS_LEN;
+
+/// Most times a status record is relayed.
+pub const MAX_HOPS: u8 = 3;
+
+/// How long a relayed record is not relayed again. Unchanged records are therefore
+/// refreshed at this rate.
+pub const DEDUP_SECS: u32 = 60;
+
+/// Frames waiting to be broadcast; the oldest is dropped when more arrive.
+pub const QUEUE_LEN: usize = 4;
+
+/// A status record and where it came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Frame {
+    /// Address of the board the record is from, least significant byte first.
+    pub origin: [u8; 6],
+    /// 0 when heard from the origin itself.
+    pub hops: u8,
+    pub status: Status,
+}
+
+impl Frame {
+    pub fn encode(&self) -> [u8; FRAME_LEN] {
+        let mut out = [0u8; FRAME_LEN];
+        out[0] = FRAME_VERSION;
+        out[1..7].copy_from_slice(&self.origin);
+        out[7] = self.hops;
+        out[8..].copy_from_slice(&self.status.encode());
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != FRAME_LEN || data[0] != FRAME_VERSION {
+            return None;
+        }
+        let mut origin = [0u8; 6];
+        origin.copy_from_slice(&data[1..7]);
+        Some(Self {
+            origin,
+            hops: data[7],
+            status: Status::decode(&data[8..])?,
+        })
+    }
+
+    /// Advertising data with the frame as manufacturer data.
+    pub fn advertising_data(&self) -> AdData {
+        let mut data = AdData::new();
+        let _ = data.push(ad_type::FLAGS, &[&[DISCOVERABLE]]);
+        let _ = data.push(
+            ad_type::MANUFACTURER_SPECIFIC_DATA,
+            &[&COMPANY_ID.to_le_bytes(), &self.encode()],
+        );
+        data
+    }
+
+    /// The reading for the gateway, [`Kind::Cow`] when heard directly.
+    pub fn reading(&self) -> Reading {
+        let kind = if self.hops == 0 {
+            Kind::Cow
+        } else {
+            Kind::Relayed
+        };
+        Reading {
+            temperature: self.status.temperature,
+            battery: self.status.battery,
+            ..Reading::new(kind)
+        }
+    }
+}
+
+/// The status record or relay frame in advertising data heard from `address`.
+pub fn heard(data: &[u8], address: [u8; 6]) -> Option<Frame> {
+    ad_structures(data).find_map(|(ad, data)| {
+        if ad != ad_type::MANUFACTURER_SPECIFIC_DATA || data.len() < 2 {
+            return None;
+        }
+        if u16::from_le_bytes([data[0], data[1]]) != COMPANY_ID {
+            return None;
+        }
+        let data = &data[2..];
+        match Status::decode(data) {
+            Some(status) => Some(Frame {
+                origin: address,
+                hops: 0,
+                status,
+            }),
+            None => Frame::decode(data),
+        }
+    })
+}
+
+/// Decides what to relay. Remembers the last `N` records relayed.
+pub struct Relay<const N: usize> {
+    own: [u8; 6],
+    /// Origin, status and when it was relayed.
+    seen: heapless::Vec<([u8; 6], Status, u32), N>,
+    queue: heapless::Deque<Frame, QUEUE_LEN>,
+}
+
+impl<const N: usize> Relay<N> {
+    /// A relay for the board with address `own`.
+    pub const fn new(own: [u8; 6]) -> Self {
+        // `accept` replaces the oldest entry of a full cache, which an empty one doesn't have
+        const { assert!(N > 0, "the relay cache needs room for at least one record") };
+        Self {
+            own,
+            seen: heapless::Vec::new(),
+            queue: heapless::Deque::new(),
+        }
+    }
+
+    /// Queue `frame` for relaying unless it is our own, went too far or was relayed
+    /// recently. Returns whether it was queued.
+    pub fn accept(&mut self, frame: Frame, now: u32) -> bool {
+        if frame.origin == self.own || frame.hops >= MAX_HOPS {
+            return false;
+        }
+        self.seen
+            .retain(|&(_, _, at)| now.saturating_sub(at) < DEDUP_SECS);
+        let duplicate = self
+            .seen
+            .iter()
+            .any(|&(origin, status, _)| origin == frame.origin && status == frame.status);
+        if duplicate {
+            return false;
+        }
+        let entry = (frame.origin, frame.status, now);
+        if let Err(entry) = self.seen.push(entry) {
+            let oldest = self
+                .seen
+                .iter_mut()
+                .min_by_key(|&&mut (_, _, at)| at)
+                .expect("full, so not empty");
+            *oldest = entry;
+        }
+        let relayed = Frame {
+            hops: frame.hops + 1,
+            ..frame
+        };
+        if let Err(relayed) = self.queue.push_back(relayed) {
+            self.queue.pop_front();
+            let _ = self.queue.push_back(relayed);
+        }
+        true
+    }
+
+    /// The next frame to broadcast.
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.queue.pop_front()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
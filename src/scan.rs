@@ -0,0 +1,295 @@
+//! Readings from nearby BLE sensors.
+//!
+//! The scanner hands every advertising report to [`parse`], which understands unencrypted
+//! BTHome v2, the ATC1441 and pvvx formats of Xiaomi thermometers running custom firmware
+//! and the status record of other COW GATT boards. [`Neighbors`] keeps the latest reading
+//! per address for the gateway service and the display. Times are seconds since boot.
+
+use crate::advertising::{ad_type, Status, BTHOME_UUID, COMPANY_ID};
+
+/// How long a sensor that went quiet is kept.
+pub const MAX_AGE_SECS: u32 = 5 * 60;
+
+/// Size of the encoded [`Neighbor`].
+pub const NEIGHBOR_LEN: usize = 15;
+
+/// Environmental Sensing service UUID, used for the service data of the custom Xiaomi
+/// firmwares.
+const ESS_UUID: u16 = 0x181A;
+const ATC1441_LEN: usize = 13;
+const PVVX_LEN: usize = 15;
+
+/// BTHome device information bits.
+const BTHOME_ENCRYPTED: u8 = 1 << 0;
+const BTHOME_VERSION_SHIFT: u8 = 5;
+
+/// The AD structures in advertising data as `(type, data)`, up to the first malformed one.
+pub fn ad_structures(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = data;
+    core::iter::from_fn(move || {
+        let (&len, tail) = rest.split_first()?;
+        let len = len as usize;
+        // a zero length ends the significant part
+        if len == 0 || len > tail.len() {
+            return None;
+        }
+        let (structure, tail) = tail.split_at(len);
+        rest = tail;
+        Some((structure[0], &structure[1..]))
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Kind {
+    BtHome,
+    Atc1441,
+    Pvvx,
+    Cow,
+    /// Another COW GATT board, heard through a relay.
+    Relayed,
+}
+
+impl Kind {
+    pub fn code(self) -> u8 {
+        match self {
+            Kind::BtHome => 1,
+            Kind::Atc1441 => 2,
+            Kind::Pvvx => 3,
+            Kind::Cow => 4,
+            Kind::Relayed => 5,
+        }
+    }
+}
+
+/// What a sensor reported, in the units of our own readings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Reading {
+    pub kind: Kind,
+    /// Temperature in 0.01 °C.
+    pub temperature: Option<i16>,
+    /// Relative humidity in 0.01 %.
+    pub humidity: Option<u16>,
+    /// Pressure in 0.1 Pa.
+    pub pressure: Option<u32>,
+    /// Battery level in percent.
+    pub battery: Option<u8>,
+    /// Battery voltage in mV.
+    pub battery_mv: Option<u16>,
+}
+
+impl Reading {
+    pub const fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            temperature: None,
+            humidity: None,
+            pressure: None,
+            battery: None,
+            battery_mv: None,
+        }
+    }
+}
+
+/// Parse advertising data (or a scan response), `None` if it holds no known sensor format.
+pub fn parse(data: &[u8]) -> Option<Reading> {
+    ad_structures(data).find_map(|(ad, data)| match ad {
+        ad_type::SERVICE_DATA_16 if data.len() >= 2 => {
+            let uuid = u16::from_le_bytes([data[0], data[1]]);
+            match uuid {
+                BTHOME_UUID => parse_bthome(&data[2..]),
+                ESS_UUID => parse_custom_xiaomi(&data[2..]),
+                _ => None,
+            }
+        }
+        ad_type::MANUFACTURER_SPECIFIC_DATA if data.len() >= 2 => {
+            let company = u16::from_le_bytes([data[0], data[1]]);
+            if company == COMPANY_ID {
+                parse_cow(&data[2..])
+            } else {
+                None
+            }
+        }
+        _ => None,
+    })
+}
+
+/// Size of a BTHome v2 object's value, `None` for IDs we do not know. Variable length
+/// objects count their length byte.
+fn bthome_object_len(id: u8, value: &[u8]) -> Option<usize> {
+    let len = match id {
+        0x00 | 0x01 | 0x09 | 0x0F..=0x11 | 0x15..=0x2F | 0x3A | 0x46 | 0x57..=0x59 | 0x60 => 1,
+        0x02
+        | 0x03
+        | 0x06..=0x08
+        | 0x0C..=0x0E
+        | 0x12..=0x14
+        | 0x3C
+        | 0x3D
+        | 0x3F..=0x41
+        | 0x43..=0x45
+        | 0x47..=0x4A
+        | 0x51
+        | 0x52
+        | 0x56
+        | 0x5A
+        | 0x5D..=0x5F
+        | 0xF0 => 2,
+        0x04 | 0x05 | 0x0A | 0x0B | 0x42 | 0x4B | 0xF2 => 3,
+        0x3E | 0x4C..=0x50 | 0x55 | 0x5B | 0x5C | 0xF1 => 4,
+        0x53 | 0x54 => 1 + *value.first()? as usize,
+        _ => return None,
+    };
+    Some(len)
+}
+
+fn parse_bthome(data: &[u8]) -> Option<Reading> {
+    let (&info, mut objects) = data.split_first()?;
+    if info & BTHOME_ENCRYPTED != 0 || info >> BTHOME_VERSION_SHIFT != 2 {
+        return None;
+    }
+    let mut reading = Reading::new(Kind::BtHome);
+    // stop at the first unknown object, there is no way to tell its length
+    while let Some((&id, rest)) = objects.split_first() {
+        let Some(len) = bthome_object_len(id, rest).filter(|&len| len <= rest.len()) else {
+            break;
+        };
+        let (value, rest) = rest.split_at(len);
+        objects = rest;
+        let u16_le = || u16::from_le_bytes([value[0], value[1]]);
+        match id {
+            0x01 => reading.battery = Some(value[0]),
+            0x02 => reading.temperature = Some(u16_le() as i16),
+            0x03 => reading.humidity = Some(u16_le()),
+            // 0.01 hPa, i.e. Pa
+            0x04 => {
+                reading.pressure = Some(u32::from_le_bytes([value[0], value[1], value[2], 0]) * 10)
+            }
+            0x0C => reading.battery_mv = Some(u16_le()),
+            0x2E => reading.humidity = Some(value[0] as u16 * 100),
+            // 0.1 °C
+            0x45 => reading.temperature = Some((u16_le() as i16).saturating_mul(10)),
+            _ => {}
+        }
+    }
+    Some(reading)
+}
+
+/// ATC1441 (big-endian, 0.1 °C, whole percent) and pvvx (little-endian, hundredths)
+/// formats, told apart by their length. Both start with the sensor's MAC address.
+fn parse_custom_xiaomi(data: &[u8]) -> Option<Reading> {
+    match data.len() {
+        ATC1441_LEN => Some(Reading {
+            temperature: Some(i16::from_be_bytes([data[6], data[7]]).saturating_mul(10)),
+            humidity: Some(data[8] as u16 * 100),
+            battery: Some(data[9]),
+            battery_mv: Some(u16::from_be_bytes([data[10], data[11]])),
+            ..Reading::new(Kind::Atc1441)
+        }),
+        PVVX_LEN => Some(Reading {
+            temperature: Some(i16::from_le_bytes([data[6], data[7]])),
+            humidity: Some(u16::from_le_bytes([data[8], data[9]])),
+            battery_mv: Some(u16::from_le_bytes([data[10], data[11]])),
+            battery: Some(data[12]),
+            ..Reading::new(Kind::Pvvx)
+        }),
+        _ => None,
+    }
+}
+
+fn parse_cow(data: &[u8]) -> Option<Reading> {
+    let status = Status::decode(data)?;
+    Some(Reading {
+        temperature: status.temperature,
+        battery: status.battery,
+        ..Reading::new(Kind::Cow)
+    })
+}
+
+/// A sensor heard recently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Neighbor {
+    /// BLE address, least significant byte first as the controller reports it.
+    pub address: [u8; 6],
+    pub rssi: i8,
+    /// When it was last heard.
+    pub seen: u32,
+    pub reading: Reading,
+}
+
+impl Neighbor {
+    /// Address, kind, RSSI, seconds since last heard (u16), temperature (i16, `0x8000` if
+    /// unknown), humidity (u16, `0xFFFF` if unknown) and battery (`0xFF` if unknown),
+    /// little-endian.
+    pub fn encode(&self, now: u32) -> [u8; NEIGHBOR_LEN] {
+        let age = now.saturating_sub(self.seen).min(u16::MAX as u32) as u16;
+        let reading = &self.reading;
+        let mut out = [0u8; NEIGHBOR_LEN];
+        out[..6].copy_from_slice(&self.address);
+        out[6] = reading.kind.code();
+        out[7] = self.rssi as u8;
+        out[8..10].copy_from_slice(&age.to_le_bytes());
+        out[10..12].copy_from_slice(&reading.temperature.unwrap_or(i16::MIN).to_le_bytes());
+        out[12..14].copy_from_slice(&reading.humidity.unwrap_or(u16::MAX).to_le_bytes());
+        out[14] = reading.battery.unwrap_or(0xFF);
+        out
+    }
+}
+
+/// The latest reading of up to `N` sensors. When full, the one heard least recently makes
+/// room for a new one.
+pub struct Neighbors<const N: usize> {
+    entries: heapless::Vec<Neighbor, N>,
+}
+
+impl<const N: usize> Neighbors<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: heapless::Vec::new(),
+        }
+    }
+
+    /// Record a reading heard from `address`.
+    pub fn update(&mut self, address: [u8; 6], rssi: i8, reading: Reading, now: u32) {
+        let neighbor = Neighbor {
+            address,
+            rssi,
+            seen: now,
+            reading,
+        };
+        if let Some(entry) = self.entries.iter_mut().find(|n| n.address == address) {
+            *entry = neighbor;
+        } else if let Err(neighbor) = self.entries.push(neighbor) {
+            let oldest = self
+                .entries
+                .iter_mut()
+                .min_by_key(|n| n.seen)
+                .expect("full, so not empty");
+            *oldest = neighbor;
+        }
+    }
+
+    /// Forget sensors not heard for [`MAX_AGE_SECS`].
+    pub fn expire(&mut self, now: u32) {
+        self.entries
+            .retain(|n| now.saturating_sub(n.seen) < MAX_AGE_SECS);
+    }
+
+    /// The sensors heard, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Neighbor> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<const N: usize> Default for Neighbors<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
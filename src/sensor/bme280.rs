@@ -0,0 +1,198 @@
+//! Bosch BME280 temperature, humidity and pressure sensor.
+
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+use super::{Measurement, Metadata, Quantity, Readings, Sensor, SensorError};
+
+/// SDO pin low / high.
+pub const ADDRESSES: [u8; 2] = [0x76, 0x77];
+
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_CALIB_00: u8 = 0x88;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_DATA: u8 = 0xF7;
+
+const CHIP_ID: u8 = 0x60;
+/// Humidity oversampling x1.
+const CTRL_HUM: u8 = 0b001;
+/// Temperature and pressure oversampling x1 (`001` each), forced mode (`01`).
+const CTRL_MEAS_FORCED: u8 = 0b0010_0101;
+const STATUS_MEASURING: u8 = 1 << 3;
+/// Status reads 1 ms apart before a measurement is given up, well past the 9.3 ms maximum.
+const STATUS_POLLS: u8 = 20;
+
+const QUANTITIES: &[Quantity] = &[
+    Quantity::Temperature,
+    Quantity::Humidity,
+    Quantity::Pressure,
+];
+
+/// Factory trimming values, see section 4.2.2 of the datasheet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+impl Calibration {
+    /// Parse registers 0x88..=0xA1 and 0xE1..=0xE7.
+    pub fn parse(tp: &[u8; 26], h: &[u8; 7]) -> Self {
+        let u = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]);
+        Self {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p1: u(6),
+            p2: s(8),
+            p3: s(10),
+            p4: s(12),
+            p5: s(14),
+            p6: s(16),
+            p7: s(18),
+            p8: s(20),
+            p9: s(22),
+            h1: tp[25],
+            h2: i16::from_le_bytes([h[0], h[1]]),
+            h3: h[2],
+            h4: ((h[3] as i8 as i16) << 4) | (h[4] & 0x0F) as i16,
+            h5: ((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16,
+            h6: h[6] as i8,
+        }
+    }
+
+    /// Floating point compensation from section 8.1 of the datasheet.
+    /// Returns (°C, %RH, Pa).
+    pub fn compensate(&self, adc_t: i32, adc_p: i32, adc_h: i32) -> (f32, f32, f32) {
+        let (adc_t, adc_p, adc_h) = (adc_t as f64, adc_p as f64, adc_h as f64);
+
+        let var1 = (adc_t / 16384.0 - self.t1 as f64 / 1024.0) * self.t2 as f64;
+        let d = adc_t / 131072.0 - self.t1 as f64 / 8192.0;
+        let var2 = d * d * self.t3 as f64;
+        let t_fine = var1 + var2;
+        let temperature = t_fine / 5120.0;
+
+        let mut var1 = t_fine / 2.0 - 64000.0;
+        let mut var2 = var1 * var1 * self.p6 as f64 / 32768.0;
+        var2 += var1 * self.p5 as f64 * 2.0;
+        var2 = var2 / 4.0 + self.p4 as f64 * 65536.0;
+        var1 = (self.p3 as f64 * var1 * var1 / 524288.0 + self.p2 as f64 * var1) / 524288.0;
+        var1 = (1.0 + var1 / 32768.0) * self.p1 as f64;
+        let pressure = if var1 == 0.0 {
+            0.0
+        } else {
+            let mut p = 1048576.0 - adc_p;
+            p = (p - var2 / 4096.0) * 6250.0 / var1;
+            let var1 = self.p9 as f64 * p * p / 2147483648.0;
+            let var2 = p * self.p8 as f64 / 32768.0;
+            p + (var1 + var2 + self.p7 as f64) / 16.0
+        };
+
+        let mut h = t_fine - 76800.0;
+        h = (adc_h - (self.h4 as f64 * 64.0 + self.h5 as f64 / 16384.0 * h))
+            * (self.h2 as f64 / 65536.0
+                * (1.0
+                    + self.h6 as f64 / 67108864.0 * h * (1.0 + self.h3 as f64 / 67108864.0 * h)));
+        h *= 1.0 - self.h1 as f64 * h / 524288.0;
+        let humidity = h.clamp(0.0, 100.0);
+
+        (temperature as f32, humidity as f32, pressure as f32)
+    }
+}
+
+pub struct Bme280<I2C> {
+    i2c: I2C,
+    address: u8,
+    calibration: Calibration,
+}
+
+impl<I2C: I2c> Bme280<I2C> {
+    /// Check the chip ID and read the calibration data.
+    pub async fn probe(mut i2c: I2C, address: u8) -> Result<Self, SensorError<I2C::Error>> {
+        let mut id = [0u8];
+        i2c.write_read(address, &[REG_CHIP_ID], &mut id).await?;
+        if id[0] != CHIP_ID {
+            return Err(SensorError::NotDetected);
+        }
+        let mut tp = [0u8; 26];
+        i2c.write_read(address, &[REG_CALIB_00], &mut tp).await?;
+        let mut h = [0u8; 7];
+        i2c.write_read(address, &[REG_CALIB_26], &mut h).await?;
+        // ctrl_hum only takes effect after a write to ctrl_meas, which `sample` does
+        i2c.write(address, &[REG_CTRL_HUM, CTRL_HUM]).await?;
+        Ok(Self {
+            i2c,
+            address,
+            calibration: Calibration::parse(&tp, &h),
+        })
+    }
+}
+
+impl<I2C: I2c> Sensor for Bme280<I2C> {
+    type Error = SensorError<I2C::Error>;
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            name: "BME280",
+            quantities: QUANTITIES,
+            address: Some(self.address),
+            ambient: true,
+        }
+    }
+
+    async fn sample(&mut self) -> Result<Readings, Self::Error> {
+        self.i2c
+            .write(self.address, &[REG_CTRL_MEAS, CTRL_MEAS_FORCED])
+            .await?;
+        // typ. 8 ms with x1 oversampling on all channels
+        Timer::after_millis(10).await;
+        let mut status = [STATUS_MEASURING];
+        for _ in 0..STATUS_POLLS {
+            self.i2c
+                .write_read(self.address, &[REG_STATUS], &mut status)
+                .await?;
+            if status[0] & STATUS_MEASURING == 0 {
+                break;
+            }
+            Timer::after_millis(1).await;
+        }
+        if status[0] & STATUS_MEASURING != 0 {
+            return Err(SensorError::Timeout);
+        }
+
+        let mut data = [0u8; 8];
+        self.i2c
+            .write_read(self.address, &[REG_DATA], &mut data)
+            .await?;
+        let adc_p = ((data[0] as i32) << 12) | ((data[1] as i32) << 4) | (data[2] as i32 >> 4);
+        let adc_t = ((data[3] as i32) << 12) | ((data[4] as i32) << 4) | (data[5] as i32 >> 4);
+        let adc_h = ((data[6] as i32) << 8) | data[7] as i32;
+        let (t, rh, p) = self.calibration.compensate(adc_t, adc_p, adc_h);
+
+        let mut readings = Readings::new();
+        let _ = readings.push(Measurement::new(Quantity::Temperature, t));
+        let _ = readings.push(Measurement::new(Quantity::Humidity, rh));
+        let _ = readings.push(Measurement::new(Quantity::Pressure, p));
+        Ok(readings)
+    }
+}
@@ -0,0 +1,39 @@
+//! The chip's internal temperature sensor.
+
+use core::convert::Infallible;
+
+use esp_hal::tsens::TemperatureSensor;
+
+use super::{Measurement, Metadata, Quantity, Readings, Sensor};
+
+pub struct DieTemperature<'d> {
+    tsens: TemperatureSensor<'d>,
+}
+
+impl<'d> DieTemperature<'d> {
+    pub fn new(tsens: TemperatureSensor<'d>) -> Self {
+        // datasheet recommends 200 µs after power-up
+        esp_hal::delay::Delay::new().delay_micros(200);
+        Self { tsens }
+    }
+}
+
+impl Sensor for DieTemperature<'_> {
+    type Error = Infallible;
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            name: "die",
+            quantities: &[Quantity::Temperature],
+            address: None,
+            ambient: false,
+        }
+    }
+
+    async fn sample(&mut self) -> Result<Readings, Self::Error> {
+        let c = self.tsens.get_temperature().to_celsius();
+        let mut readings = Readings::new();
+        let _ = readings.push(Measurement::new(Quantity::Temperature, c));
+        Ok(readings)
+    }
+}
@@ -0,0 +1,100 @@
+//! Sensor and I2C bus stand-ins for tests.
+
+use core::convert::Infallible;
+
+use embedded_hal_async::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+use super::{Measurement, Metadata, Quantity, Readings, Sensor};
+
+/// Replays a fixed list of samples, repeating the last one.
+pub struct MockSensor {
+    metadata: Metadata,
+    samples: &'static [&'static [Measurement]],
+    next: usize,
+}
+
+impl MockSensor {
+    pub fn new(metadata: Metadata, samples: &'static [&'static [Measurement]]) -> Self {
+        Self {
+            metadata,
+            samples,
+            next: 0,
+        }
+    }
+
+    /// An ambient temperature sensor replaying `samples`.
+    pub fn temperature(samples: &'static [&'static [Measurement]]) -> Self {
+        Self::new(
+            Metadata {
+                name: "mock",
+                quantities: &[Quantity::Temperature],
+                address: None,
+                ambient: true,
+            },
+            samples,
+        )
+    }
+}
+
+impl Sensor for MockSensor {
+    type Error = Infallible;
+
+    fn metadata(&self) -> Metadata {
+        self.metadata
+    }
+
+    async fn sample(&mut self) -> Result<Readings, Self::Error> {
+        let index = self.next.min(self.samples.len().saturating_sub(1));
+        self.next += 1;
+        let sample = self.samples.get(index).copied().unwrap_or(&[]);
+        Ok(Readings::from_slice(sample).unwrap_or_default())
+    }
+}
+
+/// An I2C bus with a single device at `address` that answers every read with `reply`
+/// and remembers the last bytes written to it.
+pub struct MockI2c<'a> {
+    pub address: u8,
+    pub reply: &'a [u8],
+    pub written: heapless::Vec<u8, 16>,
+}
+
+impl<'a> MockI2c<'a> {
+    pub fn new(address: u8, reply: &'a [u8]) -> Self {
+        Self {
+            address,
+            reply,
+            written: heapless::Vec::new(),
+        }
+    }
+}
+
+impl ErrorType for MockI2c<'_> {
+    type Error = ErrorKind;
+}
+
+impl I2c for MockI2c<'_> {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if address != self.address {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+        for op in operations {
+            match op {
+                Operation::Write(bytes) => {
+                    self.written.clear();
+                    let _ = self.written.extend_from_slice(bytes);
+                }
+                Operation::Read(buffer) => {
+                    for (i, byte) in buffer.iter_mut().enumerate() {
+                        *byte = self.reply.get(i).copied().unwrap_or(0xFF);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
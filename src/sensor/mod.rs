@@ -0,0 +1,194 @@
+//! Environmental sensors.
+//!
+//! Every sensor implements [`Sensor`], so the sampling task does not care whether a reading
+//! comes from the chip's internal die sensor or an external I2C part. External sensors share
+//! the I2C0 bus with the OLED and are found by [`detect`] at boot.
+
+pub mod bme280;
+pub mod die;
+pub mod mock;
+pub mod sht;
+
+use embedded_hal_async::i2c::I2c;
+
+use bme280::Bme280;
+use sht::{Sht3x, Sht4x};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Quantity {
+    /// Degrees Celsius
+    Temperature,
+    /// Percent relative humidity
+    Humidity,
+    /// Pascal
+    Pressure,
+}
+
+impl Quantity {
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Quantity::Temperature => "°C",
+            Quantity::Humidity => "%RH",
+            Quantity::Pressure => "Pa",
+        }
+    }
+
+    /// Bit of the quantity in a [`Metadata::record`].
+    pub fn flag(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Measurement {
+    pub quantity: Quantity,
+    pub value: f32,
+}
+
+impl Measurement {
+    pub fn new(quantity: Quantity, value: f32) -> Self {
+        Self { quantity, value }
+    }
+}
+
+/// All measurements of one sample, at most one per [`Quantity`].
+pub type Readings = heapless::Vec<Measurement, 3>;
+
+/// Find the measurement of `quantity` in `readings`.
+pub fn find(readings: &Readings, quantity: Quantity) -> Option<f32> {
+    readings
+        .iter()
+        .find(|m| m.quantity == quantity)
+        .map(|m| m.value)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Metadata {
+    pub name: &'static str,
+    pub quantities: &'static [Quantity],
+    /// I2C address, `None` for on-chip sensors.
+    pub address: Option<u8>,
+    /// Whether the sensor measures its surroundings rather than the chip itself.
+    pub ambient: bool,
+}
+
+/// Length of a [`Metadata::record`].
+pub const RECORD_LEN: usize = 3;
+
+impl Metadata {
+    /// The sensor as listed in the sensor registry: I2C address (`0` on-chip), the
+    /// [`Quantity::flag`]s it measures, and `1` if it measures its surroundings.
+    pub fn record(&self) -> [u8; RECORD_LEN] {
+        let flags = self.quantities.iter().fold(0, |flags, q| flags | q.flag());
+        [self.address.unwrap_or(0), flags, self.ambient as u8]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SensorError<E> {
+    I2c(E),
+    /// A checksum in the sensor's reply did not match.
+    Crc,
+    /// The device at the address is not the expected sensor.
+    NotDetected,
+    /// The sensor did not finish a measurement in time.
+    Timeout,
+}
+
+impl<E> From<E> for SensorError<E> {
+    fn from(e: E) -> Self {
+        SensorError::I2c(e)
+    }
+}
+
+#[allow(
+    async_fn_in_trait,
+    reason = "only used with the single-threaded embassy executor"
+)]
+pub trait Sensor {
+    type Error;
+
+    fn metadata(&self) -> Metadata;
+
+    /// Take one measurement of every quantity the sensor supports.
+    async fn sample(&mut self) -> Result<Readings, Self::Error>;
+}
+
+/// Any of the supported external I2C sensors.
+pub enum AnySensor<I2C> {
+    Sht3x(Sht3x<I2C>),
+    Sht4x(Sht4x<I2C>),
+    Bme280(Bme280<I2C>),
+}
+
+impl<I2C: I2c> Sensor for AnySensor<I2C> {
+    type Error = SensorError<I2C::Error>;
+
+    fn metadata(&self) -> Metadata {
+        match self {
+            AnySensor::Sht3x(s) => s.metadata(),
+            AnySensor::Sht4x(s) => s.metadata(),
+            AnySensor::Bme280(s) => s.metadata(),
+        }
+    }
+
+    async fn sample(&mut self) -> Result<Readings, Self::Error> {
+        match self {
+            AnySensor::Sht3x(s) => s.sample().await,
+            AnySensor::Sht4x(s) => s.sample().await,
+            AnySensor::Bme280(s) => s.sample().await,
+        }
+    }
+}
+
+/// Maximum number of external sensors [`detect`] returns.
+pub const MAX_SENSORS: usize = 4;
+
+/// Probe the known sensor addresses. `device` is called for a fresh bus handle per probe.
+pub async fn detect<I2C: I2c>(
+    mut device: impl FnMut() -> I2C,
+) -> heapless::Vec<AnySensor<I2C>, MAX_SENSORS> {
+    let mut found = heapless::Vec::new();
+    for address in sht::ADDRESSES {
+        // both families live at 0x44, the SHT4x serial number command tells them apart
+        let sensor = match Sht4x::probe(device(), address).await {
+            Ok(sensor) => Some(AnySensor::Sht4x(sensor)),
+            Err(_) => Sht3x::probe(device(), address)
+                .await
+                .ok()
+                .map(AnySensor::Sht3x),
+        };
+        if let Some(sensor) = sensor {
+            let _ = found.push(sensor);
+        }
+    }
+    for address in bme280::ADDRESSES {
+        if let Ok(sensor) = Bme280::probe(device(), address).await {
+            let _ = found.push(AnySensor::Bme280(sensor));
+        }
+    }
+    found
+}
+
+/// The sensors in use, the detected external ones or else the die sensor.
+pub type Registry = heapless::Vec<Metadata, MAX_SENSORS>;
+
+/// Length of the sensor registry characteristic, one [`Metadata::record`] per sensor.
+pub const REGISTRY_LEN: usize = RECORD_LEN * MAX_SENSORS;
+
+/// Whether any of `sensors` measures `quantity`.
+pub fn measures(sensors: &[Metadata], quantity: Quantity) -> bool {
+    sensors.iter().any(|s| s.quantities.contains(&quantity))
+}
+
+/// Names of `sensors`, comma separated.
+pub fn names(sensors: &[Metadata]) -> heapless::String<32> {
+    let mut names = heapless::String::new();
+    for s in sensors {
+        if !names.is_empty() {
+            let _ = names.push(',');
+        }
+        let _ = names.push_str(s.name);
+    }
+    names
+}
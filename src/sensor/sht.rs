@@ -0,0 +1,145 @@
+//! Sensirion SHT3x and SHT4x temperature/humidity sensors.
+
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+use super::{Measurement, Metadata, Quantity, Readings, Sensor, SensorError};
+
+/// Default and alternate address (ADDR pin high on SHT3x, `-B` variants of SHT4x).
+pub const ADDRESSES: [u8; 2] = [0x44, 0x45];
+
+const QUANTITIES: &[Quantity] = &[Quantity::Temperature, Quantity::Humidity];
+
+/// SHT3x single shot, high repeatability, no clock stretching.
+const SHT3X_MEASURE: [u8; 2] = [0x24, 0x00];
+const SHT3X_READ_STATUS: [u8; 2] = [0xF3, 0x2D];
+/// SHT4x measure T & RH with high precision.
+const SHT4X_MEASURE: u8 = 0xFD;
+const SHT4X_READ_SERIAL: u8 = 0x89;
+
+/// CRC-8 used by Sensirion sensors: polynomial 0x31, init 0xFF.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xFFu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x31
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Split a reply of 16-bit words each followed by its CRC, checking every CRC.
+fn words<const N: usize, E>(reply: &[u8]) -> Result<[u16; N], SensorError<E>> {
+    let mut out = [0u16; N];
+    for (word, chunk) in out.iter_mut().zip(reply.chunks_exact(3)) {
+        if crc8(&chunk[..2]) != chunk[2] {
+            return Err(SensorError::Crc);
+        }
+        *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+    Ok(out)
+}
+
+fn temperature(raw: u16) -> f32 {
+    -45.0 + 175.0 * raw as f32 / 65535.0
+}
+
+pub struct Sht3x<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> Sht3x<I2C> {
+    /// Check for an SHT3x by reading its status register.
+    pub async fn probe(mut i2c: I2C, address: u8) -> Result<Self, SensorError<I2C::Error>> {
+        let mut status = [0u8; 3];
+        i2c.write_read(address, &SHT3X_READ_STATUS, &mut status)
+            .await?;
+        words::<1, I2C::Error>(&status).map_err(|_| SensorError::<I2C::Error>::NotDetected)?;
+        Ok(Self { i2c, address })
+    }
+
+    pub fn humidity(raw: u16) -> f32 {
+        100.0 * raw as f32 / 65535.0
+    }
+}
+
+impl<I2C: I2c> Sensor for Sht3x<I2C> {
+    type Error = SensorError<I2C::Error>;
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            name: "SHT3x",
+            quantities: QUANTITIES,
+            address: Some(self.address),
+            ambient: true,
+        }
+    }
+
+    async fn sample(&mut self) -> Result<Readings, Self::Error> {
+        self.i2c.write(self.address, &SHT3X_MEASURE).await?;
+        // max. measurement duration for high repeatability
+        Timer::after_millis(16).await;
+        let mut reply = [0u8; 6];
+        self.i2c.read(self.address, &mut reply).await?;
+        let [t, rh] = words::<2, I2C::Error>(&reply)?;
+
+        let mut readings = Readings::new();
+        let _ = readings.push(Measurement::new(Quantity::Temperature, temperature(t)));
+        let _ = readings.push(Measurement::new(Quantity::Humidity, Self::humidity(rh)));
+        Ok(readings)
+    }
+}
+
+pub struct Sht4x<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+impl<I2C: I2c> Sht4x<I2C> {
+    /// Check for an SHT4x by reading its serial number.
+    pub async fn probe(mut i2c: I2C, address: u8) -> Result<Self, SensorError<I2C::Error>> {
+        i2c.write(address, &[SHT4X_READ_SERIAL]).await?;
+        Timer::after_millis(1).await;
+        let mut serial = [0u8; 6];
+        i2c.read(address, &mut serial).await?;
+        words::<2, I2C::Error>(&serial).map_err(|_| SensorError::<I2C::Error>::NotDetected)?;
+        Ok(Self { i2c, address })
+    }
+
+    pub fn humidity(raw: u16) -> f32 {
+        (-6.0 + 125.0 * raw as f32 / 65535.0).clamp(0.0, 100.0)
+    }
+}
+
+impl<I2C: I2c> Sensor for Sht4x<I2C> {
+    type Error = SensorError<I2C::Error>;
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            name: "SHT4x",
+            quantities: QUANTITIES,
+            address: Some(self.address),
+            ambient: true,
+        }
+    }
+
+    async fn sample(&mut self) -> Result<Readings, Self::Error> {
+        self.i2c.write(self.address, &[SHT4X_MEASURE]).await?;
+        // max. measurement duration for high precision
+        Timer::after_millis(10).await;
+        let mut reply = [0u8; 6];
+        self.i2c.read(self.address, &mut reply).await?;
+        let [t, rh] = words::<2, I2C::Error>(&reply)?;
+
+        let mut readings = Readings::new();
+        let _ = readings.push(Measurement::new(Quantity::Temperature, temperature(t)));
+        let _ = readings.push(Measurement::new(Quantity::Humidity, Self::humidity(rh)));
+        Ok(readings)
+    }
+}
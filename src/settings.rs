@@ -0,0 +1,356 @@
+//! User settings persisted in flash.
+//!
+//! Settings are stored as one small record at [`FLASH_OFFSET`]: a magic, a version, the
+//! encoded fields and a CRC. A blank, foreign or corrupted record reads as `None` and the
+//! caller falls back to the defaults.
+
+use embedded_storage::{ReadStorage, Storage};
+
+/// Start of the `nvs` partition in the default ESP-IDF partition table. We do not use the
+/// ESP-IDF NVS format, the partition is just a place the application image never covers.
+pub const FLASH_OFFSET: u32 = 0x9000;
+
+const MAGIC: [u8; 2] = *b"CS";
+const VERSION: u8 = 2;
+/// Version 1 records only had the display settings, they are still read.
+const VERSION_1: u8 = 1;
+
+pub const DISPLAY_SETTINGS_LEN: usize = 7;
+pub const ADVERTISING_SETTINGS_LEN: usize = 8;
+/// Magic, version, display settings, advertising settings and CRC.
+pub const RECORD_LEN: usize = MAGIC.len() + 1 + DISPLAY_SETTINGS_LEN + ADVERTISING_SETTINGS_LEN + 1;
+const RECORD_1_LEN: usize = MAGIC.len() + 1 + DISPLAY_SETTINGS_LEN + 1;
+
+const FLAG_ON_WHILE_CONNECTED: u8 = 1 << 0;
+const FLAG_PIXEL_SHIFT: u8 = 1 << 1;
+
+const FIELD_NAME: u8 = 1 << 0;
+const FIELD_SERVICE_UUID: u8 = 1 << 1;
+const FIELD_STATUS: u8 = 1 << 2;
+/// The beacon format shares the fields byte, in bits 3 and 4, and so do the BLE 5 options.
+const BEACON_SHIFT: u8 = 3;
+const BEACON_MASK: u8 = 0b11 << BEACON_SHIFT;
+const OPTION_EXTENDED: u8 = 1 << 5;
+const OPTION_LONG_RANGE: u8 = 1 << 6;
+const OPTION_CONNECTION_2M: u8 = 1 << 7;
+
+/// Advertising interval range allowed by the Core spec for legacy advertising.
+pub const INTERVAL_MIN_MS: u16 = 20;
+pub const INTERVAL_MAX_MS: u16 = 10_240;
+/// TX power range we accept, the controller picks the closest level it supports.
+pub const TX_POWER_MIN_DBM: i8 = -40;
+pub const TX_POWER_MAX_DBM: i8 = 20;
+
+/// OLED power policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct DisplaySettings {
+    /// SSD1306 contrast while on.
+    pub contrast: u8,
+    /// SSD1306 contrast while dimmed.
+    pub dim_contrast: u8,
+    /// Seconds without activity before dimming, 0 to never dim.
+    pub dim_after_secs: u16,
+    /// Seconds without activity before switching off, 0 to never switch off.
+    pub off_after_secs: u16,
+    /// Stay on while a central is connected.
+    pub on_while_connected: bool,
+    /// Move the cow around by a few pixels against burn-in.
+    pub pixel_shift: bool,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            contrast: 0x5F,
+            dim_contrast: 0x00,
+            dim_after_secs: 60,
+            off_after_secs: 10 * 60,
+            on_while_connected: true,
+            pixel_shift: true,
+        }
+    }
+}
+
+impl DisplaySettings {
+    /// Contrast, dimmed contrast, dim and off timeouts (u16) and flags, little-endian.
+    pub fn encode(&self) -> [u8; DISPLAY_SETTINGS_LEN] {
+        let dim = self.dim_after_secs.to_le_bytes();
+        let off = self.off_after_secs.to_le_bytes();
+        let mut flags = 0;
+        if self.on_while_connected {
+            flags |= FLAG_ON_WHILE_CONNECTED;
+        }
+        if self.pixel_shift {
+            flags |= FLAG_PIXEL_SHIFT;
+        }
+        [
+            self.contrast,
+            self.dim_contrast,
+            dim[0],
+            dim[1],
+            off[0],
+            off[1],
+            flags,
+        ]
+    }
+
+    /// Parse settings written by a client. Rejects unknown flags and a dim timeout that
+    /// would only kick in after the display is already off.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; DISPLAY_SETTINGS_LEN] = data.try_into().ok()?;
+        let flags = data[6];
+        if flags & !(FLAG_ON_WHILE_CONNECTED | FLAG_PIXEL_SHIFT) != 0 {
+            return None;
+        }
+        let settings = Self {
+            contrast: data[0],
+            dim_contrast: data[1],
+            dim_after_secs: u16::from_le_bytes([data[2], data[3]]),
+            off_after_secs: u16::from_le_bytes([data[4], data[5]]),
+            on_while_connected: flags & FLAG_ON_WHILE_CONNECTED != 0,
+            pixel_shift: flags & FLAG_PIXEL_SHIFT != 0,
+        };
+        let dims_too_late = settings.off_after_secs != 0
+            && settings.dim_after_secs != 0
+            && settings.dim_after_secs >= settings.off_after_secs;
+        (!dims_too_late).then_some(settings)
+    }
+}
+
+/// What goes into the advertising packets, see [`crate::advertising`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct AdvertisedFields {
+    /// Device name in the scan response.
+    pub name: bool,
+    /// 128-bit service UUID, for apps filtering scans by service.
+    pub service_uuid: bool,
+    /// Status record in the manufacturer specific data.
+    pub status: bool,
+}
+
+impl Default for AdvertisedFields {
+    fn default() -> Self {
+        Self {
+            name: true,
+            service_uuid: true,
+            status: true,
+        }
+    }
+}
+
+/// Connectionless broadcast sent between the connectable advertisements, see
+/// [`crate::advertising::bthome`] and [`crate::advertising::eddystone_tlm`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum Beacon {
+    #[default]
+    Off,
+    /// BTHome v2, picked up by Home Assistant.
+    BtHome,
+    /// Eddystone telemetry.
+    EddystoneTlm,
+}
+
+impl Beacon {
+    fn code(self) -> u8 {
+        match self {
+            Beacon::Off => 0,
+            Beacon::BtHome => 1,
+            Beacon::EddystoneTlm => 2,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Beacon::Off),
+            1 => Some(Beacon::BtHome),
+            2 => Some(Beacon::EddystoneTlm),
+            _ => None,
+        }
+    }
+}
+
+/// BLE advertising parameters and payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct AdvertisingSettings {
+    /// Shortest advertising interval.
+    pub interval_min_ms: u16,
+    /// Longest advertising interval, the controller picks one in between.
+    pub interval_max_ms: u16,
+    /// Requested TX power.
+    pub tx_power_dbm: i8,
+    /// Seconds to advertise before pausing until the button is pressed, 0 to never pause.
+    pub timeout_secs: u16,
+    pub fields: AdvertisedFields,
+    pub beacon: Beacon,
+    /// Use BLE 5 extended advertising, with everything in one packet and the beacon in a
+    /// second advertising set. Needs the `extended-advertising` feature.
+    pub extended: bool,
+    /// Advertise on the Coded PHY for long range, extended advertising only.
+    pub long_range: bool,
+    /// Ask for the 2M PHY once a central connects, for faster history downloads.
+    pub connection_2m: bool,
+}
+
+impl Default for AdvertisingSettings {
+    fn default() -> Self {
+        Self {
+            interval_min_ms: 160,
+            interval_max_ms: 160,
+            tx_power_dbm: 0,
+            timeout_secs: 0,
+            fields: AdvertisedFields::default(),
+            beacon: Beacon::Off,
+            extended: false,
+            long_range: false,
+            connection_2m: false,
+        }
+    }
+}
+
+impl AdvertisingSettings {
+    /// Interval min and max (u16), TX power (i8), timeout (u16) and field flags with the
+    /// beacon format and BLE 5 options, little-endian.
+    pub fn encode(&self) -> [u8; ADVERTISING_SETTINGS_LEN] {
+        let min = self.interval_min_ms.to_le_bytes();
+        let max = self.interval_max_ms.to_le_bytes();
+        let timeout = self.timeout_secs.to_le_bytes();
+        let mut fields = 0;
+        if self.fields.name {
+            fields |= FIELD_NAME;
+        }
+        if self.fields.service_uuid {
+            fields |= FIELD_SERVICE_UUID;
+        }
+        if self.fields.status {
+            fields |= FIELD_STATUS;
+        }
+        fields |= self.beacon.code() << BEACON_SHIFT;
+        if self.extended {
+            fields |= OPTION_EXTENDED;
+        }
+        if self.long_range {
+            fields |= OPTION_LONG_RANGE;
+        }
+        if self.connection_2m {
+            fields |= OPTION_CONNECTION_2M;
+        }
+        [
+            min[0],
+            min[1],
+            max[0],
+            max[1],
+            self.tx_power_dbm as u8,
+            timeout[0],
+            timeout[1],
+            fields,
+        ]
+    }
+
+    /// Parse settings written by a client. Rejects unknown beacons, long range without
+    /// extended advertising, intervals outside of what the spec allows or the wrong way
+    /// round, and TX powers no controller has.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data: &[u8; ADVERTISING_SETTINGS_LEN] = data.try_into().ok()?;
+        let fields = data[7];
+        if fields & OPTION_LONG_RANGE != 0 && fields & OPTION_EXTENDED == 0 {
+            return None;
+        }
+        let settings = Self {
+            interval_min_ms: u16::from_le_bytes([data[0], data[1]]),
+            interval_max_ms: u16::from_le_bytes([data[2], data[3]]),
+            tx_power_dbm: data[4] as i8,
+            timeout_secs: u16::from_le_bytes([data[5], data[6]]),
+            fields: AdvertisedFields {
+                name: fields & FIELD_NAME != 0,
+                service_uuid: fields & FIELD_SERVICE_UUID != 0,
+                status: fields & FIELD_STATUS != 0,
+            },
+            beacon: Beacon::from_code((fields & BEACON_MASK) >> BEACON_SHIFT)?,
+            extended: fields & OPTION_EXTENDED != 0,
+            long_range: fields & OPTION_LONG_RANGE != 0,
+            connection_2m: fields & OPTION_CONNECTION_2M != 0,
+        };
+        let intervals = INTERVAL_MIN_MS <= settings.interval_min_ms
+            && settings.interval_min_ms <= settings.interval_max_ms
+            && settings.interval_max_ms <= INTERVAL_MAX_MS;
+        let tx_power = (TX_POWER_MIN_DBM..=TX_POWER_MAX_DBM).contains(&settings.tx_power_dbm);
+        (intervals && tx_power).then_some(settings)
+    }
+}
+
+/// Everything that survives a reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Settings {
+    pub display: DisplaySettings,
+    pub advertising: AdvertisingSettings,
+}
+
+impl Settings {
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[..2].copy_from_slice(&MAGIC);
+        record[2] = VERSION;
+        let display = 3..3 + DISPLAY_SETTINGS_LEN;
+        record[display.clone()].copy_from_slice(&self.display.encode());
+        record[display.end..display.end + ADVERTISING_SETTINGS_LEN]
+            .copy_from_slice(&self.advertising.encode());
+        record[RECORD_LEN - 1] = crc8(&record[..RECORD_LEN - 1]);
+        record
+    }
+
+    /// Parse a stored record. A version 1 record gets the default advertising settings.
+    pub fn decode(record: &[u8; RECORD_LEN]) -> Option<Self> {
+        let len = match record[2] {
+            VERSION => RECORD_LEN,
+            VERSION_1 => RECORD_1_LEN,
+            _ => return None,
+        };
+        if record[..2] != MAGIC || crc8(&record[..len - 1]) != record[len - 1] {
+            return None;
+        }
+        let display = 3..3 + DISPLAY_SETTINGS_LEN;
+        let advertising = match record[2] {
+            VERSION => AdvertisingSettings::decode(
+                &record[display.end..display.end + ADVERTISING_SETTINGS_LEN],
+            )?,
+            _ => AdvertisingSettings::default(),
+        };
+        Some(Self {
+            display: DisplaySettings::decode(&record[display])?,
+            advertising,
+        })
+    }
+}
+
+/// Read the settings record, `None` if there is no valid one.
+pub fn load<S: ReadStorage>(storage: &mut S) -> Option<Settings> {
+    let mut record = [0; RECORD_LEN];
+    storage.read(FLASH_OFFSET, &mut record).ok()?;
+    Settings::decode(&record)
+}
+
+/// Write the settings record.
+pub fn store<S: Storage>(storage: &mut S, settings: &Settings) -> Result<(), S::Error> {
+    storage.write(FLASH_OFFSET, &settings.encode())
+}
+
+/// Overwrite the record with erased flash, so the next boot is a first boot again.
+pub fn erase<S: Storage>(storage: &mut S) -> Result<(), S::Error> {
+    storage.write(FLASH_OFFSET, &[0xFF; RECORD_LEN])
+}
+
+/// CRC-8, polynomial 0x07 (CRC-8/SMBUS).
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
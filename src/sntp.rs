@@ -0,0 +1,59 @@
+//! Minimal SNTP (RFC 4330) client packet encoding.
+//!
+//! Only the parts needed to set the wall clock are implemented: a client request and
+//! the transmit timestamp of the server reply.
+
+pub const NTP_PORT: u16 = 123;
+pub const PACKET_LEN: usize = 48;
+
+/// Seconds between the NTP era 0 epoch (1900-01-01) and the Unix epoch.
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+const LI_VN_MODE_CLIENT: u8 = (4 << 3) | 3; // no leap warning, version 4, mode 3 (client)
+const MODE_SERVER: u8 = 4;
+const MODE_BROADCAST: u8 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SntpError {
+    TooShort,
+    NotAServerReply,
+    /// Kiss-o'-death or unsynchronized server (stratum 0).
+    Unsynchronized,
+    ZeroTimestamp,
+}
+
+/// Build a client request into `buf`.
+pub fn encode_request(buf: &mut [u8; PACKET_LEN]) {
+    buf.fill(0);
+    buf[0] = LI_VN_MODE_CLIENT;
+}
+
+/// Parse a server reply and return its transmit timestamp as Unix milliseconds.
+pub fn decode_reply(buf: &[u8]) -> Result<u64, SntpError> {
+    if buf.len() < PACKET_LEN {
+        return Err(SntpError::TooShort);
+    }
+    let mode = buf[0] & 0x07;
+    if mode != MODE_SERVER && mode != MODE_BROADCAST {
+        return Err(SntpError::NotAServerReply);
+    }
+    let leap = buf[0] >> 6;
+    let stratum = buf[1];
+    if stratum == 0 || leap == 3 {
+        return Err(SntpError::Unsynchronized);
+    }
+
+    let secs = u32::from_be_bytes([buf[40], buf[41], buf[42], buf[43]]) as u64;
+    let fraction = u32::from_be_bytes([buf[44], buf[45], buf[46], buf[47]]) as u64;
+    if secs == 0 && fraction == 0 {
+        return Err(SntpError::ZeroTimestamp);
+    }
+    // After 2036 the 32-bit seconds counter wraps into era 1; values below the Unix
+    // offset can only mean that, since we never run before 1970.
+    let secs = if secs < NTP_UNIX_OFFSET_SECS {
+        secs + (1 << 32)
+    } else {
+        secs
+    };
+    Ok((secs - NTP_UNIX_OFFSET_SECS) * 1000 + ((fraction * 1000) >> 32))
+}
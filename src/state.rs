@@ -0,0 +1,141 @@
+//! State shared between tasks.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_sync::watch::Watch;
+
+use crate::alarm::{AlarmState, Thresholds};
+use crate::battery::Battery;
+use crate::button::Gesture;
+use crate::history::{History, CAPACITY, INTERVAL_SECS};
+use crate::i2c_bus::ScanResult;
+use crate::link::Link;
+use crate::peer::PeerValue;
+use crate::power::Mode;
+use crate::proximity::Leash;
+use crate::relay::Relay;
+use crate::scan::Neighbors;
+use crate::sensor::Registry;
+use crate::settings::Settings;
+use crate::stream::Stream;
+
+/// Temperature history filled by `temp_task` and downloaded over GATT.
+pub static TEMPERATURE_HISTORY: Mutex<CriticalSectionRawMutex, RefCell<History<CAPACITY>>> =
+    Mutex::new(RefCell::new(History::new(INTERVAL_SECS)));
+
+/// Most sensors the gateway keeps track of.
+pub const NEIGHBORS_MAX: usize = 8;
+
+/// Nearby sensors heard by the BLE scanner, republished over GATT and on the display.
+pub static NEIGHBORS: Mutex<CriticalSectionRawMutex, RefCell<Neighbors<NEIGHBORS_MAX>>> =
+    Mutex::new(RefCell::new(Neighbors::new()));
+
+/// Most status records the relay remembers to skip duplicates.
+pub const RELAY_SEEN_MAX: usize = 16;
+
+/// Status records of other boards to re-broadcast, filled by the scanner in relay mode. The
+/// BLE task sets our own address at startup.
+pub static RELAY: Mutex<CriticalSectionRawMutex, RefCell<Relay<RELAY_SEEN_MAX>>> =
+    Mutex::new(RefCell::new(Relay::new([0; 6])));
+
+/// Signalled when the relay queued a frame.
+pub static RELAY_PENDING: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Most peripherals the central connects to.
+pub const PEERS_MAX: usize = 2;
+
+/// Latest values of the configured peripherals, in the order of the `PEERS` list.
+pub static PEER_VALUES: Mutex<CriticalSectionRawMutex, RefCell<[PeerValue; PEERS_MAX]>> =
+    Mutex::new(RefCell::new([const { PeerValue::new() }; PEERS_MAX]));
+
+/// Signalled when a peripheral (dis)connects or sends a new value.
+pub static PEER_UPDATED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Bytes buffered in each direction of the L2CAP stream.
+pub const STREAM_BUFFER: usize = 1024;
+
+/// Data exchanged with the central over an L2CAP channel, see [`crate::stream`].
+pub static STREAM: Stream<STREAM_BUFFER> = Stream::new();
+
+/// Latest averaged battery reading, only published with a battery monitor configured.
+pub static BATTERY: Watch<CriticalSectionRawMutex, Battery, 2> = Watch::new();
+
+/// Latest temperature reading in 0.01 °C.
+pub static TEMPERATURE: Watch<CriticalSectionRawMutex, i16, 4> = Watch::new();
+
+/// Latest relative humidity in 0.01 %, only published if an external sensor provides it.
+pub static HUMIDITY: Watch<CriticalSectionRawMutex, u16, 2> = Watch::new();
+
+/// Latest pressure in 0.1 Pa, only published if an external sensor provides it.
+pub static PRESSURE: Watch<CriticalSectionRawMutex, u32, 2> = Watch::new();
+
+/// Addresses that answered the I2C bus scan at boot.
+pub static I2C_DEVICES: Watch<CriticalSectionRawMutex, ScanResult, 2> = Watch::new();
+
+/// The sensors in use, published once by `temp_task` after detection.
+pub static SENSORS: Watch<CriticalSectionRawMutex, Registry, 2> = Watch::new();
+
+/// Current temperature alarm state, published by `temp_task` on every change.
+pub static ALARM: Watch<CriticalSectionRawMutex, AlarmState, 4> = Watch::new();
+
+/// New alarm thresholds written over GATT, picked up by `temp_task`.
+pub static ALARM_THRESHOLDS: Watch<CriticalSectionRawMutex, Thresholds, 2> = Watch::new();
+
+/// Persisted settings, loaded at boot. Send a new value to apply it; `settings_task` saves it.
+pub static SETTINGS: Watch<CriticalSectionRawMutex, Settings, 3> = Watch::new();
+
+/// Whether a BLE central is connected.
+pub static BLE_CONNECTED: Watch<CriticalSectionRawMutex, bool, 2> = Watch::new();
+
+/// Parameters, MTU and RSSI of the connection to the central, meaningful while connected.
+pub static LINK: Watch<CriticalSectionRawMutex, Link, 2> = Watch::new();
+
+/// Leash alarm for the central walking away, fed by the BLE task in leash mode and
+/// acknowledged with a short press of the BOOT button.
+pub static LEASH: Mutex<CriticalSectionRawMutex, RefCell<Leash>> =
+    Mutex::new(RefCell::new(Leash::new()));
+
+/// Signalled on GATT traffic, to keep the connection on fast parameters.
+pub static LINK_ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signalled on user interaction, e.g. GATT writes, to wake the display.
+pub static USER_ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Show the pairing QR code, e.g. on first boot.
+pub static SHOW_PAIRING: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Short and double presses of the BOOT button, handled by the display task.
+pub static BUTTON: Signal<CriticalSectionRawMutex, Gesture> = Signal::new();
+
+/// Advertise again after the advertising timeout ran out, from a press of the BOOT button.
+pub static RESUME_ADVERTISING: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Erase the settings and restart, from a long press of the BOOT button.
+pub static FACTORY_RESET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// The BOOT button was let go after the long press that asked for [`FACTORY_RESET`].
+pub static FACTORY_RESET_RELEASED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Whether the pairing QR code is on screen, published by the display task.
+pub static PAIRING_ACTIVE: Watch<CriticalSectionRawMutex, bool, 1> = Watch::new();
+
+/// Whether a firmware update is being received. Nothing receives updates yet; the status
+/// LED already has a pattern for it.
+pub static OTA_IN_PROGRESS: Watch<CriticalSectionRawMutex, bool, 1> = Watch::new();
+
+/// Set when something needs attention, e.g. the display did not initialise or settings
+/// could not be saved.
+pub static FAULT: Watch<CriticalSectionRawMutex, bool, 1> = Watch::new();
+
+/// Power mode decided by `power_task`: tasks sample less while idle and switch off what
+/// they drive before deep sleep.
+pub static POWER: Watch<CriticalSectionRawMutex, Mode, 2> = Watch::new();
+
+/// Signalled on button presses, to keep the board out of idle and deep sleep.
+pub static POWER_ACTIVITY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Signalled by the display task once the panel is off for deep sleep.
+pub static SLEEP_READY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
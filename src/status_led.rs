@@ -0,0 +1,184 @@
+//! Status LED patterns.
+//!
+//! A pattern is a list of colour steps played in a loop. The [`Sequencer`] works out which
+//! step is due and only writes to the LED when the colour changes, so a steady pattern
+//! costs nothing after the first write. Plain single colour LEDs light up for any colour
+//! that is not [`Color::OFF`]. Times are milliseconds since boot.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const OFF: Color = Color::rgb(0, 0, 0);
+    // Dev board LEDs are blinding at full brightness
+    pub const RED: Color = Color::rgb(40, 0, 0);
+    pub const GREEN: Color = Color::rgb(0, 24, 0);
+    pub const BLUE: Color = Color::rgb(0, 0, 40);
+    pub const AMBER: Color = Color::rgb(40, 16, 0);
+    pub const DIM_AMBER: Color = Color::rgb(8, 3, 0);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub const fn is_off(&self) -> bool {
+        self.r == 0 && self.g == 0 && self.b == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Step {
+    pub color: Color,
+    pub ms: u32,
+}
+
+const fn step(color: Color, ms: u32) -> Step {
+    Step { color, ms }
+}
+
+/// Steps played in a loop.
+pub type Pattern = &'static [Step];
+
+/// Short blue flash every two seconds.
+pub const ADVERTISING: Pattern = &[step(Color::BLUE, 50), step(Color::OFF, 1950)];
+/// Steady green.
+pub const CONNECTED: Pattern = &[step(Color::GREEN, 1000)];
+/// Fast blue blinking while the pairing QR code is up.
+pub const PAIRING: Pattern = &[step(Color::BLUE, 150), step(Color::OFF, 150)];
+/// Three red flashes and a pause.
+pub const ERROR: Pattern = &[
+    step(Color::RED, 100),
+    step(Color::OFF, 100),
+    step(Color::RED, 100),
+    step(Color::OFF, 100),
+    step(Color::RED, 100),
+    step(Color::OFF, 1000),
+];
+/// Fast amber blinking while the leash alarm is on.
+pub const LEASH: Pattern = &[step(Color::AMBER, 100), step(Color::OFF, 100)];
+/// Slow amber pulse while a firmware update is received.
+pub const OTA: Pattern = &[
+    step(Color::DIM_AMBER, 300),
+    step(Color::AMBER, 300),
+    step(Color::DIM_AMBER, 300),
+    step(Color::OFF, 300),
+];
+
+/// What the LED shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Status {
+    Advertising,
+    Connected,
+    Pairing,
+    Ota,
+    Leash,
+    Error,
+}
+
+impl Status {
+    pub fn pattern(self) -> Pattern {
+        match self {
+            Status::Advertising => ADVERTISING,
+            Status::Connected => CONNECTED,
+            Status::Pairing => PAIRING,
+            Status::Ota => OTA,
+            Status::Leash => LEASH,
+            Status::Error => ERROR,
+        }
+    }
+}
+
+/// Everything the LED can report on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Indicators {
+    pub connected: bool,
+    pub pairing: bool,
+    pub ota: bool,
+    pub leash: bool,
+    pub error: bool,
+}
+
+impl Indicators {
+    /// The most important status that applies.
+    pub fn status(&self) -> Status {
+        if self.error {
+            Status::Error
+        } else if self.leash {
+            Status::Leash
+        } else if self.ota {
+            Status::Ota
+        } else if self.pairing {
+            Status::Pairing
+        } else if self.connected {
+            Status::Connected
+        } else {
+            Status::Advertising
+        }
+    }
+}
+
+/// Something that shows a colour: an addressable RGB LED, a plain LED, a mock.
+#[allow(async_fn_in_trait)]
+pub trait LedWriter {
+    type Error;
+
+    async fn write(&mut self, color: Color) -> Result<(), Self::Error>;
+}
+
+pub struct Sequencer {
+    pattern: Pattern,
+    started: u64,
+    /// Colour last written, `None` until the first write.
+    shown: Option<Color>,
+}
+
+impl Sequencer {
+    pub const fn new(pattern: Pattern, now: u64) -> Self {
+        Self {
+            pattern,
+            started: now,
+            shown: None,
+        }
+    }
+
+    /// Switch to `pattern`, from its first step. Setting the running pattern again keeps it
+    /// going where it is.
+    pub fn set_pattern(&mut self, pattern: Pattern, now: u64) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.started = now;
+        }
+    }
+
+    /// Colour due now and milliseconds until the next step.
+    pub fn color(&self, now: u64) -> (Color, u64) {
+        let total: u64 = self.pattern.iter().map(|step| step.ms as u64).sum();
+        if total == 0 {
+            return (Color::OFF, u64::MAX);
+        }
+        let elapsed = now.saturating_sub(self.started) % total;
+        let mut end = 0;
+        for step in self.pattern {
+            end += step.ms as u64;
+            if elapsed < end {
+                return (step.color, end - elapsed);
+            }
+        }
+        unreachable!("elapsed is below the total")
+    }
+
+    /// Write the colour due now if it differs from the one shown. Returns milliseconds
+    /// until the next step.
+    pub async fn update<W: LedWriter>(&mut self, led: &mut W, now: u64) -> Result<u64, W::Error> {
+        let (color, remaining) = self.color(now);
+        if self.shown != Some(color) {
+            led.write(color).await?;
+            self.shown = Some(color);
+        }
+        Ok(remaining)
+    }
+}
@@ -0,0 +1,103 @@
+//! Bulk data over an L2CAP connection-oriented channel.
+//!
+//! The connected central may open one credit-based channel on the PSM from the `L2CAP_PSM`
+//! build variable. Application code reads what the central sends and writes what it should
+//! receive through a [`Stream`]; the BLE task moves SDUs between the stream and the channel.
+//! The central only sends while it has credits, which are returned as the stream buffer
+//! empties, so a slow reader throttles it instead of losing data. Data written while no
+//! channel is open waits until the buffer is full and is dropped when a channel opens or
+//! closes. Until application code takes the stream over, the BLE task echoes it with
+//! [`Stream::echo`], so the central gets back what it sends instead of running out of
+//! credits.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+
+/// Most bytes per SDU: with the 2 byte SDU length and the 4 byte L2CAP header, an SDU fits
+/// in one 255 byte packet of the BLE packet pool.
+pub const SDU_MAX: usize = 249;
+
+/// SDUs the central may send before it has to wait for credits.
+pub const CREDITS: u16 = 8;
+
+/// Range of dynamically assigned LE PSMs.
+pub const PSM_MIN: u16 = 0x0080;
+pub const PSM_MAX: u16 = 0x00FF;
+
+/// `0x0080` or `128`, within [`PSM_MIN`] and [`PSM_MAX`].
+pub fn parse_psm(s: &str) -> Option<u16> {
+    let psm = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+        None => s.parse().ok()?,
+    };
+    (PSM_MIN..=PSM_MAX).contains(&psm).then_some(psm)
+}
+
+/// Buffers of `N` bytes in each direction between application code and the channel.
+pub struct Stream<const N: usize> {
+    /// Central to board.
+    incoming: Pipe<CriticalSectionRawMutex, N>,
+    /// Board to central.
+    outgoing: Pipe<CriticalSectionRawMutex, N>,
+    open: AtomicBool,
+}
+
+impl<const N: usize> Stream<N> {
+    pub const fn new() -> Self {
+        Self {
+            incoming: Pipe::new(),
+            outgoing: Pipe::new(),
+            open: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a central has a channel open.
+    pub fn is_open(&self) -> bool {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Read what the central sent, waiting until there is at least one byte.
+    pub async fn read(&self, buf: &mut [u8]) -> usize {
+        self.incoming.read(buf).await
+    }
+
+    /// Queue all of `data` for the central, waiting while the buffer is full.
+    pub async fn write(&self, data: &[u8]) {
+        self.outgoing.write_all(data).await;
+    }
+
+    /// Send everything the central sends straight back, while the central reads it.
+    pub async fn echo(&self) -> ! {
+        let mut buf = [0u8; SDU_MAX];
+        loop {
+            let len = self.read(&mut buf).await;
+            self.write(&buf[..len]).await;
+        }
+    }
+
+    /// Channel side: a channel was opened or closed, drop what is buffered.
+    pub fn set_open(&self, open: bool) {
+        self.incoming.clear();
+        self.outgoing.clear();
+        self.open.store(open, Ordering::Relaxed);
+    }
+
+    /// Channel side: pass an SDU from the central on, waiting while the reader catches up.
+    pub async fn deliver(&self, sdu: &[u8]) {
+        self.incoming.write_all(sdu).await;
+    }
+
+    /// Channel side: the next SDU for the central, as much as is buffered up to `sdu.len()`
+    /// bytes. Waits until there is at least one.
+    pub async fn next_sdu(&self, sdu: &mut [u8]) -> usize {
+        self.outgoing.read(sdu).await
+    }
+}
+
+impl<const N: usize> Default for Stream<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
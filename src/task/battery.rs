@@ -0,0 +1,76 @@
+use alloc::boxed::Box;
+
+use defmt::{info, warn};
+use embassy_time::{Duration, Timer};
+use esp_hal::analog::adc::{Adc, AdcCalCurve, AdcChannel, AdcConfig, Attenuation};
+use esp_hal::gpio::AnalogPin;
+use esp_hal::peripherals::ADC1;
+
+use crate::battery::{self, Average, Battery, Chemistry};
+use crate::state::BATTERY;
+
+/// Cell chemistry, `li-ion`, `lifepo4` or `2xaa`, see `.env.example`.
+const BATTERY_CHEMISTRY: &str = env!("BATTERY_CHEMISTRY");
+/// Ratio of the battery voltage to the voltage at the pin, e.g. `2` for two equal resistors.
+const BATTERY_DIVIDER: &str = env!("BATTERY_DIVIDER");
+
+/// How often the battery voltage is sampled.
+const SAMPLE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Reads the voltage at the battery pin in millivolts.
+pub type ReadMillivolts = Box<dyn FnMut() -> u16>;
+
+/// Calibrated one-shot reads of `pin` on ADC1. The 11 dB attenuation covers up to about
+/// 2.5 V at the pin, so a 1:2 divider reads a full Li-ion cell.
+pub fn battery_adc<P>(adc: ADC1<'static>, pin: P) -> ReadMillivolts
+where
+    P: AdcChannel + AnalogPin + 'static,
+{
+    let mut config = AdcConfig::new();
+    let mut pin =
+        config.enable_pin_with_cal::<_, AdcCalCurve<ADC1<'static>>>(pin, Attenuation::_11dB);
+    let mut adc = Adc::new(adc, config);
+    Box::new(move || loop {
+        // a conversion takes microseconds, not worth yielding for
+        if let Ok(mv) = adc.read_oneshot(&mut pin) {
+            break mv;
+        }
+    })
+}
+
+/// Sample the battery every [`SAMPLE_PERIOD`] and publish the averaged reading to
+/// [`BATTERY`].
+#[embassy_executor::task]
+pub async fn battery_task(mut read_mv: ReadMillivolts) {
+    let chemistry = Chemistry::parse(BATTERY_CHEMISTRY).unwrap_or_else(|| {
+        warn!(
+            "[battery] unknown chemistry {}, using li-ion",
+            BATTERY_CHEMISTRY
+        );
+        Chemistry::LiIon
+    });
+    let ratio = battery::parse_ratio(BATTERY_DIVIDER).unwrap_or_else(|| {
+        warn!("[battery] invalid divider {}, using 2", BATTERY_DIVIDER);
+        2000
+    });
+    info!("[battery] {:?}, divider {}/1000", chemistry, ratio);
+    let mut average = Average::new();
+    let sender = BATTERY.sender();
+
+    loop {
+        let mv = average.add(battery::battery_mv(read_mv(), ratio));
+        let reading = Battery::new(mv, chemistry);
+        let previous = BATTERY.try_get();
+        if previous.map(|previous| previous.percent) != Some(reading.percent) {
+            info!("[battery] {} mV, {}%", reading.mv, reading.percent);
+        }
+        // also when the battery is already low at boot
+        if previous.is_none_or(|previous| !previous.is_low()) && reading.is_low() {
+            warn!("[battery] low");
+        }
+        if previous != Some(reading) {
+            sender.send(reading);
+        }
+        Timer::after(SAMPLE_PERIOD).await;
+    }
+}
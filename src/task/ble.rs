@@ -1,57 +1,526 @@
-use embassy_futures::join::join;
-use embassy_futures::select::select;
-use embassy_time::Timer;
+use core::cell::RefCell;
+
+use embassy_futures::join::{join4, join_array};
+use embassy_futures::select::{select, select3, select4, Either, Either3, Either4};
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex, RawMutex};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+use static_cell::StaticCell;
 use trouble_host::prelude::*;
 
+use bt_hci::cmd::le::{
+    LeAddDeviceToFilterAcceptList, LeClearFilterAcceptList, LeConnUpdate,
+    LeReadLocalSupportedFeatures, LeSetDataLength, LeSetPhy, LeSetScanEnable, LeSetScanParams,
+};
+#[cfg(feature = "extended-advertising")]
+use bt_hci::cmd::le::{
+    LeClearAdvSets, LeReadNumberOfSupportedAdvSets, LeSetAdvSetRandomAddr, LeSetExtAdvData,
+    LeSetExtAdvEnable, LeSetExtAdvParams, LeSetExtScanResponseData,
+};
+use bt_hci::controller::{ControllerCmdAsync, ControllerCmdSync};
+
 use defmt::info;
 use defmt::warn;
 
+use crate::advertising::{self, AdData, Status, Telemetry};
+use crate::alarm::{AlarmState, Thresholds, Trigger, TriggerError, CONFIGURATION_OR, TRIGGER_LEN};
+use crate::clock::{self, TimeSource};
+use crate::history::{racp, racp_count, racp_response, RecordRequest, RECORD_LEN};
+use crate::link::{Link, LinkPolicy, Profile, LINK_LEN};
+use crate::peer::{
+    self, AddressKind, Backoff, PeerClient, PeerUuid, Target, HEART_RATE_MEASUREMENT,
+    PEER_RECORD_LEN,
+};
+use crate::proximity::{self, Proximity, Rssi, PROXIMITY_LEN};
+use crate::relay::{self, Frame, Relay};
+use crate::scan::{self, NEIGHBOR_LEN};
+use crate::sensor::{self, Quantity, REGISTRY_LEN};
+use crate::settings::{
+    AdvertisingSettings, Beacon, DisplaySettings, ADVERTISING_SETTINGS_LEN, DISPLAY_SETTINGS_LEN,
+};
+use crate::state::{
+    ALARM, ALARM_THRESHOLDS, BATTERY, BLE_CONNECTED, FAULT, HUMIDITY, I2C_DEVICES, LEASH, LINK,
+    LINK_ACTIVITY, NEIGHBORS, NEIGHBORS_MAX, PEERS_MAX, PEER_UPDATED, PEER_VALUES, PRESSURE, RELAY,
+    RELAY_PENDING, RESUME_ADVERTISING, SENSORS, SETTINGS, STREAM, TEMPERATURE, TEMPERATURE_HISTORY,
+    USER_ACTIVITY,
+};
+use crate::stream::{self, CREDITS, SDU_MAX};
+use crate::throughput::{Direction, Meter, Request, CHUNK_MAX, RESULT_LEN};
+
 const MAC_ADDRESS: &str = env!("MAC_ADDRESS");
 
-/// Max number of connections
-const CONNECTIONS_MAX: usize = 1;
+/// Peripherals to connect to as a central, see [`crate::peer`].
+const PEERS: &str = env!("PEERS");
+
+/// `on` to re-broadcast the status records of other boards, see [`crate::relay`].
+const RELAY_MODE: &str = env!("RELAY");
+
+/// `on` to raise an alarm when the central walks away, see [`crate::proximity`].
+const LEASH_MODE: &str = env!("LEASH");
+
+/// PSM of the L2CAP stream channel, see [`crate::stream`].
+const L2CAP_PSM: &str = env!("L2CAP_PSM");
+
+/// GAP device name, also put in the pairing QR code.
+pub const DEVICE_NAME: &str = "COW GATT";
+
+/// What the BLE stack needs from the controller: PHY, connection parameter and data length
+/// updates and the scanner on top of the basics, plus [`ExtAdvController`].
+pub trait BleController:
+    Controller
+    + ControllerCmdAsync<LeSetPhy>
+    + ControllerCmdAsync<LeConnUpdate>
+    + ControllerCmdSync<LeReadLocalSupportedFeatures>
+    + ControllerCmdSync<LeSetDataLength>
+    + ControllerCmdSync<LeSetScanParams>
+    + ControllerCmdSync<LeSetScanEnable>
+    + ControllerCmdSync<LeClearFilterAcceptList>
+    + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+    + ExtAdvController
+{
+}
+
+impl<C> BleController for C where
+    C: Controller
+        + ControllerCmdAsync<LeSetPhy>
+        + ControllerCmdAsync<LeConnUpdate>
+        + ControllerCmdSync<LeReadLocalSupportedFeatures>
+        + ControllerCmdSync<LeSetDataLength>
+        + ControllerCmdSync<LeSetScanParams>
+        + ControllerCmdSync<LeSetScanEnable>
+        + ControllerCmdSync<LeClearFilterAcceptList>
+        + ControllerCmdSync<LeAddDeviceToFilterAcceptList>
+        + ExtAdvController
+{
+}
+
+/// The extended advertising commands, only required with the `extended-advertising` feature.
+#[cfg(feature = "extended-advertising")]
+pub trait ExtAdvController:
+    for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
+    + ControllerCmdSync<LeClearAdvSets>
+    + ControllerCmdSync<LeSetExtAdvParams>
+    + ControllerCmdSync<LeSetAdvSetRandomAddr>
+    + ControllerCmdSync<LeReadNumberOfSupportedAdvSets>
+    + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+    + for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>
+{
+}
+
+#[cfg(feature = "extended-advertising")]
+impl<C> ExtAdvController for C where
+    C: for<'t> ControllerCmdSync<LeSetExtAdvData<'t>>
+        + ControllerCmdSync<LeClearAdvSets>
+        + ControllerCmdSync<LeSetExtAdvParams>
+        + ControllerCmdSync<LeSetAdvSetRandomAddr>
+        + ControllerCmdSync<LeReadNumberOfSupportedAdvSets>
+        + for<'t> ControllerCmdSync<LeSetExtAdvEnable<'t>>
+        + for<'t> ControllerCmdSync<LeSetExtScanResponseData<'t>>
+{
+}
+
+/// The extended advertising commands, only required with the `extended-advertising` feature.
+#[cfg(not(feature = "extended-advertising"))]
+pub trait ExtAdvController {}
+
+#[cfg(not(feature = "extended-advertising"))]
+impl<C> ExtAdvController for C {}
+
+/// Max number of connections: one central connected to us, plus the peripherals we connect to
+const CONNECTIONS_MAX: usize = 1 + PEERS_MAX;
 
-/// Max number of L2CAP channels.
-const L2CAP_CHANNELS_MAX: usize = 2; // Signal + att
+/// Max number of L2CAP channels: signal and ATT per connection, plus the stream channel of
+/// the central.
+const L2CAP_CHANNELS_MAX: usize = 2 * CONNECTIONS_MAX + 1;
+
+/// Max number of services discovered on a peripheral.
+const PEER_SERVICES_MAX: usize = 10;
+
+/// Link layer payload and time asked for with the Data Length Extension: the most the spec
+/// allows, so a full ATT MTU goes out in one packet.
+const DATA_LENGTH_MAX: u16 = 251;
+const DATA_TIME_MAX_US: u16 = 2120;
+
+// `.cargo/config.toml` sizes the packet pool for an ATT MTU of 251 plus the 4 byte L2CAP
+// header, fail the build if that setting is lost or overridden with less
+const _: () = assert!(
+    DefaultPacketPool::MTU >= 251 + 4,
+    "TROUBLE_HOST_DEFAULT_PACKET_POOL_MTU must be at least 255"
+);
+
+/// How long a connection attempt to a peripheral may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The central, shared by the scanner and the connections to peripherals. It is `None`
+/// while the scanner has it.
+type SharedCentral<'a, C> = Mutex<NoopRawMutex, Option<Central<'a, C, DefaultPacketPool>>>;
 
 const SERVICE_UUID: [u8; 16] = [
     0xFD, 0x2B, 0x44, 0x48, 0xAA, 0x0F, 0x4A, 0x15,
     0xA6, 0x2F, 0xEB, 0x0B, 0xE7, 0x7A, 0x00, 0x00
 ];
 
+/// How often the advertised status record is checked for changes while advertising, and
+/// how often a beacon burst is sent in beacon mode.
+const STATUS_REFRESH: Duration = Duration::from_secs(10);
+
+/// Passive scan for nearby sensors: a 100 ms window every second leaves the radio to
+/// advertising and the connection most of the time.
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+const SCAN_WINDOW: Duration = Duration::from_millis(100);
+
+/// How long each relayed status record is broadcast: a scan interval plus a window, so a
+/// whole scan window of a board scanning like this one falls into the burst.
+const RELAY_BURST: Duration =
+    Duration::from_millis(SCAN_INTERVAL.as_millis() + SCAN_WINDOW.as_millis());
+
+/// Advertising interval of a relay burst. Events are up to the interval plus a 10 ms random
+/// delay apart, so this gives every scan window at least two of them.
+const RELAY_INTERVAL: Duration = Duration::from_millis((SCAN_WINDOW.as_millis() - 10) / 2);
+
+/// How long each beacon burst goes on before switching back to connectable advertising.
+const BEACON_BURST: Duration = Duration::from_secs(2);
+
 // GATT Server definition
 #[gatt_server]
 struct Server {
     battery_service: BatteryService,
+    current_time_service: CurrentTimeService,
+    history_service: HistoryService,
+    environmental_sensing_service: EnvironmentalSensingService,
+    diagnostics_service: DiagnosticsService,
+    settings_service: SettingsService,
+    gateway_service: GatewayService,
+    throughput_service: ThroughputService,
 }
 
 /// Battery service
 #[gatt_service(uuid = "FD2B4448-AA0F-4A15-A62F-EB0BE77A0000")]
 struct BatteryService {
-    /// Battery Level
+    /// Battery Level, 0 until there is a reading: the power state tells it is unknown
     #[descriptor(uuid = descriptors::VALID_RANGE, read, value = [0, 100])]
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "hello", read, value = "Battery Level")]
-    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read, notify, value = 10)]
+    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read, notify, value = 0)]
     level: u8,
+    /// Battery Power State (0x2A1A), see
+    /// [`crate::battery::Battery::power_state`]. Starts out as not present.
+    #[characteristic(uuid = "2a1a", read, notify, value = 0x12)]
+    power_state: u8,
     #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100000", write, read, notify)]
     status: bool,
 }
 
+/// Current Time Service, lets a central set the clock when there is no Wi-Fi for SNTP
+#[gatt_service(uuid = service::CURRENT_TIME)]
+struct CurrentTimeService {
+    /// Exact Time 256 + Adjust Reason, see [`clock::encode_current_time`]
+    #[characteristic(uuid = characteristic::CURRENT_TIME, read, write, notify)]
+    current_time: [u8; 10],
+}
+
+/// Environmental Sensing Service with the temperature alarm.
+///
+/// Written out instead of using `#[gatt_service]`, whose descriptors can only be read: the
+/// alarm thresholds are writable ES Trigger Setting descriptors of the temperature, high
+/// then low, combined with a boolean OR, see [`Thresholds::with_trigger`].
+struct EnvironmentalSensingService {
+    /// Temperature in 0.01 °C
+    temperature: Characteristic<i16>,
+    /// High threshold, see [`Thresholds::encode_trigger`]
+    high_trigger: Descriptor<[u8; TRIGGER_LEN]>,
+    /// Low threshold, see [`Thresholds::encode_trigger`]
+    low_trigger: Descriptor<[u8; TRIGGER_LEN]>,
+    /// How the triggers combine, only [`CONFIGURATION_OR`]
+    es_configuration: Descriptor<u8>,
+    /// Relative humidity in 0.01 %, reads are refused without an external humidity sensor
+    humidity: Characteristic<u16>,
+    /// Pressure in 0.1 Pa, reads are refused without an external pressure sensor
+    pressure: Characteristic<u32>,
+    /// Detected sensors, comma separated, e.g. `SHT4x,BME280`
+    sensors: Characteristic<heapless::Vec<u8, 32>>,
+    /// One [`sensor::Metadata::record`] per sensor in use, in the order of `sensors`
+    registry: Characteristic<heapless::Vec<u8, REGISTRY_LEN>>,
+    /// How far the temperature has to move back past a threshold to clear the alarm, in
+    /// 0.01 °C
+    alarm_hysteresis: Characteristic<u16>,
+    /// 0 = normal, 1 = too hot, 2 = too cold
+    alarm: Characteristic<u8>,
+}
+
+impl EnvironmentalSensingService {
+    /// Service declaration, plus declaration and value of each characteristic, plus the
+    /// CCCDs and descriptors.
+    const ATTRIBUTE_COUNT: usize = 1 + 7 * 2 + Self::CCCD_COUNT + 6;
+    const CCCD_COUNT: usize = 4;
+
+    fn new<M: RawMutex, const MAX_ATTRIBUTES: usize>(
+        table: &mut AttributeTable<'_, M, MAX_ATTRIBUTES>,
+    ) -> Self {
+        static TEMPERATURE_STORE: StaticCell<[u8; 2]> = StaticCell::new();
+        static HIGH_TRIGGER_STORE: StaticCell<[u8; TRIGGER_LEN]> = StaticCell::new();
+        static LOW_TRIGGER_STORE: StaticCell<[u8; TRIGGER_LEN]> = StaticCell::new();
+        static ES_CONFIGURATION_STORE: StaticCell<[u8; 1]> = StaticCell::new();
+        static HUMIDITY_STORE: StaticCell<[u8; 2]> = StaticCell::new();
+        static PRESSURE_STORE: StaticCell<[u8; 4]> = StaticCell::new();
+        static SENSORS_STORE: StaticCell<[u8; 32]> = StaticCell::new();
+        static REGISTRY_STORE: StaticCell<[u8; REGISTRY_LEN]> = StaticCell::new();
+        static ALARM_HYSTERESIS_STORE: StaticCell<[u8; 2]> = StaticCell::new();
+        static ALARM_STORE: StaticCell<[u8; 1]> = StaticCell::new();
+
+        let thresholds = Thresholds::default();
+        let mut service = table.add_service(Service::new(service::ENVIRONMENTAL_SENSING));
+
+        let mut builder = service.add_characteristic(
+            characteristic::TEMPERATURE,
+            &[CharacteristicProp::Read, CharacteristicProp::Notify],
+            0i16,
+            TEMPERATURE_STORE.init([0; 2]),
+        );
+        let read_write = [CharacteristicProp::Read, CharacteristicProp::Write];
+        let high_trigger = builder.add_descriptor(
+            descriptors::ENVIRONMENTAL_SENSING_TRIGGER_SETTING,
+            &read_write,
+            HIGH_TRIGGER_STORE.init(thresholds.encode_trigger(Trigger::High)),
+        );
+        let low_trigger = builder.add_descriptor(
+            descriptors::ENVIRONMENTAL_SENSING_TRIGGER_SETTING,
+            &read_write,
+            LOW_TRIGGER_STORE.init(thresholds.encode_trigger(Trigger::Low)),
+        );
+        let es_configuration = builder.add_descriptor(
+            descriptors::ENVIRONMENTAL_SENSING_CONFIGURATION,
+            &read_write,
+            ES_CONFIGURATION_STORE.init([CONFIGURATION_OR]),
+        );
+        let temperature = builder.build();
+
+        let humidity = service
+            .add_characteristic(
+                characteristic::HUMIDITY,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                0u16,
+                HUMIDITY_STORE.init([0; 2]),
+            )
+            .build();
+        let pressure = service
+            .add_characteristic(
+                characteristic::PRESSURE,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                0u32,
+                PRESSURE_STORE.init([0; 4]),
+            )
+            .build();
+
+        let mut builder = service.add_characteristic(
+            0x408813df_5dd4_1f87_ec11_cdb001100022_u128,
+            &[CharacteristicProp::Read],
+            heapless::Vec::new(),
+            SENSORS_STORE.init([0; 32]),
+        );
+        builder.add_descriptor_ro::<&[u8], _>(descriptors::MEASUREMENT_DESCRIPTION, b"Sensors");
+        let sensors = builder.build();
+
+        let mut builder = service.add_characteristic(
+            0x408813df_5dd4_1f87_ec11_cdb001100024_u128,
+            &[CharacteristicProp::Read],
+            heapless::Vec::new(),
+            REGISTRY_STORE.init([0; REGISTRY_LEN]),
+        );
+        builder.add_descriptor_ro::<&[u8], _>(
+            descriptors::MEASUREMENT_DESCRIPTION,
+            b"Sensor registry",
+        );
+        let registry = builder.build();
+
+        let mut builder = service.add_characteristic(
+            0x408813df_5dd4_1f87_ec11_cdb001100023_u128,
+            &read_write,
+            thresholds.hysteresis,
+            ALARM_HYSTERESIS_STORE.init([0; 2]),
+        );
+        builder.add_descriptor_ro::<&[u8], _>(
+            descriptors::MEASUREMENT_DESCRIPTION,
+            b"Alarm hysteresis",
+        );
+        let alarm_hysteresis = builder.build();
+
+        let alarm = service
+            .add_characteristic(
+                0x408813df_5dd4_1f87_ec11_cdb001100021_u128,
+                &[CharacteristicProp::Read, CharacteristicProp::Notify],
+                0u8,
+                ALARM_STORE.init([0; 1]),
+            )
+            .build();
+        service.build();
+
+        Self {
+            temperature,
+            high_trigger,
+            low_trigger,
+            es_configuration,
+            humidity,
+            pressure,
+            sensors,
+            registry,
+            alarm_hysteresis,
+            alarm,
+        }
+    }
+}
+
+/// Diagnostics
+#[gatt_service(uuid = "FD2B4448-AA0F-4A15-A62F-EB0BE77A0200")]
+struct DiagnosticsService {
+    /// I2C addresses that answered the boot scan, bit `n` set for address `n`
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "I2C devices")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100030", read)]
+    i2c_devices: [u8; 16],
+    /// Negotiated connection parameters, ATT MTU and RSSI, see [`Link::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Link")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100031", read)]
+    link: [u8; LINK_LEN],
+    /// Smoothed RSSI, proximity of the central and leash alarm, see [`proximity::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Proximity")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100032", read, notify)]
+    proximity: [u8; PROXIMITY_LEN],
+}
+
+/// Persisted device settings
+#[gatt_service(uuid = "FD2B4448-AA0F-4A15-A62F-EB0BE77A0300")]
+struct SettingsService {
+    /// OLED contrast, dimming and power-off policy, see [`DisplaySettings::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Display settings")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100040", read, write)]
+    display_settings: [u8; DISPLAY_SETTINGS_LEN],
+    /// Advertising interval, TX power, timeout and fields, see [`AdvertisingSettings::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Advertising settings")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100041", read, write)]
+    advertising_settings: [u8; ADVERTISING_SETTINGS_LEN],
+}
+
+/// Readings of nearby sensors heard by the scanner
+#[gatt_service(uuid = "FD2B4448-AA0F-4A15-A62F-EB0BE77A0400")]
+struct GatewayService {
+    /// One sensor per notification, all of them every 10 s, see [`scan::Neighbor::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Nearby sensors")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100050", notify)]
+    neighbors: [u8; NEIGHBOR_LEN],
+    /// Number of sensors heard in the last five minutes
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100051", read)]
+    count: u8,
+    /// Latest value of a configured peripheral on every change, see [`peer::PeerValue::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Peripherals")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100052", notify)]
+    peers: [u8; PEER_RECORD_LEN],
+}
+
+/// Throughput test, see [`crate::throughput`]
+#[gatt_service(uuid = "FD2B4448-AA0F-4A15-A62F-EB0BE77A0500")]
+struct ThroughputService {
+    /// Start a test run, see [`Request::parse`]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100060", write)]
+    control: heapless::Vec<u8, 2>,
+    /// Test data: notified as long as the ATT MTU allows when sending, written by the
+    /// central when receiving
+    #[characteristic(
+        uuid = "408813df-5dd4-1f87-ec11-cdb001100061",
+        notify,
+        write_without_response
+    )]
+    data: heapless::Vec<u8, CHUNK_MAX>,
+    /// Result of the last run, see [`Meter::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Throughput")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100062", read, notify)]
+    result: [u8; RESULT_LEN],
+}
+
+/// Temperature history download
+#[gatt_service(uuid = "FD2B4448-AA0F-4A15-A62F-EB0BE77A0100")]
+struct HistoryService {
+    /// Record Access Control Point, see [`RecordRequest`] for the supported requests
+    #[characteristic(uuid = characteristic::RECORD_ACCESS_CONTROL_POINT, write, notify)]
+    control: heapless::Vec<u8, 11>,
+    /// One interval summary per notification, see [`crate::history::Summary::encode`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, read, value = "Temperature history")]
+    #[characteristic(uuid = "408813df-5dd4-1f87-ec11-cdb001100010", notify)]
+    records: [u8; RECORD_LEN],
+}
+
+/// Requests written to the history control point, served by [`history_task`]. Requests that
+/// failed to parse carry the error response to notify instead.
+static HISTORY_REQUEST: Signal<CriticalSectionRawMutex, Result<RecordRequest, [u8; 4]>> =
+    Signal::new();
+
+/// Records the readings of sensors in advertising reports in [`NEIGHBORS`], and in relay
+/// mode queues the status records of other boards in [`RELAY`].
+struct ScanHandler;
+
+impl EventHandler for ScanHandler {
+    fn on_adv_reports(&self, mut it: LeAdvReportsIter<'_>) {
+        let now = Instant::now().as_secs() as u32;
+        while let Some(Ok(report)) = it.next() {
+            let address = report.addr.into_inner();
+            // boards heard through a relay are listed under their own address
+            let heard = match relay::heard(report.data, address) {
+                Some(frame) => {
+                    if relay_enabled() && RELAY.lock(|relay| relay.borrow_mut().accept(frame, now))
+                    {
+                        RELAY_PENDING.signal(());
+                    }
+                    Some((frame.origin, frame.reading()))
+                }
+                None => scan::parse(report.data).map(|reading| (address, reading)),
+            };
+            if let Some((address, reading)) = heard {
+                NEIGHBORS.lock(|neighbors| {
+                    neighbors
+                        .borrow_mut()
+                        .update(address, report.rssi, reading, now)
+                });
+            }
+        }
+    }
+}
+
+pub(crate) fn relay_enabled() -> bool {
+    RELAY_MODE == "on"
+}
+
+fn leash_enabled() -> bool {
+    LEASH_MODE == "on"
+}
+
+/// Test runs written to the throughput control point, served by [`throughput_task`].
+static THROUGHPUT_REQUEST: Signal<CriticalSectionRawMutex, Request> = Signal::new();
+
+/// Bytes written by the central during a receive test run.
+static THROUGHPUT_RECEIVED: BlockingMutex<CriticalSectionRawMutex, RefCell<Option<Meter>>> =
+    BlockingMutex::new(RefCell::new(None));
+
+/// The configured MAC address in the byte order the controller expects (least significant first).
+pub fn mac_address() -> [u8; 6] {
+    let parts = MAC_ADDRESS.split(":");
+    let hexes: heapless::Vec<u8, 6> = parts.map(|f| u8::from_str_radix(f, 16).unwrap()).collect();
+    hexes.into_array().unwrap()
+}
+
 /// Run the BLE stack.
 pub async fn run<C>(controller: C)
 where
-    C: Controller,
+    C: BleController,
 {
-    let parts = MAC_ADDRESS.split(":");
-    let hexes: heapless::Vec<u8, 6> = parts.map(|f| u8::from_str_radix(f, 16).unwrap()).collect();
-
-    let address = Address::random(hexes.into_array().unwrap());
+    let address = Address::random(mac_address());
     warn!("MAC address = {:?}", address);
 
     let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
         HostResources::new();
     let stack = trouble_host::new(controller, &mut resources).set_random_address(address);
     let Host {
+        central,
         mut peripheral,
         runner,
         ..
@@ -59,21 +528,139 @@ where
 
     info!("Starting advertising and GATT service");
     let server = Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
-        name: "COW GATT",
+        name: DEVICE_NAME,
         appearance: &appearance::power_device::GENERIC_POWER_DEVICE,
     }))
     .unwrap();
+    let thresholds = ALARM_THRESHOLDS.try_get().unwrap_or_default();
+    let environmental_sensing = &server.environmental_sensing_service;
+    let _ = server.set(
+        &environmental_sensing.high_trigger,
+        &thresholds.encode_trigger(Trigger::High),
+    );
+    let _ = server.set(
+        &environmental_sensing.low_trigger,
+        &thresholds.encode_trigger(Trigger::Low),
+    );
+    let _ = server.set(
+        &environmental_sensing.alarm_hysteresis,
+        &thresholds.hysteresis,
+    );
+    if let Some(devices) = I2C_DEVICES.try_get() {
+        let _ = server.set(&server.diagnostics_service.i2c_devices, &devices.bitmap);
+    }
+    let settings = SETTINGS.try_get().unwrap_or_default();
+    let _ = server.set(
+        &server.settings_service.display_settings,
+        &settings.display.encode(),
+    );
+    let _ = server.set(
+        &server.settings_service.advertising_settings,
+        &settings.advertising.encode(),
+    );
 
-    let _ = join(ble_task(runner), async {
+    let mut targets: heapless::Vec<Target, PEERS_MAX> = heapless::Vec::new();
+    for entry in peer::entries(PEERS) {
+        match Target::parse(entry) {
+            Some(target) => {
+                if targets.push(target).is_err() {
+                    warn!(
+                        "[peer] only {} peers supported, ignoring {}",
+                        PEERS_MAX, entry
+                    );
+                }
+            }
+            None => warn!("[peer] invalid target {}", entry),
+        }
+    }
+    let central: SharedCentral<'_, C> = Mutex::new(Some(central));
+    if relay_enabled() {
+        info!("[relay] relaying the status records of other boards");
+        RELAY.lock(|relay| *relay.borrow_mut() = Relay::new(mac_address()));
+    }
+    let peers = join_array(core::array::from_fn::<_, PEERS_MAX, _>(|index| {
+        let (stack, central, targets) = (&stack, &central, &targets);
+        async move {
+            match targets.get(index) {
+                Some(target) => peer_task(stack, central, index, target).await,
+                None => core::future::pending().await,
+            }
+        }
+    }));
+
+    let _ = join4(ble_task(runner), scan_task(&central), peers, async {
+        let mut counters = BeaconCounters::default();
         loop {
-            match advertise("COW Example", &mut peripheral, &server).await {
-                Ok(conn) => {
+            match advertise(DEVICE_NAME, &mut peripheral, &server, &mut counters).await {
+                Ok(None) => {
+                    // beacons keep going, only the connectable advertising pauses
+                    info!("[adv] timed out, waiting for a button press");
+                    RESUME_ADVERTISING.reset();
+                    let beacons = async {
+                        loop {
+                            RELAY_PENDING.reset();
+                            if let Err(e) = relay_queued(&mut peripheral).await {
+                                break e;
+                            }
+                            // relayed records go out right away
+                            let burst =
+                                beacon(DEVICE_NAME, &mut peripheral, STATUS_REFRESH, &mut counters);
+                            if let Either::First(Err(e)) = select(burst, RELAY_PENDING.wait()).await
+                            {
+                                break e;
+                            }
+                        }
+                    };
+                    if let Either::First(e) = select(beacons, RESUME_ADVERTISING.wait()).await {
+                        #[cfg(feature = "defmt")]
+                        let e = defmt::Debug2Format(&e);
+                        panic!("[adv] beacon error: {:?}", e);
+                    }
+                }
+                Ok(Some(conn)) => {
+                    BLE_CONNECTED.sender().send(true);
+                    update_link(&server, |link| {
+                        *link = Link {
+                            mtu: conn.raw().att_mtu(),
+                            ..Link::default()
+                        }
+                    });
+                    let settings = SETTINGS.try_get().unwrap_or_default().advertising;
+                    if settings.connection_2m {
+                        // the central may refuse, the link then stays on 1M
+                        if let Err(e) = conn.raw().set_phy(&stack, PhyKind::Le2M).await {
+                            warn!("[adv] 2M PHY update failed: {:?}", defmt::Debug2Format(&e));
+                        }
+                    }
+                    // the ATT MTU is up to the central, longer link layer packets are ours
+                    let data_length = LeSetDataLength::new(
+                        conn.raw().handle(),
+                        DATA_LENGTH_MAX,
+                        DATA_TIME_MAX_US,
+                    );
+                    if let Err(e) = stack.command(data_length).await {
+                        warn!(
+                            "[adv] data length update failed: {:?}",
+                            defmt::Debug2Format(&e)
+                        );
+                    }
                     // set up tasks when the connection is established to a central, so they don't run when no one is connected.
+                    HISTORY_REQUEST.reset();
                     let a = gatt_events_task(&server, &conn);
                     let b = custom_task(&server, &conn, &stack);
+                    let c = current_time_task(&server, &conn);
+                    let d = history_task(&server, &conn);
+                    let e = environment_task(&server, &conn);
+                    let f = gateway_task(&server, &conn, targets.len());
+                    let g = relay_task(&mut peripheral);
+                    let h = link_task(&conn, &stack);
+                    let i = throughput_task(&server, &conn);
+                    let j = stream_task(&conn, &stack);
+                    let k = battery_level_task(&server, &conn);
                     // run until any task ends (usually because the connection has been closed),
                     // then return to advertising state.
-                    select(a, b).await;
+                    select3(select4(a, b, c, d), select4(e, f, g, h), select3(i, j, k)).await;
+                    BLE_CONNECTED.sender().send(false);
                 }
                 Err(e) => {
                     #[cfg(feature = "defmt")]
@@ -103,7 +690,7 @@ where
 /// ```
 async fn ble_task<C: Controller, P: PacketPool>(mut runner: Runner<'_, C, P>) {
     loop {
-        if let Err(e) = runner.run().await {
+        if let Err(e) = runner.run_with_handler(&ScanHandler).await {
             #[cfg(feature = "defmt")]
             let e = defmt::Debug2Format(&e);
             panic!("[ble_task] error: {:?}", e);
@@ -111,6 +698,172 @@ async fn ble_task<C: Controller, P: PacketPool>(mut runner: Runner<'_, C, P>) {
     }
 }
 
+/// Scan for nearby sensors for as long as the stack runs, the reports go to [`ScanHandler`].
+/// Every [`STATUS_REFRESH`] the central is handed back for a moment so peripherals can be
+/// connected, and sensors that went quiet are forgotten.
+async fn scan_task<C: BleController>(central: &SharedCentral<'_, C>) {
+    let config = ScanConfig {
+        active: false,
+        interval: SCAN_INTERVAL,
+        window: SCAN_WINDOW,
+        ..Default::default()
+    };
+    info!("[scan] scanning for nearby sensors");
+    loop {
+        {
+            let mut shared = central.lock().await;
+            let mut scanner = Scanner::new(shared.take().expect("central is put back"));
+            // scanning stops when the session is dropped
+            match scanner.scan(&config).await {
+                Ok(_session) => Timer::after(STATUS_REFRESH).await,
+                Err(e) => {
+                    warn!(
+                        "[scan] could not start scanning: {:?}",
+                        defmt::Debug2Format(&e)
+                    );
+                    Timer::after(STATUS_REFRESH).await;
+                }
+            }
+            *shared = Some(scanner.into_inner());
+        }
+        let now = Instant::now().as_secs() as u32;
+        NEIGHBORS.lock(|neighbors| neighbors.borrow_mut().expire(now));
+        // let a peer task waiting for the central take it before scanning resumes
+        yield_now().await;
+    }
+}
+
+/// Keep a connection to `target` and its latest value in [`PEER_VALUES`], reconnecting
+/// with a growing delay.
+async fn peer_task<'a, C: BleController>(
+    stack: &'a Stack<'a, C, DefaultPacketPool>,
+    central: &SharedCentral<'a, C>,
+    index: usize,
+    target: &Target,
+) {
+    let address = Address {
+        kind: match target.kind {
+            AddressKind::Public => AddrKind::PUBLIC,
+            AddressKind::Random => AddrKind::RANDOM,
+        },
+        addr: BdAddr::new(target.address),
+    };
+    let config = ConnectConfig {
+        connect_params: Default::default(),
+        scan_config: ScanConfig {
+            filter_accept_list: &[(address.kind, &address.addr)],
+            timeout: CONNECT_TIMEOUT,
+            ..Default::default()
+        },
+    };
+    let mut backoff = Backoff::new();
+    loop {
+        let connection = {
+            let mut shared = central.lock().await;
+            let central = shared.as_mut().expect("central is put back after scanning");
+            central.connect(&config).await
+        };
+        match connection {
+            Ok(conn) => {
+                info!("[peer] connected to {:?}", address);
+                update_peer(index, |peer| peer.connected = true);
+                if let Err(e) = subscribe(stack, &conn, index, target, &mut backoff).await {
+                    warn!("[peer] {:?}: {:?}", address, defmt::Debug2Format(&e));
+                }
+                update_peer(index, |peer| peer.connected = false);
+                info!("[peer] disconnected from {:?}", address);
+            }
+            Err(e) => warn!(
+                "[peer] connecting to {:?} failed: {:?}",
+                address,
+                defmt::Debug2Format(&e)
+            ),
+        }
+        Timer::after_millis(backoff.next_delay()).await;
+    }
+}
+
+/// Subscribe to the target characteristic and record its values until the connection closes.
+async fn subscribe<'a, C: BleController>(
+    stack: &'a Stack<'a, C, DefaultPacketPool>,
+    conn: &Connection<'a, DefaultPacketPool>,
+    index: usize,
+    target: &Target,
+    backoff: &mut Backoff,
+) -> Result<(), BleHostError<C::Error>> {
+    let client = GattClient::<C, DefaultPacketPool, PEER_SERVICES_MAX>::new(stack, conn).await?;
+    let listen = peer::listen(&client, target, backoff, |data| {
+        record_value(index, target, data)
+    });
+    match select(client.task(), listen).await {
+        Either::First(result) => result,
+        Either::Second(result) => result,
+    }
+}
+
+impl<C: BleController> PeerClient for GattClient<'_, C, DefaultPacketPool, PEER_SERVICES_MAX> {
+    type Error = BleHostError<C::Error>;
+    type Characteristic = Characteristic<u8>;
+
+    async fn find(
+        &self,
+        service: PeerUuid,
+        characteristic: PeerUuid,
+    ) -> Result<Option<Characteristic<u8>>, Self::Error> {
+        let services = self.services_by_uuid(&uuid(service)).await?;
+        let Some(service) = services.first() else {
+            return Ok(None);
+        };
+        let characteristic = self
+            .characteristic_by_uuid(service, &uuid(characteristic))
+            .await?;
+        Ok(Some(characteristic))
+    }
+
+    async fn read(
+        &self,
+        characteristic: &Characteristic<u8>,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        self.read_characteristic(characteristic, buf).await
+    }
+
+    async fn subscribe(
+        &self,
+        characteristic: &Characteristic<u8>,
+        subscribed: impl FnOnce(),
+        mut on_value: impl FnMut(&[u8]),
+    ) -> Result<(), Self::Error> {
+        let mut listener = GattClient::subscribe(self, characteristic, false).await?;
+        subscribed();
+        loop {
+            let notification = listener.next().await;
+            on_value(notification.as_ref());
+        }
+    }
+}
+
+fn uuid(uuid: PeerUuid) -> Uuid {
+    match uuid {
+        PeerUuid::Short(uuid) => Uuid::new_short(uuid),
+        PeerUuid::Long(uuid) => Uuid::new_long(uuid),
+    }
+}
+
+fn record_value(index: usize, target: &Target, data: &[u8]) {
+    if target.characteristic == PeerUuid::Short(HEART_RATE_MEASUREMENT) {
+        if let Some(bpm) = peer::heart_rate(data) {
+            info!("[peer] heart rate {} bpm", bpm);
+        }
+    }
+    update_peer(index, |peer| peer.set(data));
+}
+
+fn update_peer(index: usize, update: impl FnOnce(&mut peer::PeerValue)) {
+    PEER_VALUES.lock(|peers| update(&mut peers.borrow_mut()[index]));
+    PEER_UPDATED.signal(());
+}
+
 /// Stream Events until the connection closes.
 ///
 /// This function will handle the GATT events and process them.
@@ -120,30 +873,175 @@ async fn gatt_events_task<P: PacketPool>(
     conn: &GattConnection<'_, '_, P>,
 ) -> Result<(), Error> {
     let level = server.battery_service.level;
+    let current_time = server.current_time_service.current_time;
+    let history_control = &server.history_service.control;
+    let environmental_sensing = &server.environmental_sensing_service;
+    let alarm_hysteresis = environmental_sensing.alarm_hysteresis;
+    let high_trigger = environmental_sensing.high_trigger.handle();
+    let low_trigger = environmental_sensing.low_trigger.handle();
+    let es_configuration = environmental_sensing.es_configuration.handle();
+    let humidity = environmental_sensing.humidity;
+    let pressure = environmental_sensing.pressure;
+    let display_settings = server.settings_service.display_settings;
+    let advertising_settings = server.settings_service.advertising_settings;
+    let throughput_control = &server.throughput_service.control;
+    let throughput_data = &server.throughput_service.data;
     let reason = loop {
         match conn.next().await {
             GattConnectionEvent::Disconnected { reason } => break reason,
+            GattConnectionEvent::ConnectionParamsUpdated {
+                conn_interval,
+                peripheral_latency,
+                supervision_timeout,
+            } => {
+                info!(
+                    "[gatt] connection interval {} us, latency {}, timeout {} ms",
+                    conn_interval.as_micros(),
+                    peripheral_latency,
+                    supervision_timeout.as_millis()
+                );
+                update_link(server, |link| {
+                    link.interval_us = conn_interval.as_micros() as u32;
+                    link.latency = peripheral_latency;
+                    link.timeout_ms = supervision_timeout.as_millis() as u32;
+                });
+            }
             GattConnectionEvent::Gatt { event } => {
+                LINK_ACTIVITY.signal(());
+                // invalid writes are refused with an ATT error, so the value is not stored
+                let mut rejection = None;
                 match &event {
                     GattEvent::Read(event) => {
                         if event.handle() == level.handle {
                             let value = server.get(&level);
                             info!("[gatt] Read Event to Level Characteristic: {:?}", value);
+                        } else if event.handle() == current_time.handle {
+                            // refresh the stored value so the reply carries the time of the read
+                            let now = clock::now_unix_ms().unwrap_or(0);
+                            let _ = server.set(&current_time, &clock::encode_current_time(now, 0));
+                        } else if event.handle() == humidity.handle
+                            || event.handle() == pressure.handle
+                        {
+                            // only the quantities of the detected sensors are registered
+                            let quantity = if event.handle() == humidity.handle {
+                                Quantity::Humidity
+                            } else {
+                                Quantity::Pressure
+                            };
+                            let sensors = SENSORS.try_get().unwrap_or_default();
+                            if !sensor::measures(&sensors, quantity) {
+                                rejection = Some(AttErrorCode::READ_NOT_PERMITTED);
+                            }
                         }
                     }
                     GattEvent::Write(event) => {
+                        USER_ACTIVITY.signal(());
                         if event.handle() == level.handle {
                             info!(
                                 "[gatt] Write Event to Level Characteristic: {:?}",
                                 event.data()
                             );
+                        } else if event.handle() == current_time.handle {
+                            match clock::decode_current_time(event.data()) {
+                                Some(unix_ms) => clock::set_unix_ms(unix_ms, TimeSource::Gatt),
+                                None => warn!("[gatt] invalid Current Time: {:?}", event.data()),
+                            }
+                        } else if event.handle() == high_trigger || event.handle() == low_trigger {
+                            let trigger = if event.handle() == high_trigger {
+                                Trigger::High
+                            } else {
+                                Trigger::Low
+                            };
+                            let thresholds = ALARM_THRESHOLDS.try_get().unwrap_or_default();
+                            match thresholds.with_trigger(trigger, event.data()) {
+                                Ok(thresholds) => ALARM_THRESHOLDS.sender().send(thresholds),
+                                Err(e) => {
+                                    warn!(
+                                        "[gatt] invalid {:?} trigger {:?}: {:?}",
+                                        trigger,
+                                        event.data(),
+                                        e
+                                    );
+                                    rejection = Some(trigger_error(e));
+                                }
+                            }
+                        } else if event.handle() == es_configuration {
+                            if event.data() != [CONFIGURATION_OR] {
+                                warn!("[gatt] unsupported ES Configuration: {:?}", event.data());
+                                rejection = Some(AttErrorCode::WRITE_REQUEST_REJECTED);
+                            }
+                        } else if event.handle() == alarm_hysteresis.handle {
+                            match <[u8; 2]>::try_from(event.data()) {
+                                Ok(hysteresis) => {
+                                    let thresholds = ALARM_THRESHOLDS.try_get().unwrap_or_default();
+                                    ALARM_THRESHOLDS.sender().send(Thresholds {
+                                        hysteresis: u16::from_le_bytes(hysteresis),
+                                        ..thresholds
+                                    });
+                                }
+                                Err(_) => {
+                                    rejection = Some(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)
+                                }
+                            }
+                        } else if event.handle() == display_settings.handle {
+                            match DisplaySettings::decode(event.data()) {
+                                Some(display) => {
+                                    let mut settings = SETTINGS.try_get().unwrap_or_default();
+                                    settings.display = display;
+                                    SETTINGS.sender().send(settings);
+                                }
+                                None => {
+                                    warn!("[gatt] invalid display settings: {:?}", event.data());
+                                    rejection = Some(AttErrorCode::WRITE_REQUEST_REJECTED);
+                                }
+                            }
+                        } else if event.handle() == advertising_settings.handle {
+                            // applied the next time advertising starts, i.e. after disconnecting
+                            match AdvertisingSettings::decode(event.data()) {
+                                Some(advertising) => {
+                                    let mut settings = SETTINGS.try_get().unwrap_or_default();
+                                    settings.advertising = advertising;
+                                    SETTINGS.sender().send(settings);
+                                }
+                                None => {
+                                    warn!(
+                                        "[gatt] invalid advertising settings: {:?}",
+                                        event.data()
+                                    );
+                                    rejection = Some(AttErrorCode::WRITE_REQUEST_REJECTED);
+                                }
+                            }
+                        } else if event.handle() == throughput_data.handle {
+                            THROUGHPUT_RECEIVED.lock(|meter| {
+                                if let Some(meter) = meter.borrow_mut().as_mut() {
+                                    meter.add(event.data().len());
+                                }
+                            });
+                        } else if event.handle() == throughput_control.handle {
+                            match Request::parse(event.data()) {
+                                Some(request) => THROUGHPUT_REQUEST.signal(request),
+                                None => {
+                                    warn!("[gatt] invalid throughput request: {:?}", event.data());
+                                    rejection = Some(AttErrorCode::WRITE_REQUEST_REJECTED);
+                                }
+                            }
+                        } else if event.handle() == history_control.handle {
+                            let op = event.data().first().copied().unwrap_or(0);
+                            HISTORY_REQUEST.signal(
+                                RecordRequest::parse(event.data())
+                                    .map_err(|value| racp_response(op, value)),
+                            );
                         }
                     }
                     _ => {}
                 };
                 // This step is also performed at drop(), but writing it explicitly is necessary
                 // in order to ensure reply is sent.
-                match event.accept() {
+                let reply = match rejection {
+                    Some(code) => event.reject(code),
+                    None => event.accept(),
+                };
+                match reply {
                     Ok(reply) => reply.send().await,
                     Err(e) => warn!("[gatt] error sending response: {:?}", e),
                 };
@@ -152,72 +1050,378 @@ async fn gatt_events_task<P: PacketPool>(
         }
     };
     info!("[gatt] disconnected: {:?}", reason);
+    // a timeout rather than a disconnect from the central means it went out of range
+    if leash_enabled() && reason == bt_hci::param::Status::CONN_TIMEOUT {
+        warn!("[gatt] central lost, leash alarm");
+        LEASH.lock(|leash| leash.borrow_mut().lost());
+    }
     Ok(())
 }
 
-/// Create an advertiser to use to connect to a BLE Central, and wait for it to connect.
-async fn advertise<'values, 'server, C: Controller>(
+/// ATT error for a refused trigger setting.
+fn trigger_error(e: TriggerError) -> AttErrorCode {
+    match e {
+        TriggerError::Length => AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH,
+        TriggerError::Condition => AttErrorCode::WRITE_REQUEST_REJECTED,
+        TriggerError::Range => AttErrorCode::OUT_OF_RANGE,
+    }
+}
+
+/// Advertise with the [`AdvertisingSettings`] in effect and wait for a central to connect.
+///
+/// Advertising restarts whenever the status record changes, so scanners see fresh values.
+/// In beacon mode a beacon burst goes out every [`STATUS_REFRESH`] in between, or with
+/// extended advertising continuously in a second advertising set. Returns `None` once the
+/// advertising timeout runs out.
+async fn advertise<'values, 'server, C: BleController>(
     name: &'values str,
     peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
     server: &'server Server<'values>,
-) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
-    let mut advertiser_data = [0; 31];
-
-    let len_adv = AdStructure::encode_slice(
-        &[
-            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-            AdStructure::ServiceUuids128(&[SERVICE_UUID]),
-        ],
-        &mut advertiser_data[..],
-    )?;
-
-    let mut scan_rsp_data = [0u8; 31];
-    let len_scan = AdStructure::encode_slice(
-        &[
-            AdStructure::CompleteLocalName(name.as_bytes()),
-            AdStructure::ManufacturerSpecificData { company_identifier: 0x0245, payload: &[] },
-        ],
-        &mut scan_rsp_data[..],
-    )?;
-
-
-    let advertiser = peripheral
+    counters: &mut BeaconCounters,
+) -> Result<Option<GattConnection<'values, 'server, DefaultPacketPool>>, BleHostError<C::Error>> {
+    let settings = SETTINGS.try_get().unwrap_or_default().advertising;
+    let scan_data = advertising::scan_response(&settings.fields, name);
+    let deadline = (settings.timeout_secs != 0)
+        .then(|| Instant::now() + Duration::from_secs(settings.timeout_secs as u64));
+    let extended = settings.extended && cfg!(feature = "extended-advertising");
+    if settings.extended && !extended {
+        warn!("[adv] built without extended-advertising, using legacy advertising");
+    }
+
+    loop {
+        RELAY_PENDING.reset();
+        relay_queued(peripheral).await?;
+        if settings.beacon != Beacon::Off && !extended {
+            beacon(name, peripheral, BEACON_BURST, counters).await?;
+        }
+        let status = current_status();
+        let started = Instant::now();
+        #[cfg(feature = "extended-advertising")]
+        let advertiser = if extended {
+            advertise_extended(name, peripheral, &settings, &status, counters).await?
+        } else {
+            advertise_legacy(peripheral, &settings, &status, &scan_data).await?
+        };
+        #[cfg(not(feature = "extended-advertising"))]
+        let advertiser = advertise_legacy(peripheral, &settings, &status, &scan_data).await?;
+        info!("[adv] advertising {:?}", status);
+
+        let changed = async {
+            loop {
+                // relayed records go out right away
+                if let Either::Second(()) =
+                    select(Timer::after(STATUS_REFRESH), RELAY_PENDING.wait()).await
+                {
+                    break;
+                }
+                if settings.beacon != Beacon::Off || current_status() != status {
+                    break;
+                }
+            }
+        };
+        let timeout = async {
+            match deadline {
+                Some(deadline) => Timer::at(deadline).await,
+                None => core::future::pending().await,
+            }
+        };
+        // dropping the advertiser stops advertising
+        match select3(advertiser.accept(), changed, timeout).await {
+            Either3::First(conn) => {
+                let conn = conn?.with_attribute_server(server)?;
+                info!("[adv] connection established");
+                return Ok(Some(conn));
+            }
+            Either3::Second(()) => {
+                if extended && settings.beacon != Beacon::Off {
+                    counters.sent(started.elapsed(), &settings);
+                }
+            }
+            Either3::Third(()) => return Ok(None),
+        }
+    }
+}
+
+/// Start legacy connectable advertising with the status record in the advertising data.
+async fn advertise_legacy<'values, C: BleController>(
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    settings: &AdvertisingSettings,
+    status: &Status,
+    scan_data: &AdData,
+) -> Result<Advertiser<'values, C, DefaultPacketPool>, BleHostError<C::Error>> {
+    let adv_data = advertising::advertising_data(&settings.fields, &SERVICE_UUID, status);
+    peripheral
         .advertise(
-            &Default::default(),
+            &parameters(settings),
             Advertisement::ConnectableScannableUndirected {
-                adv_data: &advertiser_data[..len_adv],
-                scan_data: &scan_rsp_data[..len_scan],
+                adv_data: adv_data.as_bytes(),
+                scan_data: scan_data.as_bytes(),
+            },
+        )
+        .await
+}
+
+/// Start extended advertising: a connectable set with everything in one packet, on the
+/// Coded PHY in long range mode, and a non-connectable set with the beacon if one is selected.
+/// The beacon set stays on the 1M PHY, most receivers do not scan the Coded PHY.
+#[cfg(feature = "extended-advertising")]
+async fn advertise_extended<'values, C: BleController>(
+    name: &str,
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    settings: &AdvertisingSettings,
+    status: &Status,
+    counters: &BeaconCounters,
+) -> Result<Advertiser<'values, C, DefaultPacketPool>, BleHostError<C::Error>> {
+    let adv_data = advertising::extended_data(&settings.fields, name, &SERVICE_UUID, status);
+    let phy = if settings.long_range {
+        PhyKind::LeCoded
+    } else {
+        PhyKind::Le1M
+    };
+    let connectable = AdvertisementSet {
+        params: AdvertisementParameters {
+            primary_phy: phy,
+            secondary_phy: phy,
+            ..parameters(settings)
+        },
+        data: Advertisement::ExtConnectableNonscannableUndirected {
+            adv_data: adv_data.as_bytes(),
+        },
+    };
+    match beacon_data(settings, status, counters) {
+        Some(beacon) => {
+            let sets = [
+                connectable,
+                AdvertisementSet {
+                    params: parameters(settings),
+                    data: Advertisement::ExtNonconnectableNonscannableUndirected {
+                        anonymous: false,
+                        adv_data: beacon.as_bytes(),
+                    },
+                },
+            ];
+            let mut handles = AdvertisementSet::handles(&sets);
+            peripheral.advertise_ext(&sets, &mut handles).await
+        }
+        None => {
+            let sets = [connectable];
+            let mut handles = AdvertisementSet::handles(&sets);
+            peripheral.advertise_ext(&sets, &mut handles).await
+        }
+    }
+}
+
+/// Carried over between beacon bursts.
+#[derive(Default)]
+struct BeaconCounters {
+    /// BTHome packet ID, receivers drop repeats.
+    packet_id: u8,
+    /// Eddystone-TLM advertising count, estimated from the burst lengths and the interval.
+    adv_count: u32,
+}
+
+impl BeaconCounters {
+    /// Count a beacon broadcast for `duration`, the next one gets a new packet ID.
+    fn sent(&mut self, duration: Duration, settings: &AdvertisingSettings) {
+        self.packet_id = self.packet_id.wrapping_add(1);
+        let sent = duration.as_millis() / settings.interval_max_ms as u64;
+        self.adv_count = self.adv_count.wrapping_add(sent as u32);
+    }
+}
+
+/// The selected beacon, `None` if beacons are off.
+fn beacon_data(
+    settings: &AdvertisingSettings,
+    status: &Status,
+    counters: &BeaconCounters,
+) -> Option<AdData> {
+    match settings.beacon {
+        Beacon::Off => None,
+        Beacon::BtHome => Some(advertising::bthome(
+            status,
+            HUMIDITY.try_get(),
+            PRESSURE.try_get(),
+            counters.packet_id,
+        )),
+        Beacon::EddystoneTlm => Some(advertising::eddystone_tlm(&Telemetry {
+            battery_mv: BATTERY.try_get().filter(|b| b.is_present()).map(|b| b.mv),
+            temperature: status.temperature,
+            adv_count: counters.adv_count,
+            uptime_ds: (Instant::now().as_millis() / 100) as u32,
+        })),
+    }
+}
+
+/// Broadcast the configured beacon, non-connectable, for `duration`.
+async fn beacon<'values, C: BleController>(
+    name: &str,
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    duration: Duration,
+    counters: &mut BeaconCounters,
+) -> Result<(), BleHostError<C::Error>> {
+    let settings = SETTINGS.try_get().unwrap_or_default().advertising;
+    let Some(adv_data) = beacon_data(&settings, &current_status(), counters) else {
+        Timer::after(duration).await;
+        return Ok(());
+    };
+    let scan_data = advertising::scan_response(&settings.fields, name);
+    let advertisement = if scan_data.as_bytes().is_empty() {
+        Advertisement::NonconnectableNonscannableUndirected {
+            adv_data: adv_data.as_bytes(),
+        }
+    } else {
+        Advertisement::NonconnectableScannableUndirected {
+            adv_data: adv_data.as_bytes(),
+            scan_data: scan_data.as_bytes(),
+        }
+    };
+    // advertising stops when the advertiser is dropped
+    let _advertiser = peripheral
+        .advertise(&parameters(&settings), advertisement)
+        .await?;
+    Timer::after(duration).await;
+    counters.sent(duration, &settings);
+    Ok(())
+}
+
+/// Relay status records while a central is connected, when connectable advertising is off.
+async fn relay_task<C: BleController>(peripheral: &mut Peripheral<'_, C, DefaultPacketPool>) {
+    loop {
+        RELAY_PENDING.reset();
+        if let Err(e) = relay_queued(peripheral).await {
+            warn!("[relay] broadcast failed: {:?}", defmt::Debug2Format(&e));
+        }
+        RELAY_PENDING.wait().await;
+    }
+}
+
+/// Broadcast every status record queued in [`RELAY`].
+async fn relay_queued<C: BleController>(
+    peripheral: &mut Peripheral<'_, C, DefaultPacketPool>,
+) -> Result<(), BleHostError<C::Error>> {
+    while let Some(frame) = RELAY.lock(|relay| relay.borrow_mut().pop()) {
+        relay_burst(peripheral, &frame).await?;
+    }
+    Ok(())
+}
+
+/// Broadcast a relayed status record, non-connectable, for [`RELAY_BURST`].
+async fn relay_burst<C: BleController>(
+    peripheral: &mut Peripheral<'_, C, DefaultPacketPool>,
+    frame: &Frame,
+) -> Result<(), BleHostError<C::Error>> {
+    let settings = SETTINGS.try_get().unwrap_or_default().advertising;
+    let adv_data = frame.advertising_data();
+    // advertising stops when the advertiser is dropped
+    let _advertiser = peripheral
+        .advertise(
+            &AdvertisementParameters {
+                interval_min: RELAY_INTERVAL,
+                interval_max: RELAY_INTERVAL,
+                ..parameters(&settings)
+            },
+            Advertisement::NonconnectableNonscannableUndirected {
+                adv_data: adv_data.as_bytes(),
             },
         )
         .await?;
+    Timer::after(RELAY_BURST).await;
+    Ok(())
+}
 
-    info!("[adv] advertising");
-    let conn = advertiser.accept().await?.with_attribute_server(server)?;
-    info!("[adv] connection established");
-    Ok(conn)
+fn parameters(settings: &AdvertisingSettings) -> AdvertisementParameters {
+    AdvertisementParameters {
+        interval_min: Duration::from_millis(settings.interval_min_ms as u64),
+        interval_max: Duration::from_millis(settings.interval_max_ms as u64),
+        tx_power: tx_power(settings.tx_power_dbm),
+        ..Default::default()
+    }
 }
 
-/// Example task to use the BLE notifier interface.
-/// This task will notify the connected central of a counter value every 2 seconds.
-/// It will also read the RSSI value every 2 seconds.
-/// and will stop when the connection is closed by the central or an error occurs.
+/// The status record put in the advertising data.
+fn current_status() -> Status {
+    Status {
+        temperature: TEMPERATURE.try_get(),
+        battery: BATTERY
+            .try_get()
+            .filter(|b| b.is_present())
+            .map(|b| b.percent),
+        alarm: ALARM.try_get().unwrap_or(AlarmState::Normal),
+        fault: FAULT.try_get().unwrap_or(false),
+    }
+}
+
+/// The highest TX power level the host knows that is not above `dbm`.
+fn tx_power(dbm: i8) -> TxPower {
+    match dbm {
+        ..=-21 => TxPower::Minus40dBm,
+        -20..=-17 => TxPower::Minus20dBm,
+        -16..=-13 => TxPower::Minus16dBm,
+        -12..=-9 => TxPower::Minus12dBm,
+        -8..=-5 => TxPower::Minus8dBm,
+        -4..=-1 => TxPower::Minus4dBm,
+        0..=1 => TxPower::ZerodBm,
+        2 => TxPower::Plus2dBm,
+        3 => TxPower::Plus3dBm,
+        4 => TxPower::Plus4dBm,
+        5 => TxPower::Plus5dBm,
+        6 => TxPower::Plus6dBm,
+        7 => TxPower::Plus7dBm,
+        8..=9 => TxPower::Plus8dBm,
+        10..=11 => TxPower::Plus10dBm,
+        12..=13 => TxPower::Plus12dBm,
+        14..=15 => TxPower::Plus14dBm,
+        16..=17 => TxPower::Plus16dBm,
+        18..=19 => TxPower::Plus18dBm,
+        20.. => TxPower::Plus20dBm,
+    }
+}
+
+/// Read the RSSI value every 2 seconds for the link diagnostics and the proximity zone,
+/// stops when the connection is closed by the central or an error occurs.
 async fn custom_task<C: Controller, P: PacketPool>(
     server: &Server<'_>,
     conn: &GattConnection<'_, '_, P>,
     stack: &Stack<'_, C, P>,
 ) {
-    let mut tick: u8 = 0;
-    let level = server.battery_service.level;
+    let proximity_value = server.diagnostics_service.proximity;
+    let mut rssi = Rssi::new();
+    let mut proximity = Proximity::Unknown;
     loop {
-        tick = tick.wrapping_add(1);
-        info!("[custom_task] notifying connection of tick {}", tick);
-        if level.notify(conn, &tick).await.is_err() {
-            info!("[custom_task] error notifying connection");
-            break;
-        };
         // read RSSI (Received Signal Strength Indicator) of the connection.
-        if let Ok(rssi) = conn.raw().rssi(stack).await {
-            info!("[custom_task] RSSI: {:?}", rssi);
+        if let Ok(sample) = conn.raw().rssi(stack).await {
+            let smoothed = rssi.update(sample);
+            let previous = proximity;
+            if let Some(smoothed) = smoothed {
+                proximity = Proximity::classify(smoothed, proximity);
+            }
+            info!(
+                "[custom_task] RSSI: {:?}, smoothed {:?}, {:?}",
+                sample, smoothed, proximity
+            );
+            // the MTU exchange happens whenever the central gets to it
+            update_link(server, |link| {
+                link.rssi = smoothed;
+                link.proximity = proximity;
+                link.mtu = conn.raw().att_mtu();
+            });
+            let now = Instant::now().as_millis();
+            let (tripped, changed) = LEASH.lock(|leash| {
+                let mut leash = leash.borrow_mut();
+                let was = leash.is_tripped();
+                let tripped = leash_enabled() && leash.update(proximity, now);
+                (tripped, tripped != was)
+            });
+            if changed {
+                warn!("[custom_task] leash alarm {}", tripped);
+            }
+            let value = proximity::encode(smoothed, proximity, tripped);
+            let _ = server.set(&proximity_value, &value);
+            let notify = proximity != previous || changed;
+            if notify && proximity_value.notify(conn, &value).await.is_err() {
+                info!("[custom_task] error notifying connection");
+                break;
+            }
         } else {
             info!("[custom_task] error getting RSSI");
             break;
@@ -225,3 +1429,443 @@ async fn custom_task<C: Controller, P: PacketPool>(
         Timer::after_secs(2).await;
     }
 }
+
+/// Notify the Current Time when SNTP set the clock.
+async fn current_time_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>) {
+    let current_time = server.current_time_service.current_time;
+    // a sync before the connection is not news to the central
+    clock::TIME_SYNCED.reset();
+    loop {
+        clock::TIME_SYNCED.wait().await;
+        let Some(now) = clock::now_unix_ms() else {
+            continue;
+        };
+        let value = clock::encode_current_time(now, clock::ADJUST_EXTERNAL_REFERENCE);
+        let _ = server.set(&current_time, &value);
+        if current_time.notify(conn, &value).await.is_err() {
+            info!("[time] error notifying connection");
+            break;
+        }
+    }
+}
+
+/// Keep the Battery Level and Power State up to date and notify changes. Without a battery
+/// monitor there is never a reading and the values stay as they are.
+async fn battery_level_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>) {
+    let level = server.battery_service.level;
+    let power_state = server.battery_service.power_state;
+    let Some(mut readings) = BATTERY.receiver() else {
+        warn!("[battery] no BATTERY receiver left");
+        return core::future::pending().await;
+    };
+    if let Some(battery) = BATTERY.try_get() {
+        let _ = server.set(&level, &battery.percent);
+        let _ = server.set(&power_state, &battery.power_state());
+    }
+    loop {
+        let battery = readings.changed().await;
+        let previous_level = server.get(&level).unwrap_or_default();
+        let previous_state = server.get(&power_state).unwrap_or_default();
+        let mut result = Ok(());
+        if battery.percent != previous_level {
+            let _ = server.set(&level, &battery.percent);
+            result = level.notify(conn, &battery.percent).await;
+        }
+        if result.is_ok() && battery.power_state() != previous_state {
+            let _ = server.set(&power_state, &battery.power_state());
+            result = power_state.notify(conn, &battery.power_state()).await;
+        }
+        if result.is_err() {
+            info!("[battery] error notifying connection");
+            break;
+        }
+    }
+}
+
+/// Run throughput tests and notify their results.
+async fn throughput_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>) {
+    let data = &server.throughput_service.data;
+    let result = server.throughput_service.result;
+    THROUGHPUT_REQUEST.reset();
+    loop {
+        let request = THROUGHPUT_REQUEST.wait().await;
+        info!("[throughput] {:?}", request);
+        let started = Instant::now();
+        let end = started + Duration::from_secs(request.secs as u64);
+        let meter = match request.direction {
+            Direction::Send => {
+                let mut meter = Meter::new(Direction::Send, started.as_millis());
+                let len = (conn.raw().att_mtu() as usize - 3).min(CHUNK_MAX);
+                let mut chunk: heapless::Vec<u8, CHUNK_MAX> = heapless::Vec::new();
+                let _ = chunk.resize(len, 0);
+                while Instant::now() < end {
+                    // a sequence number lets the central spot lost notifications
+                    chunk[0] = meter.packets as u8;
+                    if data.notify(conn, &chunk).await.is_err() {
+                        info!("[throughput] error notifying connection");
+                        return;
+                    }
+                    meter.add(len);
+                }
+                meter
+            }
+            Direction::Receive => {
+                let meter = Meter::new(Direction::Receive, started.as_millis());
+                THROUGHPUT_RECEIVED.lock(|received| *received.borrow_mut() = Some(meter));
+                Timer::at(end).await;
+                THROUGHPUT_RECEIVED
+                    .lock(|received| received.borrow_mut().take())
+                    .unwrap_or(meter)
+            }
+        };
+        let now = Instant::now().as_millis();
+        info!(
+            "[throughput] {} bytes in {} packets, {} B/s",
+            meter.bytes,
+            meter.packets,
+            meter.rate(now)
+        );
+        let encoded = meter.encode(now);
+        let _ = server.set(&result, &encoded);
+        if result.notify(conn, &encoded).await.is_err() {
+            info!("[throughput] error notifying connection");
+            return;
+        }
+    }
+}
+
+/// Accept L2CAP channels from the central on the configured PSM and connect them to
+/// [`STREAM`], which echoes what the central sends.
+async fn stream_task<'a, C: BleController, P: PacketPool>(
+    conn: &GattConnection<'_, '_, P>,
+    stack: &'a Stack<'a, C, P>,
+) {
+    let Some(psm) = stream::parse_psm(L2CAP_PSM) else {
+        warn!("[l2cap] invalid PSM {}, stream disabled", L2CAP_PSM);
+        return core::future::pending().await;
+    };
+    let config = L2capChannelConfig {
+        mtu: Some(SDU_MAX as u16),
+        // hand credits back in batches as the stream buffer drains
+        flow_policy: CreditFlowPolicy::Every(CREDITS / 2),
+        initial_credits: Some(CREDITS),
+        ..Default::default()
+    };
+    loop {
+        let channel = match L2capChannel::accept(stack, conn.raw(), &[psm], &config).await {
+            Ok(channel) => channel,
+            Err(e) => {
+                // also when the connection closes, which ends the other tasks
+                warn!("[l2cap] accept failed: {:?}", defmt::Debug2Format(&e));
+                return core::future::pending().await;
+            }
+        };
+        info!("[l2cap] channel open on PSM {:#x}", psm);
+        STREAM.set_open(true);
+        let (mut writer, mut reader) = channel.split();
+        let receive = async {
+            let mut sdu = [0u8; SDU_MAX];
+            loop {
+                match reader.receive(stack, &mut sdu).await {
+                    Ok(len) => STREAM.deliver(&sdu[..len]).await,
+                    Err(e) => {
+                        info!("[l2cap] receive ended: {:?}", defmt::Debug2Format(&e));
+                        break;
+                    }
+                }
+            }
+        };
+        let send = async {
+            let mut sdu = [0u8; SDU_MAX];
+            loop {
+                let len = STREAM.next_sdu(&mut sdu).await;
+                if let Err(e) = writer.send(stack, &sdu[..len]).await {
+                    info!("[l2cap] send ended: {:?}", defmt::Debug2Format(&e));
+                    break;
+                }
+            }
+        };
+        select3(receive, send, STREAM.echo()).await;
+        STREAM.set_open(false);
+        info!("[l2cap] channel closed");
+    }
+}
+
+/// Request fast connection parameters while there is GATT traffic and slow ones once the
+/// link has been idle for a while, see [`LinkPolicy`].
+async fn link_task<C: BleController, P: PacketPool>(
+    conn: &GattConnection<'_, '_, P>,
+    stack: &Stack<'_, C, P>,
+) {
+    let mut policy = LinkPolicy::new(Instant::now().as_millis());
+    LINK_ACTIVITY.reset();
+    loop {
+        let now = Instant::now().as_millis();
+        if let Some(profile) = policy.poll(now) {
+            info!("[link] requesting {:?}", profile);
+            let params = connect_params(&profile);
+            // the central may refuse or pick other values, they are tracked as they come
+            if let Err(e) = conn.raw().update_connection_params(stack, &params).await {
+                warn!("[link] update failed: {:?}", defmt::Debug2Format(&e));
+            }
+        }
+        let due = async {
+            match policy.due_in(Instant::now().as_millis()) {
+                Some(ms) => Timer::after_millis(ms).await,
+                None => core::future::pending().await,
+            }
+        };
+        if let Either::Second(()) = select(due, LINK_ACTIVITY.wait()).await {
+            policy.activity(Instant::now().as_millis());
+        }
+    }
+}
+
+fn connect_params(profile: &Profile) -> ConnectParams {
+    ConnectParams {
+        min_connection_interval: Duration::from_micros(profile.interval_min_us as u64),
+        max_connection_interval: Duration::from_micros(profile.interval_max_us as u64),
+        max_latency: profile.latency,
+        supervision_timeout: Duration::from_millis(profile.timeout_ms as u64),
+        ..Default::default()
+    }
+}
+
+/// Update the link diagnostics shown on the display and in the diagnostics service.
+fn update_link(server: &Server<'_>, update: impl FnOnce(&mut Link)) {
+    let mut link = LINK.try_get().unwrap_or_default();
+    update(&mut link);
+    let _ = server.set(&server.diagnostics_service.link, &link.encode());
+    LINK.sender().send(link);
+}
+
+/// Serve temperature history requests written to the record access control point.
+///
+/// Timestamps are Unix seconds once the clock is synced and seconds since boot before that,
+/// both in the range filter and in the streamed records.
+async fn history_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>) {
+    let control = &server.history_service.control;
+    let records = server.history_service.records;
+    loop {
+        let request = match HISTORY_REQUEST.wait().await {
+            Ok(request) => request,
+            Err(response) => {
+                if notify_racp(control, conn, response).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        info!("[history] request {:?}", request);
+        let range = match request {
+            RecordRequest::Report { from, to } | RecordRequest::Count { from, to } => {
+                to_uptime_range(from, to)
+            }
+            // an Abort while streaming is handled below, here there is nothing to abort
+            RecordRequest::Abort => {
+                let response = racp_response(racp::ABORT, racp::SUCCESS);
+                if notify_racp(control, conn, response).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let response = match (request, range) {
+            (RecordRequest::Count { .. }, range) => {
+                let count = range.map_or(0, |(from, to)| {
+                    TEMPERATURE_HISTORY.lock(|history| history.borrow().range(from, to).count())
+                });
+                racp_count(count.min(u16::MAX as usize) as u16)
+            }
+            (_, None) => racp_response(request.op_code(), racp::NO_RECORDS),
+            (_, Some((from, to))) => {
+                let mut sent = 0;
+                let mut next = from;
+                let mut aborted = false;
+                while let Some(summary) =
+                    TEMPERATURE_HISTORY.lock(|history| history.borrow().first_in_range(next, to))
+                {
+                    let timestamp = clock::unix_secs_at_uptime(summary.start)
+                        .map(|unix| unix as u32)
+                        .unwrap_or(summary.start);
+                    let record = summary.encode(timestamp);
+                    // a request written meanwhile interrupts the record, which is sent again
+                    // unless it was an Abort. Other requests are not queued, they are refused
+                    // with Procedure Not Completed and have to be written again afterwards.
+                    match select(records.notify(conn, &record), HISTORY_REQUEST.wait()).await {
+                        Either::First(Ok(())) => {}
+                        Either::First(Err(_)) => return,
+                        Either::Second(Ok(RecordRequest::Abort)) => {
+                            aborted = true;
+                            break;
+                        }
+                        Either::Second(other) => {
+                            let response = match other {
+                                Ok(other) => {
+                                    racp_response(other.op_code(), racp::PROCEDURE_NOT_COMPLETED)
+                                }
+                                Err(response) => response,
+                            };
+                            if notify_racp(control, conn, response).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                    }
+                    LINK_ACTIVITY.signal(());
+                    sent += 1;
+                    let Some(after) = summary.start.checked_add(1) else {
+                        break;
+                    };
+                    next = after;
+                }
+                info!("[history] sent {} records, aborted {}", sent, aborted);
+                if aborted {
+                    racp_response(racp::ABORT, racp::SUCCESS)
+                } else if sent == 0 {
+                    racp_response(request.op_code(), racp::NO_RECORDS)
+                } else {
+                    racp_response(request.op_code(), racp::SUCCESS)
+                }
+            }
+        };
+        if notify_racp(control, conn, response).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn notify_racp<P: PacketPool>(
+    control: &Characteristic<heapless::Vec<u8, 11>>,
+    conn: &GattConnection<'_, '_, P>,
+    response: [u8; 4],
+) -> Result<(), Error> {
+    // a 4 byte response always fits the 11 byte control point
+    control
+        .notify(conn, &heapless::Vec::from_slice(&response).unwrap())
+        .await
+}
+
+/// Convert a requested range to seconds since boot, the time base history is stored in.
+/// `None` if the range ends before boot, there are no records that old.
+fn to_uptime_range(from: u32, to: u32) -> Option<(u32, u32)> {
+    if (from, to) == (0, u32::MAX) || !clock::is_synced() {
+        return Some((from, to));
+    }
+    if (to as u64) < clock::unix_secs_at_uptime(0)? {
+        return None;
+    }
+    let from = clock::uptime_secs_at_unix(from as u64)?;
+    let to = clock::uptime_secs_at_unix(to as u64)?;
+    Some((from, to))
+}
+
+/// Notify readings and alarm state changes.
+async fn environment_task<P: PacketPool>(server: &Server<'_>, conn: &GattConnection<'_, '_, P>) {
+    let temperature = server.environmental_sensing_service.temperature;
+    let humidity = server.environmental_sensing_service.humidity;
+    let pressure = server.environmental_sensing_service.pressure;
+    let alarm = server.environmental_sensing_service.alarm;
+    let status = server.battery_service.status;
+    if let Some(sensors) = SENSORS.try_get() {
+        let names = sensor::names(&sensors);
+        let names = heapless::Vec::from_slice(names.as_bytes()).unwrap_or_default();
+        let _ = server.set(&server.environmental_sensing_service.sensors, &names);
+        let registry: heapless::Vec<u8, REGISTRY_LEN> =
+            sensors.iter().flat_map(|s| s.record()).collect();
+        let _ = server.set(&server.environmental_sensing_service.registry, &registry);
+    }
+    let Some(mut temperatures) = TEMPERATURE.receiver() else {
+        warn!("[environment] no TEMPERATURE receiver left");
+        return core::future::pending().await;
+    };
+    let Some(mut humidities) = HUMIDITY.receiver() else {
+        warn!("[environment] no HUMIDITY receiver left");
+        return core::future::pending().await;
+    };
+    let Some(mut pressures) = PRESSURE.receiver() else {
+        warn!("[environment] no PRESSURE receiver left");
+        return core::future::pending().await;
+    };
+    let Some(mut alarms) = ALARM.receiver() else {
+        warn!("[environment] no ALARM receiver left");
+        return core::future::pending().await;
+    };
+    loop {
+        let result = match select4(
+            temperatures.changed(),
+            humidities.changed(),
+            pressures.changed(),
+            alarms.changed(),
+        )
+        .await
+        {
+            Either4::First(value) => temperature.notify(conn, &value).await,
+            Either4::Second(value) => humidity.notify(conn, &value).await,
+            Either4::Third(value) => pressure.notify(conn, &value).await,
+            Either4::Fourth(state) => {
+                let _ = server.set(&status, &state.is_active());
+                let code = match state {
+                    AlarmState::Normal => 0,
+                    AlarmState::High => 1,
+                    AlarmState::Low => 2,
+                };
+                match status.notify(conn, &state.is_active()).await {
+                    Ok(()) => alarm.notify(conn, &code).await,
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if result.is_err() {
+            info!("[environment] error notifying connection");
+            break;
+        }
+    }
+}
+
+/// Notify the readings of nearby sensors, one per notification, every [`STATUS_REFRESH`],
+/// and the values of the first `peers` configured peripherals whenever one changes.
+async fn gateway_task<P: PacketPool>(
+    server: &Server<'_>,
+    conn: &GattConnection<'_, '_, P>,
+    peers: usize,
+) {
+    let neighbors = server.gateway_service.neighbors;
+    let peer_values = server.gateway_service.peers;
+    let mut next = Instant::now();
+    loop {
+        let now = Instant::now();
+        if now >= next {
+            next = now + STATUS_REFRESH;
+            let now = now.as_secs() as u32;
+            let entries: heapless::Vec<[u8; NEIGHBOR_LEN], NEIGHBORS_MAX> =
+                NEIGHBORS.lock(|neighbors| {
+                    let neighbors = neighbors.borrow();
+                    neighbors.iter().map(|n| n.encode(now)).collect()
+                });
+            let _ = server.set(&server.gateway_service.count, &(entries.len() as u8));
+            for entry in &entries {
+                if neighbors.notify(conn, entry).await.is_err() {
+                    info!("[gateway] error notifying connection");
+                    return;
+                }
+            }
+        }
+        let records: heapless::Vec<[u8; PEER_RECORD_LEN], PEERS_MAX> = PEER_VALUES.lock(|values| {
+            let values = values.borrow();
+            values[..peers]
+                .iter()
+                .enumerate()
+                .map(|(index, value)| value.encode(index as u8))
+                .collect()
+        });
+        for record in &records {
+            if peer_values.notify(conn, record).await.is_err() {
+                info!("[gateway] error notifying connection");
+                return;
+            }
+        }
+        select(Timer::at(next), PEER_UPDATED.wait()).await;
+    }
+}
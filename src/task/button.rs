@@ -0,0 +1,59 @@
+use defmt::info;
+use embassy_futures::select::select;
+use embassy_time::{Instant, Timer};
+use esp_hal::gpio::Input;
+
+use crate::button::{Gesture, Recognizer};
+use crate::state::{
+    BUTTON, FACTORY_RESET, FACTORY_RESET_RELEASED, LEASH, POWER_ACTIVITY, RESUME_ADVERTISING,
+};
+
+/// Recognise gestures on the BOOT button, which pulls the pin low while pressed. Short and
+/// double presses go to the display task, resume advertising after a timeout, acknowledge
+/// the leash alarm and hold off deep sleep, a long press asks `settings_task` for a factory
+/// reset.
+#[embassy_executor::task]
+pub async fn button_task(mut button: Input<'static>) {
+    let mut recognizer = Recognizer::default();
+    let mut resetting = false;
+
+    loop {
+        let pressed = button.is_low();
+        if resetting && !pressed {
+            FACTORY_RESET_RELEASED.signal(());
+            resetting = false;
+        }
+        if let Some(gesture) = recognizer.update(pressed, Instant::now().as_millis()) {
+            info!("[button] {:?}", gesture);
+            POWER_ACTIVITY.signal(());
+            match gesture {
+                Gesture::Long => {
+                    FACTORY_RESET_RELEASED.reset();
+                    FACTORY_RESET.signal(());
+                    resetting = true;
+                }
+                Gesture::Short | Gesture::Double => {
+                    BUTTON.signal(gesture);
+                    RESUME_ADVERTISING.signal(());
+                    LEASH.lock(|leash| leash.borrow_mut().acknowledge());
+                }
+            }
+        }
+
+        // wait for the level to differ from what we just read, so no edge slips through
+        // between reading and waiting
+        let change = async {
+            if pressed {
+                button.wait_for_high().await
+            } else {
+                button.wait_for_low().await
+            }
+        };
+        match recognizer.deadline() {
+            Some(deadline) => {
+                select(change, Timer::at(Instant::from_millis(deadline))).await;
+            }
+            None => change.await,
+        }
+    }
+}
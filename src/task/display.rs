@@ -1,74 +1,405 @@
 use defmt::{info, warn};
 use embassy_executor::task;
-use embassy_time::{Duration, Timer};
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::mono_font::ascii::FONT_6X9;
-use embedded_graphics::mono_font::MonoTextStyleBuilder;
+use embedded_graphics::mono_font::{MonoTextStyle, MonoTextStyleBuilder};
 use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::DrawTarget;
+use static_cell::ConstStaticCell;
 
-use crate::display::update_display;
+use crate::alarm::AlarmState;
+use crate::animation::{self, Animator, Event, Frame, Library};
+use crate::battery::Battery;
+use crate::button::Gesture;
+use crate::display::{
+    draw_alert_banner, draw_battery_banner, update_display, update_nearby_display,
+    update_pairing_display, update_readings_display, update_status_display, Page,
+};
+use crate::display_power::{DisplayPower, PowerState};
+use crate::framebuffer::{Framebuffer, PageWriter};
+use crate::link::Link;
 use crate::mock::MockDisplayType;
+use crate::power::Mode;
+use crate::qr::Payload;
+use crate::scan::Neighbor;
+use crate::sensor;
+use crate::state::{
+    ALARM, BATTERY, BLE_CONNECTED, BUTTON, HUMIDITY, LEASH, LINK, NEIGHBORS, NEIGHBORS_MAX,
+    PAIRING_ACTIVE, POWER, PRESSURE, SENSORS, SETTINGS, SHOW_PAIRING, SLEEP_READY, TEMPERATURE,
+    USER_ACTIVITY,
+};
 
 // Import the DisplayType from main
-use esp_hal::i2c::master::I2c;
-use esp_hal::Blocking;
-use ssd1306::mode::BufferedGraphicsMode;
-use ssd1306::prelude::I2CInterface;
+use crate::i2c_bus::I2cDevice;
+use display_interface::DisplayError;
+use ssd1306::mode::BasicMode;
+use ssd1306::prelude::{Brightness, I2CInterface};
 use ssd1306::size::DisplaySize128x64;
-use ssd1306::Ssd1306;
+use ssd1306::Ssd1306Async;
 
-// Define the concrete display type
-pub type DisplayType = Ssd1306<
-    I2CInterface<I2c<'static, Blocking>>,
-    DisplaySize128x64,
-    BufferedGraphicsMode<DisplaySize128x64>,
->;
+// Define the concrete display type. The frame is kept in our own Framebuffer, so the
+// driver only needs to address pages and push bytes; init it in horizontal addressing mode.
+pub type DisplayType = Ssd1306Async<I2CInterface<I2cDevice>, DisplaySize128x64, BasicMode>;
+
+impl PageWriter for DisplayType {
+    type Error = DisplayError;
+
+    async fn write_page(&mut self, page: u8, column: u8, data: &[u8]) -> Result<(), DisplayError> {
+        let row = page * 8;
+        self.set_draw_area((column, row), (column + data.len() as u8, row + 8))
+            .await?;
+        self.draw(data).await
+    }
+}
+
+// The current and last flushed frame, 2 KiB, kept out of the task arena
+static FRAMEBUFFER: ConstStaticCell<Framebuffer> = ConstStaticCell::new(Framebuffer::new());
+
+/// How long the pairing QR code stays up unless a central connects first.
+const PAIRING_SECS: u64 = 120;
+/// Log flush timings every this many frames.
+const STATS_FRAMES: u32 = 60;
+/// How often the latency probe wakes up while a flush is in flight.
+const PROBE_PERIOD: Duration = Duration::from_millis(1);
+/// Longest sleep between updates, so timeouts and new state are picked up.
+const MAX_SLEEP_MS: u64 = 1000;
 
 // Define a display wrapper that can work with the specific display type
+#[allow(clippy::large_enum_variant)] // only ever one of them, held by the display task
 pub enum DisplayWrapper {
     Real(DisplayType),
     Mock(MockDisplayType),
 }
 
+// Switch the panel on at the given contrast, or off. It keeps its memory while off.
+async fn set_power(display: &mut DisplayType, contrast: Option<u8>) -> Result<(), DisplayError> {
+    match contrast {
+        Some(contrast) => {
+            display
+                .set_brightness(Brightness::custom(2, contrast))
+                .await?;
+            display.set_display_on(true).await
+        }
+        None => display.set_display_on(false).await,
+    }
+}
+
+/// Everything a frame is drawn from.
+struct Screen<'a> {
+    page: Page,
+    cow: Frame<'a>,
+    shift: (i32, i32),
+    alarm: AlarmState,
+    temperature: i16,
+    humidity: Option<u16>,
+    pressure: Option<u32>,
+    /// `None` without a battery monitor
+    battery: Option<Battery>,
+    sensors: &'a str,
+    /// `None` while no central is connected
+    link: Option<Link>,
+    leash: bool,
+    uptime_secs: u64,
+    neighbors: &'a [Neighbor],
+    pairing: Option<&'a str>,
+}
+
+// Draw the pairing QR code or the current page. An alarm hides both behind the cow with
+// the alarm banner, a low battery gets a banner over the cow page.
+fn draw<D>(
+    display: &mut D,
+    screen: &Screen<'_>,
+    text_style: MonoTextStyle<'_, BinaryColor>,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let x_offset = 30 + screen.shift.0;
+    let y_offset = 22 + screen.shift.1;
+
+    if screen.alarm.is_active() {
+        update_display(display, &screen.cow, x_offset, y_offset, text_style)?;
+        return draw_alert_banner(display, screen.alarm, screen.temperature, text_style);
+    }
+    if let Some(payload) = screen.pairing {
+        return update_pairing_display(display, payload, text_style);
+    }
+    match screen.page {
+        Page::Cow => {
+            update_display(display, &screen.cow, x_offset, y_offset, text_style)?;
+            match &screen.battery {
+                Some(battery) => draw_battery_banner(display, battery, text_style),
+                None => Ok(()),
+            }
+        }
+        Page::Readings => update_readings_display(
+            display,
+            screen.temperature,
+            screen.humidity,
+            screen.pressure,
+            screen.battery,
+            text_style,
+        ),
+        Page::Status => update_status_display(
+            display,
+            screen.link.as_ref(),
+            screen.leash,
+            screen.uptime_secs,
+            screen.sensors,
+            text_style,
+        ),
+        Page::Nearby => update_nearby_display(display, screen.neighbors, text_style),
+    }
+}
+
+/// Sleep for `ms`, or until the button is pressed.
+async fn sleep(ms: u64) -> Option<Gesture> {
+    match select(Timer::after(Duration::from_millis(ms)), BUTTON.wait()).await {
+        Either::First(()) => None,
+        Either::Second(gesture) => Some(gesture),
+    }
+}
+
+/// Min/avg/max of a series of durations.
+#[derive(Clone, Copy, Default)]
+struct Stats {
+    count: u32,
+    total_us: u64,
+    min_us: u64,
+    max_us: u64,
+}
+
+impl Stats {
+    fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros();
+        self.min_us = if self.count == 0 {
+            us
+        } else {
+            self.min_us.min(us)
+        };
+        self.max_us = self.max_us.max(us);
+        self.total_us += us;
+        self.count += 1;
+    }
+
+    fn avg_us(&self) -> u64 {
+        self.total_us.checked_div(self.count as u64).unwrap_or(0)
+    }
+}
+
+/// Wake up every [`PROBE_PERIOD`] and record how late each wake-up was. Run next to a
+/// flush, this shows whether the executor still gets to poll other futures during the
+/// transfer: a blocking flush shows up as a single wake-up late by the whole frame.
+async fn probe_latency(latency: &mut Stats) -> ! {
+    loop {
+        let deadline = Instant::now() + PROBE_PERIOD;
+        Timer::at(deadline).await;
+        latency.record(Instant::now().saturating_duration_since(deadline));
+    }
+}
+
 #[task]
-pub async fn display_task(mut disp: DisplayWrapper) {
+pub async fn display_task(mut disp: DisplayWrapper, pairing: Payload) {
     let text_style = MonoTextStyleBuilder::new()
         .font(&FONT_6X9)
         .text_color(BinaryColor::On)
         .build();
 
+    let frame = FRAMEBUFFER.take();
     let mut counter = 0;
-    let x_offset = 30;
-    let y_offset = 22;
+    let mut flush_time = Stats::default();
+    let mut flush_bytes = 0;
+    let mut latency = Stats::default();
+    let mut alarms = ALARM.receiver().expect("Too many ALARM receivers");
+    let mut temperatures = TEMPERATURE
+        .receiver()
+        .expect("Too many TEMPERATURE receivers");
+    let mut settings = SETTINGS.receiver().expect("Too many SETTINGS receivers");
+    let mut connections = BLE_CONNECTED
+        .receiver()
+        .expect("Too many BLE_CONNECTED receivers");
+
+    let library = Library::parse(animation::COW).expect("Invalid cow animation");
+    let mut animator = Animator::new(library, Instant::now().as_millis());
+    let mut last_alarm = AlarmState::Normal;
+    let mut connected = false;
+    let mut page = Page::default();
+    // A press that ended the last sleep
+    let mut pressed = None;
+
+    let display_settings = settings.try_get().unwrap_or_default().display;
+    let mut power = DisplayPower::new(display_settings, Instant::now().as_secs());
+    // Contrast the panel was last set to, None while off; unknown until the first update
+    let mut applied: Option<Option<u8>> = None;
+    // Uptime until which the pairing QR code is shown
+    let mut pairing_until = None;
 
     loop {
-        // Update the display using the common function
+        let now_ms = Instant::now().as_millis();
+        let now = now_ms / 1000;
+        let alarm = alarms.try_get().unwrap_or(AlarmState::Normal);
+        let temperature = temperatures.try_get().unwrap_or(0);
+
+        if let Some(settings) = settings.try_changed() {
+            power.set_settings(settings.display, now);
+        }
+        if let Some(now_connected) = connections.try_changed() {
+            connected = now_connected;
+            power.set_connected(connected, now);
+            // whoever scanned the code has connected
+            if connected {
+                pairing_until = None;
+            }
+            let event = if connected {
+                Event::Connect
+            } else {
+                Event::Disconnect
+            };
+            animator.handle(event, now_ms);
+        }
+        if alarm != last_alarm {
+            let event = match alarm {
+                AlarmState::High => Event::Hot,
+                AlarmState::Low => Event::Cold,
+                AlarmState::Normal => Event::Normal,
+            };
+            animator.handle(event, now_ms);
+            last_alarm = alarm;
+        }
+        if SHOW_PAIRING.try_take().is_some() {
+            info!("Showing pairing QR code: {}", pairing.as_str());
+            pairing_until = Some(now + PAIRING_SECS);
+        }
+        match pressed.take().or_else(|| BUTTON.try_take()) {
+            // The first press only wakes the display, later ones dismiss the QR code or
+            // step through the pages
+            Some(Gesture::Short) => {
+                if power.state(now) == PowerState::On {
+                    if pairing_until.is_some_and(|until| now < until) {
+                        pairing_until = None;
+                    } else {
+                        page = page.next();
+                        info!("Display page {:?}", page);
+                    }
+                }
+                power.activity(now);
+            }
+            Some(Gesture::Double) => {
+                info!("Showing pairing QR code: {}", pairing.as_str());
+                pairing_until = Some(now + PAIRING_SECS);
+            }
+            Some(Gesture::Long) | None => {}
+        }
+        let show_pairing = pairing_until.is_some_and(|until| now < until);
+        if PAIRING_ACTIVE.try_get() != Some(show_pairing) {
+            PAIRING_ACTIVE.sender().send(show_pairing);
+        }
+        let leash = LEASH.lock(|leash| leash.borrow().is_tripped());
+        // An active alarm or the QR code keep the display on so they can be seen
+        if USER_ACTIVITY.try_take().is_some() || alarm.is_active() || leash || show_pairing {
+            power.activity(now);
+        }
+
+        // Deep sleep is next: switch the panel off, or it keeps showing the last frame
+        let sleeping = POWER.try_get() == Some(Mode::Sleep);
+        let contrast = if sleeping { None } else { power.contrast(now) };
+        if applied != Some(contrast) {
+            let result = match &mut disp {
+                DisplayWrapper::Real(real_disp) => set_power(real_disp, contrast).await,
+                DisplayWrapper::Mock(_) => Ok(()),
+            };
+            match result {
+                Ok(()) => {
+                    info!("Display {:?}, contrast {:?}", power.state(now), contrast);
+                    applied = Some(contrast);
+                }
+                Err(_) => warn!("Failed to set display power"),
+            }
+        }
+        if sleeping && applied == Some(None) {
+            SLEEP_READY.signal(());
+        }
+
+        // Nothing to draw while the display is off
+        if contrast.is_none() {
+            pressed = sleep(MAX_SLEEP_MS).await;
+            continue;
+        }
+
+        // Draw the whole frame, then send only what changed since the last flush
+        let (cow, remaining_ms) = animator.frame(now_ms);
+        let sensors = sensor::names(&SENSORS.try_get().unwrap_or_default());
+        let neighbors: heapless::Vec<Neighbor, NEIGHBORS_MAX> =
+            NEIGHBORS.lock(|neighbors| neighbors.borrow().iter().copied().collect());
+        let screen = Screen {
+            page,
+            cow,
+            shift: power.shift(now),
+            alarm,
+            temperature,
+            humidity: HUMIDITY.try_get(),
+            pressure: PRESSURE.try_get(),
+            battery: BATTERY.try_get(),
+            sensors: &sensors,
+            link: connected.then(|| LINK.try_get().unwrap_or_default()),
+            leash,
+            uptime_secs: now,
+            neighbors: &neighbors,
+            pairing: show_pairing.then_some(pairing.as_str()),
+        };
+        let Ok(()) = draw(frame, &screen, text_style);
+
         match &mut disp {
             DisplayWrapper::Real(real_disp) => {
-                if update_display(real_disp, counter, x_offset, y_offset, text_style).is_err() {
-                    warn!("Error updating real display");
-                    continue;
+                let start = Instant::now();
+                let result = match select(frame.flush(real_disp), probe_latency(&mut latency)).await
+                {
+                    Either::First(result) => result,
+                    Either::Second(never) => never,
+                };
+                flush_time.record(start.elapsed());
+
+                match result {
+                    Ok(sent) => flush_bytes += sent,
+                    Err(_) => {
+                        warn!("Failed to flush real display");
+                        continue;
+                    }
                 }
 
-                if real_disp.flush().is_err() {
-                    warn!("Failed to flush real display");
-                    continue;
+                if flush_time.count == STATS_FRAMES {
+                    info!(
+                        "Display flush over {} frames: {} bytes, min {} us, avg {} us, max {} us; executor wake-up latency avg {} us, max {} us",
+                        flush_time.count,
+                        flush_bytes,
+                        flush_time.min_us,
+                        flush_time.avg_us(),
+                        flush_time.max_us,
+                        latency.avg_us(),
+                        latency.max_us
+                    );
+                    flush_time = Stats::default();
+                    flush_bytes = 0;
+                    latency = Stats::default();
                 }
             }
             DisplayWrapper::Mock(mock_disp) => {
-                if update_display(mock_disp, counter, x_offset, y_offset, text_style).is_err() {
-                    warn!("Error updating mock display");
-                    continue;
-                }
-
-                if mock_disp.flush().is_err() {
+                if frame.flush(mock_disp).await.is_err() {
                     warn!("Failed to flush mock display");
                     continue;
                 }
+                // Nobody inspects the writes outside of tests
+                mock_disp.take_writes();
             }
         }
 
-        info!("Display updated with counter: {}", counter);
+        info!(
+            "Display updated: frame {}, {}",
+            counter,
+            animator.playing(now_ms)
+        );
         counter += 1;
-        Timer::after(Duration::from_secs(1)).await;
+        pressed = sleep(remaining_ms.min(MAX_SLEEP_MS)).await;
     }
 }
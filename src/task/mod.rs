@@ -1,7 +1,18 @@
+mod battery;
 pub mod ble;
+mod button;
 mod display;
+pub mod net;
+mod power;
+mod settings;
+mod status_led;
 mod temperature;
 
+pub use battery::{battery_adc, battery_task, ReadMillivolts};
 pub use ble::run;
+pub use button::button_task;
 pub use display::{display_task, DisplayType, DisplayWrapper};
+pub use power::{power_task, WakePin};
+pub use settings::settings_task;
+pub use status_led::{status_led_task, GpioLed, RgbLed, StatusLed};
 pub use temperature::temp_task;
\ No newline at end of file
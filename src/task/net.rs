@@ -0,0 +1,318 @@
+use core::fmt::Write;
+
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{Ipv4Address, Runner, Stack};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_io_async::Write as _;
+use esp_wifi::wifi::{
+    ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent, WifiState,
+};
+
+use crate::alarm::AlarmState;
+use crate::battery::Battery;
+use crate::clock::{self, TimeSource};
+use crate::mdns::{self, Responder};
+use crate::sntp;
+use crate::state::{ALARM, BATTERY, HUMIDITY, PRESSURE, TEMPERATURE};
+use crate::task::ble;
+
+/// Wi-Fi credentials, taken from `.env` at build time. An empty SSID disables networking.
+pub const WIFI_SSID: &str = env!("WIFI_SSID");
+const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
+const NTP_SERVER: &str = env!("NTP_SERVER");
+
+/// Local port the SNTP client sends from.
+const SNTP_LOCAL_PORT: u16 = 50123;
+/// How often the clock is re-synced once it has been set.
+const SNTP_RESYNC: Duration = Duration::from_secs(60 * 60);
+/// Back-off after a failed sync attempt.
+const SNTP_RETRY: Duration = Duration::from_secs(30);
+/// Unsolicited announcements after an address change, RFC 6762 section 8.3 asks for at least
+/// two, one second apart.
+const MDNS_ANNOUNCEMENTS: u8 = 2;
+const MDNS_ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the address is checked for changes while no queries arrive.
+const MDNS_ADDRESS_POLL: Duration = Duration::from_secs(5);
+/// How long a status client may take before it is dropped.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keep the station connected, reconnecting whenever the AP drops us.
+#[embassy_executor::task]
+pub async fn connection_task(mut controller: WifiController<'static>) {
+    info!("[wifi] connecting to {}", WIFI_SSID);
+    loop {
+        if esp_wifi::wifi::wifi_state() == WifiState::StaConnected {
+            controller.wait_for_event(WifiEvent::StaDisconnected).await;
+            warn!("[wifi] disconnected");
+            Timer::after(Duration::from_secs(5)).await;
+        }
+        if !matches!(controller.is_started(), Ok(true)) {
+            let config = Configuration::Client(ClientConfiguration {
+                ssid: WIFI_SSID.into(),
+                password: WIFI_PASSWORD.into(),
+                ..Default::default()
+            });
+            controller
+                .set_configuration(&config)
+                .expect("Failed to configure Wi-Fi");
+            controller
+                .start_async()
+                .await
+                .expect("Failed to start Wi-Fi");
+        }
+        match controller.connect_async().await {
+            Ok(()) => info!("[wifi] connected"),
+            Err(e) => {
+                warn!("[wifi] failed to connect: {:?}", e);
+                Timer::after(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+/// Drive the embassy-net stack.
+#[embassy_executor::task]
+pub async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
+
+/// Periodically set the wall clock from an SNTP server.
+#[embassy_executor::task]
+pub async fn sntp_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; sntp::PACKET_LEN * 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0u8; sntp::PACKET_LEN * 2];
+
+    loop {
+        stack.wait_config_up().await;
+
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        if let Err(e) = socket.bind(SNTP_LOCAL_PORT) {
+            warn!("[sntp] bind failed: {:?}", e);
+            Timer::after(SNTP_RETRY).await;
+            continue;
+        }
+
+        let next = match sync_once(stack, &socket).await {
+            Ok(unix_ms) => {
+                clock::set_unix_ms(unix_ms, TimeSource::Sntp);
+                clock::TIME_SYNCED.signal(());
+                SNTP_RESYNC
+            }
+            Err(()) => SNTP_RETRY,
+        };
+        drop(socket);
+        Timer::after(next).await;
+    }
+}
+
+async fn sync_once(stack: Stack<'static>, socket: &UdpSocket<'_>) -> Result<u64, ()> {
+    let server = match stack.dns_query(NTP_SERVER, DnsQueryType::A).await {
+        Ok(addrs) if !addrs.is_empty() => addrs[0],
+        Ok(_) | Err(_) => {
+            warn!("[sntp] could not resolve {}", NTP_SERVER);
+            return Err(());
+        }
+    };
+
+    let mut request = [0u8; sntp::PACKET_LEN];
+    sntp::encode_request(&mut request);
+    if let Err(e) = socket.send_to(&request, (server, sntp::NTP_PORT)).await {
+        warn!("[sntp] send failed: {:?}", e);
+        return Err(());
+    }
+
+    let mut reply = [0u8; sntp::PACKET_LEN];
+    match with_timeout(Duration::from_secs(5), socket.recv_from(&mut reply)).await {
+        Ok(Ok((len, _))) => sntp::decode_reply(&reply[..len]).map_err(|e| {
+            warn!("[sntp] bad reply: {:?}", e);
+        }),
+        Ok(Err(e)) => {
+            warn!("[sntp] receive failed: {:?}", e);
+            Err(())
+        }
+        Err(_) => {
+            warn!("[sntp] timed out waiting for {}", NTP_SERVER);
+            Err(())
+        }
+    }
+}
+
+/// Announce `<hostname>.local` and the `_coagatt._tcp` service, and answer mDNS queries.
+#[embassy_executor::task]
+pub async fn mdns_task(stack: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 1024];
+
+    // ble::mac_address() is least significant byte first, humans read it the other way round
+    let mut mac = ble::mac_address();
+    mac.reverse();
+    let hostname = mdns::hostname_from_mac(&mac);
+    let mut instance: heapless::String<24> = heapless::String::new();
+    let _ = write!(instance, "COW GATT {:02x}{:02x}", mac[4], mac[5]);
+    let mut mac_txt: heapless::String<24> = heapless::String::new();
+    let _ = write!(
+        mac_txt,
+        "mac={:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    let txt = [concat!("ver=", env!("CARGO_PKG_VERSION")), mac_txt.as_str()];
+
+    let group = Ipv4Address::new(
+        mdns::MDNS_GROUP[0],
+        mdns::MDNS_GROUP[1],
+        mdns::MDNS_GROUP[2],
+        mdns::MDNS_GROUP[3],
+    );
+    if let Err(e) = stack.join_multicast_group(group) {
+        warn!("[mdns] failed to join multicast group: {:?}", e);
+        return;
+    }
+
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(mdns::MDNS_PORT) {
+        warn!("[mdns] bind failed: {:?}", e);
+        return;
+    }
+
+    let mut packet = [0u8; 512];
+    let mut reply = [0u8; 512];
+    loop {
+        stack.wait_config_up().await;
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+        let responder = Responder {
+            hostname: &hostname,
+            instance: &instance,
+            ipv4: config.address.address().octets(),
+            port: mdns::SERVICE_PORT,
+            txt: &txt,
+        };
+        info!("[mdns] announcing {}.local", hostname.as_str());
+        let mut announcements = 0;
+        let mut next_announcement = Instant::now();
+
+        // answer queries until the address changes
+        while stack.config_v4().map(|c| c.address) == Some(config.address) {
+            if announcements < MDNS_ANNOUNCEMENTS && Instant::now() >= next_announcement {
+                if let Some(len) = responder.announce(&mut reply) {
+                    let _ = socket
+                        .send_to(&reply[..len], (group, mdns::MDNS_PORT))
+                        .await;
+                }
+                announcements += 1;
+                next_announcement += MDNS_ANNOUNCE_INTERVAL;
+            }
+            // wake up for the next announcement, or to notice an address change while quiet
+            let wake = if announcements < MDNS_ANNOUNCEMENTS {
+                next_announcement
+            } else {
+                Instant::now() + MDNS_ADDRESS_POLL
+            };
+            let (len, meta) = match select(socket.recv_from(&mut packet), Timer::at(wake)).await {
+                Either::First(Ok(received)) => received,
+                Either::First(Err(e)) => {
+                    warn!("[mdns] receive failed: {:?}", e);
+                    continue;
+                }
+                Either::Second(()) => continue,
+            };
+            // legacy unicast queries (not from port 5353) expect a direct DNS style reply
+            let legacy = meta.endpoint.port != mdns::MDNS_PORT;
+            let reply_len = if legacy {
+                responder.respond_unicast(&packet[..len], &mut reply)
+            } else {
+                responder.respond(&packet[..len], &mut reply)
+            };
+            let Some(reply_len) = reply_len else {
+                continue;
+            };
+            let result = if legacy {
+                socket.send_to(&reply[..reply_len], meta.endpoint).await
+            } else {
+                socket
+                    .send_to(&reply[..reply_len], (group, mdns::MDNS_PORT))
+                    .await
+            };
+            if let Err(e) = result {
+                warn!("[mdns] send failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Serve the latest readings to each TCP client on [`mdns::SERVICE_PORT`], the port of the
+/// DNS-SD service, as `key=value` lines, then close the connection.
+#[embassy_executor::task]
+pub async fn status_task(stack: Stack<'static>) {
+    let mut rx_buffer = [0u8; 64];
+    let mut tx_buffer = [0u8; 256];
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(STATUS_TIMEOUT));
+        if let Err(e) = socket.accept(mdns::SERVICE_PORT).await {
+            warn!("[status] accept failed: {:?}", e);
+            continue;
+        }
+        if let Err(e) = socket.write_all(status_text().as_bytes()).await {
+            warn!("[status] write failed: {:?}", e);
+        }
+        socket.close();
+        // wait for the data to be acknowledged before the socket is reused
+        let _ = socket.flush().await;
+    }
+}
+
+/// Uptime and, once the clock is synced, Unix time, both in seconds, then the readings
+/// measured so far, temperature and humidity with two decimals and pressure in hPa, and
+/// the alarm state.
+fn status_text() -> heapless::String<160> {
+    let mut text = heapless::String::new();
+    let _ = writeln!(text, "uptime={}", Instant::now().as_secs());
+    if let Some(unix_ms) = clock::now_unix_ms() {
+        let _ = writeln!(text, "time={}", unix_ms / 1000);
+    }
+    if let Some(temperature) = TEMPERATURE.try_get() {
+        let sign = if temperature < 0 { "-" } else { "" };
+        let abs = temperature.unsigned_abs();
+        let _ = writeln!(text, "temperature={}{}.{:02}", sign, abs / 100, abs % 100);
+    }
+    if let Some(humidity) = HUMIDITY.try_get() {
+        let _ = writeln!(text, "humidity={}.{:02}", humidity / 100, humidity % 100);
+    }
+    if let Some(pressure) = PRESSURE.try_get() {
+        // 0.1 Pa to hPa with one decimal
+        let _ = writeln!(text, "pressure={}.{}", pressure / 1000, pressure / 100 % 10);
+    }
+    if let Some(battery) = BATTERY.try_get().filter(Battery::is_present) {
+        let _ = writeln!(text, "battery={}", battery.percent);
+        let _ = writeln!(text, "battery_mv={}", battery.mv);
+    }
+    let alarm = match ALARM.try_get().unwrap_or(AlarmState::Normal) {
+        AlarmState::Normal => "normal",
+        AlarmState::High => "high",
+        AlarmState::Low => "low",
+    };
+    let _ = writeln!(text, "alarm={}", alarm);
+    text
+}
@@ -0,0 +1,111 @@
+use alloc::boxed::Box;
+
+use defmt::{info, warn};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use esp_hal::gpio::RtcPinWithResistors;
+#[cfg(feature = "esp32c6")]
+use esp_hal::rtc_cntl::sleep::Ext1WakeupSource;
+#[cfg(feature = "esp32c3")]
+use esp_hal::rtc_cntl::sleep::RtcioWakeupSource;
+use esp_hal::rtc_cntl::sleep::{TimerWakeupSource, WakeupLevel};
+use esp_hal::rtc_cntl::Rtc;
+
+use super::ble::relay_enabled;
+use crate::power::{Mode, PowerConfig, PowerPolicy};
+use crate::state::{
+    ALARM, BLE_CONNECTED, LEASH, OTA_IN_PROGRESS, PAIRING_ACTIVE, PEER_VALUES, POWER,
+    POWER_ACTIVITY, SLEEP_READY,
+};
+
+/// Seconds without a connection before deep sleep, `0` to stay awake, see `.env.example`.
+const DEEP_SLEEP_SECS: &str = env!("DEEP_SLEEP_SECS");
+/// Seconds a deep sleep lasts.
+const WAKE_SECS: &str = env!("WAKE_SECS");
+
+/// How often connection and alarm changes are picked up.
+const POLL: Duration = Duration::from_secs(1);
+/// How long the display gets to switch off before deep sleep, longer than it waits between
+/// updates.
+const SLEEP_GRACE: Duration = Duration::from_millis(1500);
+
+/// An RTC pin with a button that pulls it low to wake the board from deep sleep.
+pub type WakePin = Box<dyn RtcPinWithResistors>;
+
+/// Whether something needs attention or the radio, which keeps the board active: besides
+/// alarms, pairing and updates, connections to peripherals and relaying, which a deep sleep
+/// would drop.
+fn busy() -> bool {
+    ALARM.try_get().is_some_and(|alarm| alarm.is_active())
+        || LEASH.lock(|leash| leash.borrow().is_tripped())
+        || PAIRING_ACTIVE.try_get().unwrap_or(false)
+        || OTA_IN_PROGRESS.try_get().unwrap_or(false)
+        || PEER_VALUES.lock(|peers| peers.borrow().iter().any(|peer| peer.connected))
+        || relay_enabled()
+}
+
+/// Publish the power [`Mode`] to [`POWER`] and put the board to deep sleep when it is due.
+/// It wakes up when the timer runs out or the button on `wake` is pressed, and boots again.
+///
+/// There is no light sleep in between: the BLE controller of `esp-wifi` needs its clocks
+/// between radio events, so while awake the CPU only waits for interrupts.
+#[embassy_executor::task]
+pub async fn power_task(mut rtc: Rtc<'static>, mut wake: Option<WakePin>) {
+    let config = PowerConfig::parse(DEEP_SLEEP_SECS, WAKE_SECS).unwrap_or_else(|| {
+        warn!(
+            "[power] invalid DEEP_SLEEP_SECS {} or WAKE_SECS {}, deep sleep disabled",
+            DEEP_SLEEP_SECS, WAKE_SECS
+        );
+        PowerConfig {
+            sleep_after_secs: 0,
+            wake_after_secs: 0,
+        }
+    });
+    info!("[power] {:?}", config);
+    let mut policy = PowerPolicy::new(config, Instant::now().as_secs());
+    let mut connections = BLE_CONNECTED
+        .receiver()
+        .expect("Too many BLE_CONNECTED receivers");
+    let mut mode = Mode::Active;
+    POWER.sender().send(mode);
+    SLEEP_READY.reset();
+
+    loop {
+        let now = Instant::now().as_secs();
+        if let Some(connected) = connections.try_changed() {
+            policy.set_connected(connected, now);
+        }
+        if POWER_ACTIVITY.try_take().is_some() {
+            policy.activity(now);
+        }
+        policy.set_busy(busy(), now);
+
+        let latest = policy.mode(now);
+        if latest != mode {
+            mode = latest;
+            info!("[power] {:?}", mode);
+            POWER.sender().send(mode);
+        }
+        if mode == Mode::Sleep {
+            // the display switches off, nothing else needs to be told: RAM is lost anyway
+            if with_timeout(SLEEP_GRACE, SLEEP_READY.wait()).await.is_err() {
+                warn!("[power] display not ready, sleeping anyway");
+            }
+            info!("[power] deep sleep for {} s", config.wake_after_secs);
+            let timer =
+                TimerWakeupSource::new(core::time::Duration::from_secs(config.wake_after_secs));
+            let Some(pin) = wake.take() else {
+                rtc.sleep_deep(&[&timer]);
+            };
+            // the wake-up source wants the pin for good, and deep sleep does not return
+            let pin: &'static mut dyn RtcPinWithResistors = Box::leak(pin);
+            let mut pins = [(pin, WakeupLevel::Low)];
+            #[cfg(feature = "esp32c3")]
+            let button = RtcioWakeupSource::new(&mut pins);
+            #[cfg(feature = "esp32c6")]
+            let button = Ext1WakeupSource::new(&mut pins);
+            rtc.sleep_deep(&[&timer, &button]);
+        }
+
+        Timer::after(POLL).await;
+    }
+}
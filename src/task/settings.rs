@@ -0,0 +1,54 @@
+use defmt::{info, warn};
+use embassy_futures::select::{select, Either};
+use embassy_time::{with_timeout, Duration};
+use esp_storage::FlashStorage;
+
+use crate::settings;
+use crate::state::{FACTORY_RESET, FACTORY_RESET_RELEASED, FAULT, SETTINGS};
+
+/// Wait this long after a change before writing, so a client stepping through values
+/// (e.g. a contrast slider) costs one flash write instead of many.
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// Erase the settings, then restart once the button is let go so the board comes up as on
+/// first boot. The button is on GPIO9, a strapping pin: held low through the reset, the chip
+/// would start the ROM download mode instead of the firmware.
+async fn factory_reset(flash: &mut FlashStorage) -> ! {
+    match settings::erase(flash) {
+        Ok(()) => info!("[settings] factory reset, restarting when the button is released"),
+        Err(e) => warn!("[settings] failed to erase: {:?}", defmt::Debug2Format(&e)),
+    }
+    FACTORY_RESET_RELEASED.wait().await;
+    esp_hal::system::software_reset()
+}
+
+/// Save [`SETTINGS`] to flash whenever it changes, erase them on [`FACTORY_RESET`].
+#[embassy_executor::task]
+pub async fn settings_task(mut flash: FlashStorage) {
+    let mut receiver = SETTINGS.receiver().expect("Too many SETTINGS receivers");
+    let mut stored = settings::load(&mut flash);
+
+    loop {
+        let mut latest = match select(receiver.changed(), FACTORY_RESET.wait()).await {
+            Either::First(settings) => settings,
+            Either::Second(()) => factory_reset(&mut flash).await,
+        };
+        while let Ok(newer) = with_timeout(SAVE_DELAY, receiver.changed()).await {
+            latest = newer;
+        }
+        // the value loaded at boot shows up as a change too
+        if stored == Some(latest) {
+            continue;
+        }
+        match settings::store(&mut flash, &latest) {
+            Ok(()) => {
+                info!("[settings] saved {:?}", latest);
+                stored = Some(latest);
+            }
+            Err(e) => {
+                warn!("[settings] failed to save: {:?}", defmt::Debug2Format(&e));
+                FAULT.sender().send(true);
+            }
+        }
+    }
+}
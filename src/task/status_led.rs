@@ -0,0 +1,123 @@
+use defmt::{info, warn};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::{Level, Output};
+use esp_hal::rmt::{Channel, ConstChannelAccess, Error as RmtError, PulseCode, Tx, TxChannelAsync};
+use esp_hal::Async;
+
+use crate::state::{BLE_CONNECTED, FAULT, LEASH, OTA_IN_PROGRESS, PAIRING_ACTIVE};
+use crate::status_led::{Color, Indicators, LedWriter, Sequencer};
+
+/// How often the indicators are checked between pattern steps.
+const POLL_MS: u64 = 250;
+
+// WS2812 bit timings in RMT ticks at 80 MHz (12.5 ns)
+const T0H: u16 = 32;
+const T0L: u16 = 68;
+const T1H: u16 = 64;
+const T1L: u16 = 36;
+
+/// Plain LED on a GPIO, lit for any colour but [`Color::OFF`].
+pub struct GpioLed {
+    pin: Output<'static>,
+    active_low: bool,
+}
+
+impl GpioLed {
+    pub fn new(pin: Output<'static>, active_low: bool) -> Self {
+        Self { pin, active_low }
+    }
+}
+
+impl LedWriter for GpioLed {
+    type Error = core::convert::Infallible;
+
+    async fn write(&mut self, color: Color) -> Result<(), Self::Error> {
+        let lit = !color.is_off();
+        self.pin.set_level(Level::from(lit != self.active_low));
+        Ok(())
+    }
+}
+
+/// RMT channel 0 configured for transmitting, as returned by `configure_tx`.
+pub type RgbChannel = Channel<Async, ConstChannelAccess<Tx, 0>>;
+
+/// Addressable WS2812 RGB LED, as on the ESP32-C3/C6 DevKits, driven by RMT channel 0.
+pub struct RgbLed {
+    channel: RgbChannel,
+}
+
+impl RgbLed {
+    /// `channel` must run at 80 MHz with a clock divider of 1 and idle low.
+    pub fn new(channel: RgbChannel) -> Self {
+        Self { channel }
+    }
+}
+
+impl LedWriter for RgbLed {
+    type Error = RmtError;
+
+    async fn write(&mut self, color: Color) -> Result<(), RmtError> {
+        // 24 bits in GRB order, most significant bit first, then the end marker
+        let mut codes = [u32::empty(); 25];
+        let grb = ((color.g as u32) << 16) | ((color.r as u32) << 8) | color.b as u32;
+        for (i, code) in codes[..24].iter_mut().enumerate() {
+            *code = if grb & (1 << (23 - i)) != 0 {
+                u32::new(Level::High, T1H, Level::Low, T1L)
+            } else {
+                u32::new(Level::High, T0H, Level::Low, T0L)
+            };
+        }
+        self.channel.transmit(&codes).await
+    }
+}
+
+pub enum StatusLed {
+    Gpio(GpioLed),
+    Rgb(RgbLed),
+}
+
+impl LedWriter for StatusLed {
+    type Error = RmtError;
+
+    async fn write(&mut self, color: Color) -> Result<(), RmtError> {
+        match self {
+            StatusLed::Gpio(led) => {
+                let Ok(()) = led.write(color).await;
+                Ok(())
+            }
+            StatusLed::Rgb(led) => led.write(color).await,
+        }
+    }
+}
+
+/// Show the connection, pairing, update, leash and fault state on the status LED.
+#[embassy_executor::task]
+pub async fn status_led_task(mut led: StatusLed) {
+    let mut indicators = Indicators::default();
+    let mut sequencer = Sequencer::new(indicators.status().pattern(), Instant::now().as_millis());
+
+    loop {
+        let now = Instant::now().as_millis();
+        let latest = Indicators {
+            connected: BLE_CONNECTED.try_get().unwrap_or(false),
+            pairing: PAIRING_ACTIVE.try_get().unwrap_or(false),
+            ota: OTA_IN_PROGRESS.try_get().unwrap_or(false),
+            leash: LEASH.lock(|leash| leash.borrow().is_tripped()),
+            error: FAULT.try_get().unwrap_or(false),
+        };
+        if latest != indicators {
+            indicators = latest;
+            info!("[led] {:?}", indicators.status());
+            sequencer.set_pattern(indicators.status().pattern(), now);
+        }
+
+        let remaining = match sequencer.update(&mut led, now).await {
+            Ok(remaining) => remaining,
+            Err(e) => {
+                warn!("[led] failed to write: {:?}", e);
+                POLL_MS
+            }
+        };
+        Timer::after(Duration::from_millis(remaining.min(POLL_MS))).await;
+    }
+}
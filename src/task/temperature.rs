@@ -1,16 +1,90 @@
-use defmt::info;
-use embassy_time::Timer;
-use esp_hal::tsens::{TemperatureSensor};
+use defmt::{info, warn};
+use embassy_futures::select::select;
+use embassy_time::{Instant, Timer};
+use esp_hal::tsens::TemperatureSensor;
 
+use crate::alarm::{Alarm, Thresholds};
+use crate::history::centi_celsius;
+use crate::i2c_bus::{I2cBus, I2cDevice};
+use crate::power::Mode;
+use crate::sensor::die::DieTemperature;
+use crate::sensor::{self, Quantity, Sensor};
+use crate::state::{
+    ALARM, ALARM_THRESHOLDS, HUMIDITY, POWER, PRESSURE, SENSORS, TEMPERATURE, TEMPERATURE_HISTORY,
+};
+
+/// Sample all sensors every 2 s, every 10 s while the board is idle. The first external
+/// sensor that measures temperature takes precedence over the chip's die temperature for
+/// history and alarms.
 #[embassy_executor::task]
-pub async fn temp_task(tsens: TemperatureSensor<'static>) {
-    // datasheet recommends 200 µs after power-up
-    esp_hal::delay::Delay::new().delay_micros(200);
+pub async fn temp_task(tsens: TemperatureSensor<'static>, i2c_bus: &'static I2cBus) {
+    let mut die = DieTemperature::new(tsens);
+    let mut external = sensor::detect(|| I2cDevice::new(i2c_bus)).await;
+
+    let mut registry = sensor::Registry::new();
+    for s in &external {
+        let metadata = s.metadata();
+        info!("found {} at {:?}", metadata.name, metadata.address);
+        let _ = registry.push(metadata);
+    }
+    if registry.is_empty() {
+        info!("no external sensor found, using die temperature");
+        let _ = registry.push(die.metadata());
+    }
+    SENSORS.sender().send(registry);
+
+    let mut alarm = Alarm::new(Thresholds::default());
+    let mut thresholds = ALARM_THRESHOLDS
+        .receiver()
+        .expect("Too many ALARM_THRESHOLDS receivers");
+    ALARM.sender().send(alarm.state());
+    let mut power = POWER.receiver().expect("Too many POWER receivers");
 
     loop {
-        let t = tsens.get_temperature();
-        let c = t.to_celsius();
-        info!("chip temperature = {:?} °C", c);
-        Timer::after_secs(2).await;
+        let (mut temperature, mut humidity, mut pressure) = (None, None, None);
+        for s in external.iter_mut() {
+            match s.sample().await {
+                Ok(readings) => {
+                    temperature = temperature.or(sensor::find(&readings, Quantity::Temperature));
+                    humidity = humidity.or(sensor::find(&readings, Quantity::Humidity));
+                    pressure = pressure.or(sensor::find(&readings, Quantity::Pressure));
+                }
+                Err(e) => warn!("{} sample failed: {:?}", s.metadata().name, e),
+            }
+        }
+        let die_c = sensor::find(
+            &die.sample().await.unwrap_or_default(),
+            Quantity::Temperature,
+        );
+        info!("chip temperature = {:?} °C", die_c);
+
+        if let Some(h) = humidity {
+            info!("humidity = {:?} %RH", h);
+            HUMIDITY.sender().send((h * 100.0) as u16);
+        }
+        if let Some(p) = pressure {
+            info!("pressure = {:?} Pa", p);
+            PRESSURE.sender().send((p * 10.0) as u32);
+        }
+
+        if let Some(c) = temperature.or(die_c) {
+            let value = centi_celsius(c);
+            let now = Instant::now().as_secs() as u32;
+            TEMPERATURE_HISTORY.lock(|history| history.borrow_mut().record(now, value));
+            TEMPERATURE.sender().send(value);
+
+            if let Some(new) = thresholds.try_changed() {
+                info!("alarm thresholds set to {:?}", new);
+                alarm.set_thresholds(new);
+            }
+            if let Some(state) = alarm.update(value) {
+                warn!("temperature alarm: {:?} at {:?} °C", state, c);
+                ALARM.sender().send(state);
+            }
+        }
+
+        // back to the short interval as soon as the board is active again
+        let mode = power.try_get().unwrap_or(Mode::Active);
+        select(Timer::after_secs(mode.sample_secs()), power.changed()).await;
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,100 @@
+//! Throughput test.
+//!
+//! A central writes a [`Request`] to the throughput control point: either the board sends
+//! notifications as fast as the link takes them, or it counts what the central writes,
+//! for the requested number of seconds. The [`Meter`] result is then notified. Times are
+//! milliseconds since boot.
+
+/// Most payload per notification, an ATT MTU of 247 less the 3 byte header.
+pub const CHUNK_MAX: usize = 244;
+
+/// Size of the encoded [`Meter`] result.
+pub const RESULT_LEN: usize = 13;
+
+/// Longest test run.
+pub const SECS_MAX: u8 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Direction {
+    /// Board to central, notifications.
+    Send,
+    /// Central to board, writes without response.
+    Receive,
+}
+
+impl Direction {
+    fn code(self) -> u8 {
+        match self {
+            Direction::Send => 1,
+            Direction::Receive => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Request {
+    pub direction: Direction,
+    pub secs: u8,
+}
+
+impl Request {
+    /// `<direction u8> <seconds u8>`, direction 1 to send and 2 to receive, 1 to
+    /// [`SECS_MAX`] seconds.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let &[direction, secs] = data else {
+            return None;
+        };
+        let direction = match direction {
+            1 => Direction::Send,
+            2 => Direction::Receive,
+            _ => return None,
+        };
+        (1..=SECS_MAX)
+            .contains(&secs)
+            .then_some(Self { direction, secs })
+    }
+}
+
+/// Counts bytes over a test run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Meter {
+    pub direction: Direction,
+    pub started: u64,
+    pub bytes: u32,
+    pub packets: u32,
+}
+
+impl Meter {
+    pub const fn new(direction: Direction, now: u64) -> Self {
+        Self {
+            direction,
+            started: now,
+            bytes: 0,
+            packets: 0,
+        }
+    }
+
+    pub fn add(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_add(bytes as u32);
+        self.packets = self.packets.saturating_add(1);
+    }
+
+    /// Bytes per second up to `now`, 0 before the first millisecond has passed.
+    pub fn rate(&self, now: u64) -> u32 {
+        let elapsed = now.saturating_sub(self.started);
+        if elapsed == 0 {
+            return 0;
+        }
+        (self.bytes as u64 * 1000 / elapsed).min(u32::MAX as u64) as u32
+    }
+
+    /// Direction, bytes, packets and bytes per second up to `now`, little-endian.
+    pub fn encode(&self, now: u64) -> [u8; RESULT_LEN] {
+        let mut out = [0u8; RESULT_LEN];
+        out[0] = self.direction.code();
+        out[1..5].copy_from_slice(&self.bytes.to_le_bytes());
+        out[5..9].copy_from_slice(&self.packets.to_le_bytes());
+        out[9..13].copy_from_slice(&self.rate(now).to_le_bytes());
+        out
+    }
+}
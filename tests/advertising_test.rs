@@ -0,0 +1,273 @@
+//! Advertising settings and payload tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::advertising::Status;
+#[cfg(test)]
+use coa_gatt::alarm::AlarmState;
+
+#[cfg(test)]
+fn status() -> Status {
+    Status {
+        temperature: Some(21_50),
+        battery: Some(87),
+        alarm: AlarmState::High,
+        fault: false,
+    }
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::advertising::{
+        ad_type, advertising_data, bthome, eddystone_tlm, extended_data, scan_response, Status,
+        Telemetry, COMPANY_ID, MAX_AD_LEN,
+    };
+    use coa_gatt::alarm::AlarmState;
+    use coa_gatt::settings::{AdvertisedFields, AdvertisingSettings, Beacon};
+    use defmt::{assert, assert_eq};
+
+    use super::status;
+
+    const UUID: [u8; 16] = [0xAA; 16];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn advertising_settings_round_trip_and_validate() {
+        let advertising = AdvertisingSettings {
+            interval_min_ms: 1000,
+            interval_max_ms: 1500,
+            tx_power_dbm: -12,
+            timeout_secs: 300,
+            fields: AdvertisedFields {
+                name: false,
+                ..Default::default()
+            },
+            beacon: Beacon::EddystoneTlm,
+            extended: true,
+            long_range: true,
+            connection_2m: false,
+        };
+        let encoded = advertising.encode();
+        assert_eq!(encoded, [0xE8, 0x03, 0xDC, 0x05, 0xF4, 0x2C, 0x01, 0x76]);
+        assert_eq!(AdvertisingSettings::decode(&encoded), Some(advertising));
+        let default = AdvertisingSettings::default();
+        assert_eq!(
+            AdvertisingSettings::decode(&default.encode()),
+            Some(default)
+        );
+
+        let invalid = [
+            // min above max
+            AdvertisingSettings {
+                interval_min_ms: 200,
+                interval_max_ms: 100,
+                ..advertising
+            },
+            // faster than the spec allows
+            AdvertisingSettings {
+                interval_min_ms: 10,
+                ..advertising
+            },
+            AdvertisingSettings {
+                interval_max_ms: 20_000,
+                ..advertising
+            },
+            AdvertisingSettings {
+                tx_power_dbm: 30,
+                ..advertising
+            },
+            // the Coded PHY needs extended advertising
+            AdvertisingSettings {
+                extended: false,
+                ..advertising
+            },
+        ];
+        for settings in invalid {
+            assert_eq!(AdvertisingSettings::decode(&settings.encode()), None);
+        }
+        let mut unknown_beacon = encoded;
+        unknown_beacon[7] |= 0x18;
+        assert_eq!(AdvertisingSettings::decode(&unknown_beacon), None);
+        assert_eq!(AdvertisingSettings::decode(&encoded[..7]), None);
+    }
+
+    #[test]
+    fn status_record_round_trips() {
+        assert_eq!(status().encode(), [0x01, 0x66, 0x08, 87, 0x01]);
+        assert_eq!(Status::decode(&status().encode()), Some(status()));
+
+        let unknown = Status {
+            temperature: None,
+            battery: None,
+            alarm: AlarmState::Normal,
+            fault: true,
+        };
+        assert_eq!(unknown.encode(), [0x01, 0x00, 0x80, 0xFF, 0x04]);
+        assert_eq!(Status::decode(&unknown.encode()), Some(unknown));
+
+        // other versions, hot and cold at once
+        assert_eq!(Status::decode(&[0x02, 0x66, 0x08, 87, 0x01]), None);
+        assert_eq!(Status::decode(&[0x01, 0x66, 0x08, 87, 0x03]), None);
+    }
+
+    #[test]
+    fn all_fields_fit_in_one_packet() {
+        let data = advertising_data(&AdvertisedFields::default(), &UUID, &status());
+        let bytes = data.as_bytes();
+        assert_eq!(bytes.len(), 30);
+        assert_eq!(&bytes[..3], &[0x02, ad_type::FLAGS, 0x06]);
+        assert_eq!(&bytes[3..5], &[0x11, ad_type::COMPLETE_SERVICE_UUIDS_128]);
+        assert_eq!(&bytes[5..21], &UUID);
+        assert_eq!(&bytes[21..23], &[0x08, ad_type::MANUFACTURER_SPECIFIC_DATA]);
+        assert_eq!(&bytes[23..25], &COMPANY_ID.to_le_bytes());
+        assert_eq!(&bytes[25..], &status().encode());
+    }
+
+    #[test]
+    fn disabled_fields_are_left_out() {
+        let fields = AdvertisedFields {
+            name: false,
+            service_uuid: false,
+            status: true,
+        };
+        let data = advertising_data(&fields, &UUID, &status());
+        assert_eq!(data.as_bytes().len(), 3 + 9);
+        assert_eq!(data.as_bytes()[4], ad_type::MANUFACTURER_SPECIFIC_DATA);
+        assert_eq!(scan_response(&fields, "COW GATT").as_bytes(), &[]);
+
+        // flags are always there
+        let fields = AdvertisedFields {
+            status: false,
+            ..fields
+        };
+        let data = advertising_data(&fields, &UUID, &status());
+        assert_eq!(data.as_bytes(), &[0x02, ad_type::FLAGS, 0x06]);
+    }
+
+    #[test]
+    fn long_names_are_shortened() {
+        let fields = AdvertisedFields::default();
+        let data = scan_response(&fields, "COW GATT");
+        assert_eq!(&data.as_bytes()[..2], &[9, ad_type::COMPLETE_LOCAL_NAME]);
+        assert_eq!(&data.as_bytes()[2..], b"COW GATT");
+
+        let name = "A cow with a name far too long to advertise";
+        let data = scan_response(&fields, name);
+        assert_eq!(data.as_bytes().len(), MAX_AD_LEN);
+        assert_eq!(data.as_bytes()[1], ad_type::SHORTENED_LOCAL_NAME);
+        assert!(name.as_bytes().starts_with(&data.as_bytes()[2..]));
+    }
+
+    #[test]
+    fn extended_data_has_everything_in_one_packet() {
+        let data = extended_data(&AdvertisedFields::default(), "COW GATT", &UUID, &status());
+        let bytes = data.as_bytes();
+        let legacy = advertising_data(&AdvertisedFields::default(), &UUID, &status());
+        assert_eq!(&bytes[..30], legacy.as_bytes());
+        assert_eq!(&bytes[30..32], &[9, ad_type::COMPLETE_LOCAL_NAME]);
+        assert_eq!(&bytes[32..], b"COW GATT");
+
+        // names no longer need shortening
+        let name = "A cow with a name far too long to advertise";
+        let data = extended_data(&AdvertisedFields::default(), name, &UUID, &status());
+        assert_eq!(&data.as_bytes()[32..], name.as_bytes());
+    }
+
+    #[test]
+    fn bthome_matches_the_spec_example() {
+        // bthome.io example: 25.00 °C and 50.55 %, `0A16D2FC4002C40903BF13` after the flags
+        let status = Status {
+            temperature: Some(25_00),
+            battery: None,
+            alarm: AlarmState::Normal,
+            fault: false,
+        };
+        let data = bthome(&status, Some(50_55), None, 9);
+        let bytes = data.as_bytes();
+        assert_eq!(&bytes[..3], &[0x02, 0x01, 0x06]);
+        assert_eq!(bytes[3] as usize, bytes.len() - 4);
+        assert_eq!(&bytes[4..8], &[0x16, 0xD2, 0xFC, 0x40]);
+        assert_eq!(
+            &bytes[8..],
+            &[
+                0x00, 0x09, // packet id
+                0x02, 0xC4, 0x09, // temperature
+                0x03, 0xBF, 0x13, // humidity
+                0x18, 0x00, 0x1D, 0x00, 0x26, 0x00, // cold, heat, problem
+            ]
+        );
+    }
+
+    #[test]
+    fn bthome_carries_everything_known() {
+        // spec examples: battery `0161` 97 %, pressure `04138A01` 1008.83 hPa
+        let status = Status {
+            battery: Some(97),
+            ..status()
+        };
+        let data = bthome(&status, None, Some(1_008_830), 1);
+        let bytes = data.as_bytes();
+        assert!(bytes.len() <= MAX_AD_LEN);
+        assert_eq!(
+            &bytes[8..],
+            &[
+                0x00, 0x01, // packet id
+                0x01, 0x61, // battery
+                0x02, 0x66, 0x08, // temperature
+                0x04, 0x13, 0x8A, 0x01, // pressure
+                0x18, 0x00, 0x1D, 0x01, 0x26, 0x00, // too hot
+            ]
+        );
+
+        // the longest packet still fits
+        let data = bthome(&status, Some(u16::MAX), Some(u32::MAX), 255);
+        assert_eq!(data.as_bytes().len(), 3 + 25);
+    }
+
+    #[test]
+    fn eddystone_tlm_frame() {
+        let telemetry = Telemetry {
+            battery_mv: Some(3000),
+            temperature: Some(25_50),
+            adv_count: 0x0102_0304,
+            uptime_ds: 36_000,
+        };
+        let data = eddystone_tlm(&telemetry);
+        assert_eq!(
+            data.as_bytes(),
+            &[
+                0x02, 0x01, 0x06, // flags
+                0x03, 0x03, 0xAA, 0xFE, // Eddystone service
+                0x11, 0x16, 0xAA, 0xFE, 0x20, 0x00, // TLM, unencrypted
+                0x0B, 0xB8, // 3000 mV
+                0x19, 0x80, // 25.5 °C in 8.8
+                0x01, 0x02, 0x03, 0x04, // advertising count
+                0x00, 0x00, 0x8C, 0xA0, // one hour
+            ]
+        );
+
+        // spec examples for 8.8 fixed point, and the values for unknown
+        let frame = |temperature| {
+            let data = eddystone_tlm(&Telemetry {
+                battery_mv: None,
+                temperature,
+                ..telemetry
+            });
+            let bytes = data.as_bytes();
+            [bytes[13], bytes[14], bytes[15], bytes[16]]
+        };
+        assert_eq!(frame(Some(1_00)), [0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(frame(Some(-1_00)), [0x00, 0x00, 0xFF, 0x00]);
+        assert_eq!(frame(None), [0x00, 0x00, 0x80, 0x00]);
+    }
+}
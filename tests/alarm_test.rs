@@ -0,0 +1,149 @@
+//! Temperature alarm threshold and hysteresis tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::alarm::{Alarm, Thresholds};
+
+#[cfg(test)]
+fn alarm(high: Option<i16>, low: Option<i16>, hysteresis: u16) -> Alarm {
+    Alarm::new(Thresholds {
+        high,
+        low,
+        hysteresis,
+    })
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::alarm::{AlarmState, Thresholds, Trigger, TriggerError};
+    use defmt::assert_eq;
+
+    use super::alarm;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn raises_exactly_at_threshold() {
+        let mut alarm = alarm(Some(30_00), None, 1_00);
+        assert_eq!(alarm.update(29_99), None);
+        assert_eq!(alarm.update(30_00), Some(AlarmState::High));
+    }
+
+    #[test]
+    fn hysteresis_keeps_alarm_until_cleared() {
+        let mut alarm = alarm(Some(30_00), Some(10_00), 1_00);
+        assert_eq!(alarm.update(30_50), Some(AlarmState::High));
+        // back below the threshold, but not by the full hysteresis yet
+        assert_eq!(alarm.update(29_50), None);
+        assert_eq!(alarm.update(29_01), None);
+        assert_eq!(alarm.update(29_00), Some(AlarmState::Normal));
+
+        assert_eq!(alarm.update(10_00), Some(AlarmState::Low));
+        assert_eq!(alarm.update(10_99), None);
+        assert_eq!(alarm.update(11_00), Some(AlarmState::Normal));
+    }
+
+    #[test]
+    fn zero_hysteresis_does_not_chatter_at_threshold() {
+        let mut alarm = alarm(Some(30_00), None, 0);
+        assert_eq!(alarm.update(30_00), Some(AlarmState::High));
+        assert_eq!(alarm.update(30_00), None);
+        assert_eq!(alarm.update(29_99), Some(AlarmState::Normal));
+    }
+
+    #[test]
+    fn jumps_straight_from_high_to_low() {
+        let mut alarm = alarm(Some(30_00), Some(10_00), 5_00);
+        assert_eq!(alarm.update(31_00), Some(AlarmState::High));
+        assert_eq!(alarm.update(5_00), Some(AlarmState::Low));
+    }
+
+    #[test]
+    fn removing_threshold_clears_alarm() {
+        let mut alarm = alarm(Some(30_00), None, 1_00);
+        assert_eq!(alarm.update(35_00), Some(AlarmState::High));
+        alarm.set_thresholds(Thresholds {
+            high: None,
+            low: None,
+            hysteresis: 1_00,
+        });
+        assert_eq!(alarm.update(35_00), Some(AlarmState::Normal));
+    }
+
+    #[test]
+    fn default_trigger_settings() {
+        // the initial values of the temperature descriptors
+        let thresholds = Thresholds::default();
+        assert_eq!(thresholds.encode_trigger(Trigger::High), [0x07, 0x70, 0x17]);
+        assert_eq!(thresholds.encode_trigger(Trigger::Low), [0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn trigger_settings_round_trip() {
+        let thresholds = Thresholds {
+            high: Some(45_00),
+            low: Some(-5_00),
+            hysteresis: 50,
+        };
+        let none = Thresholds {
+            high: None,
+            low: None,
+            hysteresis: 50,
+        };
+        let high = thresholds.encode_trigger(Trigger::High);
+        let low = thresholds.encode_trigger(Trigger::Low);
+        let applied = none
+            .with_trigger(Trigger::High, &high)
+            .and_then(|t| t.with_trigger(Trigger::Low, &low));
+        assert_eq!(applied, Ok(thresholds));
+        // inactive clears the threshold
+        assert_eq!(
+            thresholds.with_trigger(Trigger::Low, &[0x00, 0, 0]),
+            Ok(Thresholds {
+                low: None,
+                ..thresholds
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_trigger_settings() {
+        let thresholds = Thresholds {
+            high: Some(45_00),
+            low: Some(-5_00),
+            hysteresis: 50,
+        };
+        // low at or above high
+        let low = 45_00i16.to_le_bytes();
+        assert_eq!(
+            thresholds.with_trigger(Trigger::Low, &[0x05, low[0], low[1]]),
+            Err(TriggerError::Range)
+        );
+        // the high threshold only raises at or above, the low one at or below
+        assert_eq!(
+            thresholds.with_trigger(Trigger::High, &[0x05, 0, 0]),
+            Err(TriggerError::Condition)
+        );
+        assert_eq!(
+            thresholds.with_trigger(Trigger::Low, &[0x06, 0, 0]),
+            Err(TriggerError::Condition)
+        );
+        assert_eq!(
+            thresholds.with_trigger(Trigger::High, &[0x07, 0]),
+            Err(TriggerError::Length)
+        );
+        assert_eq!(
+            thresholds.with_trigger(Trigger::High, &[0x00]),
+            Err(TriggerError::Length)
+        );
+    }
+}
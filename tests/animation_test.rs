@@ -0,0 +1,189 @@
+//! Cow animation asset and player tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::animation::{Library, Row};
+#[cfg(test)]
+use coa_gatt::framebuffer::{Framebuffer, WIDTH};
+
+/// Row `index` of the frame `animation` shows `elapsed` ms after it started.
+#[cfg(test)]
+fn row_at(library: &Library<'static>, name: &str, elapsed: u64, index: usize) -> Row<'static> {
+    let (frame, _) = library.animation(name).unwrap().frame_at(elapsed).unwrap();
+    frame.rows().nth(index).unwrap()
+}
+
+#[cfg(test)]
+fn lit(frame: &Framebuffer, x: usize, y: usize) -> bool {
+    frame.pixels()[(y / 8) * WIDTH + x] & (1 << (y % 8)) != 0
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::animation::{Animator, Event, Library, ParseError, Row, COW, IDLE};
+    use coa_gatt::display::update_display;
+    use coa_gatt::framebuffer::Framebuffer;
+    use defmt::{assert, assert_eq};
+    use embedded_graphics::mono_font::ascii::FONT_6X9;
+    use embedded_graphics::mono_font::MonoTextStyleBuilder;
+    use embedded_graphics::pixelcolor::BinaryColor;
+
+    use super::{lit, row_at};
+
+    const TINY: &str = "\
+anim idle loop
+frame 100
+|a
+frame 300
+|b
+anim flash once
+frame 50
+:#
+on connect flash
+on hot idle
+";
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn shipped_cow_parses() {
+        let library = Library::parse(COW).unwrap();
+        let idle = library.animation(IDLE).unwrap();
+        assert!(idle.looping);
+        assert_eq!(idle.frames().count(), 10);
+        assert_eq!(idle.duration_ms(), 10_000);
+        // winks every ten seconds, rows keep their leading spaces
+        assert_eq!(row_at(&library, IDLE, 0, 1), Row::Text("  ^__^"));
+        assert_eq!(row_at(&library, IDLE, 0, 2), Row::Text(r"B (X.)\____"));
+        assert_eq!(row_at(&library, IDLE, 1000, 2), Row::Text(r"B (oo)\____"));
+
+        assert_eq!(library.on(Event::Connect).unwrap().name, "blink");
+        assert_eq!(library.on(Event::Hot).unwrap().name, "sweat");
+        assert_eq!(library.on(Event::Cold).unwrap().name, "shiver");
+        assert_eq!(library.on(Event::Normal).unwrap().name, IDLE);
+        assert!(library.on(Event::Disconnect).is_none());
+    }
+
+    #[test]
+    fn broken_assets_report_the_line() {
+        let cases: [(&str, usize, &str); 9] = [
+            ("anim idle loop\nframe 10\n|a\nmoo\n", 4, "unknown line"),
+            ("anim idle forever\n", 1, "expected `loop` or `once`"),
+            ("anim idle loop\nframe 0\n", 2, "bad frame duration"),
+            ("anim idle loop\n|a\n", 2, "row outside of a frame"),
+            ("frame 10\n", 1, "frame outside of an animation"),
+            (
+                "anim idle loop\n\nanim x once\nframe 10\n",
+                1,
+                "animation without frames",
+            ),
+            (
+                "anim idle loop\nframe 10\nanim idle once\nframe 10\n",
+                3,
+                "duplicate animation",
+            ),
+            (
+                "anim idle loop\nframe 10\non moo idle\n",
+                3,
+                "unknown event",
+            ),
+            (
+                "anim idle loop\nframe 10\non hot sweat\n",
+                3,
+                "unknown animation",
+            ),
+        ];
+        for (source, line, reason) in cases {
+            assert_eq!(
+                Library::parse(source).unwrap_err(),
+                ParseError { line, reason }
+            );
+        }
+        assert_eq!(
+            Library::parse("anim idle once\nframe 10\n").unwrap_err(),
+            ParseError {
+                line: 1,
+                reason: "idle animation must loop"
+            }
+        );
+        assert_eq!(
+            Library::parse("anim cow loop\nframe 10\n")
+                .unwrap_err()
+                .reason,
+            "no idle animation"
+        );
+    }
+
+    #[test]
+    fn loops_wrap_and_once_ends() {
+        let library = Library::parse(TINY).unwrap();
+        let idle = library.animation(IDLE).unwrap();
+        assert_eq!(row_at(&library, IDLE, 0, 0), Row::Text("a"));
+        assert_eq!(row_at(&library, IDLE, 100, 0), Row::Text("b"));
+        assert_eq!(row_at(&library, IDLE, 450, 0), Row::Text("a"));
+        assert_eq!(idle.frame_at(150).unwrap().1, 250);
+
+        let flash = library.animation("flash").unwrap();
+        assert_eq!(row_at(&library, "flash", 49, 0), Row::Pixels("#"));
+        assert!(flash.frame_at(50).is_none());
+    }
+
+    #[test]
+    fn once_animation_plays_over_the_loop() {
+        let library = Library::parse(TINY).unwrap();
+        let mut animator = Animator::new(library, 1000);
+        animator.handle(Event::Connect, 1020);
+        assert_eq!(animator.playing(1020), "flash");
+        assert_eq!(animator.frame(1020).1, 50);
+        // the loop kept running underneath: 70 ms in is still its first frame
+        assert_eq!(animator.playing(1070), IDLE);
+        assert_eq!(animator.frame(1070).1, 30);
+    }
+
+    #[test]
+    fn looping_event_restarts_only_on_change() {
+        let library = Library::parse(COW).unwrap();
+        let mut animator = Animator::new(library, 0);
+        animator.handle(Event::Hot, 500);
+        assert_eq!(animator.playing(500), "sweat");
+        animator.handle(Event::Hot, 700);
+        assert_eq!(animator.frame(700).1, 200);
+
+        animator.handle(Event::Normal, 2500);
+        let (frame, remaining) = animator.frame(2500);
+        assert_eq!(remaining, 1000);
+        assert_eq!(frame.rows().nth(2), Some(Row::Text(r"B (X.)\____")));
+
+        // no disconnect animation, nothing changes
+        animator.handle(Event::Disconnect, 2600);
+        assert_eq!(animator.frame(2600).1, 900);
+    }
+
+    #[test]
+    fn pixel_rows_are_drawn_as_pixels() {
+        let text_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X9)
+            .text_color(BinaryColor::On)
+            .build();
+        let library = Library::parse(COW).unwrap();
+        let (sweat, _) = library.animation("sweat").unwrap().frame_at(0).unwrap();
+        let mut frame = Framebuffer::new();
+        let Ok(()) = update_display(&mut frame, &sweat, 30, 22, text_style);
+
+        // drop tip on the third pixel row, 6 px above the cursor
+        assert!(lit(&frame, 50, 18));
+        assert!(!lit(&frame, 49, 18));
+        assert!(lit(&frame, 49, 19) && lit(&frame, 51, 19));
+        // seven pixel rows take the place of one text row, the ears stay on the next baseline
+        assert!((22..30).any(|y| lit(&frame, 30 + 12, y)));
+    }
+}
@@ -0,0 +1,119 @@
+//! Battery voltage and charge level tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::battery::{battery_mv, parse_ratio, Average, Battery, Chemistry, AVERAGE_LEN};
+    use defmt::{assert, assert_eq};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn parse_chemistry() {
+        assert_eq!(Chemistry::parse("li-ion"), Some(Chemistry::LiIon));
+        assert_eq!(Chemistry::parse("lifepo4"), Some(Chemistry::LiFePo4));
+        assert_eq!(Chemistry::parse("2xaa"), Some(Chemistry::Alkaline2xAa));
+        assert_eq!(Chemistry::parse("LiPo"), None);
+    }
+
+    #[test]
+    fn li_ion_curve() {
+        assert_eq!(Chemistry::LiIon.percent(4300), 100);
+        assert_eq!(Chemistry::LiIon.percent(4200), 100);
+        assert_eq!(Chemistry::LiIon.percent(3900), 65);
+        // halfway between 3.8 V at 41% and 3.9 V at 65%
+        assert_eq!(Chemistry::LiIon.percent(3850), 53);
+        assert_eq!(Chemistry::LiIon.percent(3300), 0);
+        assert_eq!(Chemistry::LiIon.percent(3000), 0);
+    }
+
+    #[test]
+    fn lifepo4_curve() {
+        assert_eq!(Chemistry::LiFePo4.percent(3400), 100);
+        // the flat part
+        assert_eq!(Chemistry::LiFePo4.percent(3285), 55);
+        assert_eq!(Chemistry::LiFePo4.percent(3100), 7);
+        assert_eq!(Chemistry::LiFePo4.percent(2400), 0);
+    }
+
+    #[test]
+    fn alkaline_curve() {
+        assert_eq!(Chemistry::Alkaline2xAa.percent(3200), 100);
+        assert_eq!(Chemistry::Alkaline2xAa.percent(2600), 50);
+        assert_eq!(Chemistry::Alkaline2xAa.percent(2000), 0);
+    }
+
+    #[test]
+    fn curves_never_go_down_with_voltage() {
+        for chemistry in [
+            Chemistry::LiIon,
+            Chemistry::LiFePo4,
+            Chemistry::Alkaline2xAa,
+        ] {
+            let mut last = 0;
+            for mv in (2000..4400).step_by(5) {
+                let percent = chemistry.percent(mv);
+                assert!(percent >= last && percent <= 100);
+                last = percent;
+            }
+        }
+    }
+
+    #[test]
+    fn parse_divider_ratio() {
+        assert_eq!(parse_ratio("2"), Some(2000));
+        assert_eq!(parse_ratio("1.5"), Some(1500));
+        assert_eq!(parse_ratio("3.125"), Some(3125));
+        assert_eq!(parse_ratio("1"), Some(1000));
+        // a divider does not amplify
+        assert_eq!(parse_ratio("0.5"), None);
+        assert_eq!(parse_ratio("1.2345"), None);
+        assert_eq!(parse_ratio("2.x"), None);
+        assert_eq!(parse_ratio(""), None);
+    }
+
+    #[test]
+    fn divider_scaling() {
+        assert_eq!(battery_mv(1850, 2000), 3700);
+        assert_eq!(battery_mv(2000, 1500), 3000);
+        assert_eq!(battery_mv(u16::MAX, 2000), u16::MAX);
+    }
+
+    #[test]
+    fn average_of_the_last_samples() {
+        let mut average = Average::new();
+        assert_eq!(average.add(3700), 3700);
+        assert_eq!(average.add(3800), 3750);
+        for _ in 0..AVERAGE_LEN {
+            average.add(4000);
+        }
+        // older samples have dropped out
+        assert_eq!(average.add(4000), 4000);
+        assert_eq!(average.add(3200), 3900);
+    }
+
+    #[test]
+    fn power_state() {
+        let good = Battery::new(3900, Chemistry::LiIon);
+        assert!(good.is_present() && !good.is_low());
+        assert_eq!(good.power_state(), 0b10_01_00_11);
+
+        let low = Battery::new(3650, Chemistry::LiIon);
+        assert!(low.is_low());
+        assert_eq!(low.power_state(), 0b11_01_00_11);
+
+        // nothing on the divider, e.g. powered over USB
+        let missing = Battery::new(0, Chemistry::LiIon);
+        assert!(!missing.is_present() && !missing.is_low());
+        assert_eq!(missing.power_state(), 0b00_01_00_10);
+    }
+}
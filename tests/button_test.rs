@@ -0,0 +1,150 @@
+//! Button gesture recognition tests with synthetic timings.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::button::{Gesture, Recognizer};
+#[cfg(test)]
+use heapless::Vec;
+
+/// Drive a recognizer the way the button task does, on every level change and at every
+/// deadline, through `(time, pressed)` changes. Returns the gestures with their times.
+#[cfg(test)]
+fn run(changes: &[(u64, bool)], until: u64) -> Vec<(u64, Gesture), 8> {
+    let mut recognizer = Recognizer::default();
+    let mut gestures = Vec::new();
+    let mut changes = changes.iter().peekable();
+    let mut level = false;
+    let mut now = 0;
+    loop {
+        while let Some(&&(at, pressed)) = changes.peek() {
+            if at > now {
+                break;
+            }
+            level = pressed;
+            changes.next();
+        }
+        if let Some(gesture) = recognizer.update(level, now) {
+            gestures.push((now, gesture)).unwrap();
+        }
+        let next_change = changes.peek().map(|&&(at, _)| at);
+        now = match (next_change, recognizer.deadline()) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => break,
+        };
+        if now > until {
+            break;
+        }
+    }
+    gestures
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::button::{Gesture, Recognizer, DEBOUNCE_MS, DOUBLE_GAP_MS, LONG_MS};
+    use defmt::assert_eq;
+
+    use super::run;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn short_press_waits_for_the_double_press_gap() {
+        let gestures = run(&[(1000, true), (1100, false)], 10_000);
+        assert_eq!(
+            gestures.as_slice(),
+            &[(1100 + DOUBLE_GAP_MS, Gesture::Short)]
+        );
+    }
+
+    #[test]
+    fn contact_bounce_is_one_press() {
+        let changes = [
+            (1000, true),
+            (1003, false),
+            (1006, true),
+            (1010, false),
+            (1012, true),
+            (1200, false),
+            (1204, true),
+            (1207, false),
+        ];
+        let gestures = run(&changes, 10_000);
+        assert_eq!(
+            gestures.as_slice(),
+            &[(1207 + DOUBLE_GAP_MS, Gesture::Short)]
+        );
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_is_ignored() {
+        let gestures = run(&[(1000, true), (1000 + DEBOUNCE_MS - 1, false)], 10_000);
+        assert_eq!(gestures.as_slice(), &[]);
+    }
+
+    #[test]
+    fn double_press_is_reported_on_the_second_release() {
+        let changes = [(1000, true), (1100, false), (1300, true), (1400, false)];
+        let gestures = run(&changes, 10_000);
+        assert_eq!(
+            gestures.as_slice(),
+            &[(1400 + DEBOUNCE_MS, Gesture::Double)]
+        );
+    }
+
+    #[test]
+    fn slow_second_press_is_two_short_presses() {
+        let second = 1100 + DOUBLE_GAP_MS + 50;
+        let changes = [
+            (1000, true),
+            (1100, false),
+            (second, true),
+            (second + 100, false),
+        ];
+        let gestures = run(&changes, 10_000);
+        assert_eq!(
+            gestures.as_slice(),
+            &[
+                (1100 + DOUBLE_GAP_MS, Gesture::Short),
+                (second + 100 + DOUBLE_GAP_MS, Gesture::Short),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_press_is_reported_while_held() {
+        let gestures = run(&[(1000, true), (1000 + LONG_MS + 3000, false)], 20_000);
+        assert_eq!(gestures.as_slice(), &[(1000 + LONG_MS, Gesture::Long)]);
+    }
+
+    #[test]
+    fn late_polling_still_recognises_gestures() {
+        let mut recognizer = Recognizer::default();
+        assert_eq!(recognizer.update(true, 1000), None);
+        assert_eq!(recognizer.deadline(), Some(1000 + DEBOUNCE_MS));
+        assert_eq!(recognizer.update(true, 1000 + DEBOUNCE_MS), None);
+        assert_eq!(recognizer.deadline(), Some(1000 + LONG_MS));
+        assert_eq!(recognizer.update(false, 1100), None);
+        // the debounce and double press deadlines are both missed
+        assert_eq!(recognizer.update(false, 2000), Some(Gesture::Short));
+
+        // pressed, then not polled again until well past the long press time
+        assert_eq!(recognizer.update(true, 3000), None);
+        assert_eq!(
+            recognizer.update(true, 3000 + LONG_MS + 500),
+            Some(Gesture::Long)
+        );
+        assert_eq!(recognizer.update(false, 9000), None);
+        assert_eq!(recognizer.update(false, 9000 + DEBOUNCE_MS), None);
+        assert_eq!(recognizer.deadline(), None);
+    }
+}
@@ -0,0 +1,99 @@
+//! Wall clock date conversion and Current Time characteristic tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::clock::{
+        days_in_month, decode_current_time, encode_current_time, DateTime,
+        ADJUST_EXTERNAL_REFERENCE,
+    };
+    use defmt::{assert, assert_eq};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn unix_epoch() {
+        let dt = DateTime::from_unix_secs(0);
+        assert_eq!(
+            dt,
+            DateTime {
+                year: 1970,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0,
+                // a Thursday
+                weekday: 4,
+            }
+        );
+        assert_eq!(dt.to_unix_secs(), 0);
+    }
+
+    #[test]
+    fn leap_day() {
+        // 2024-02-29T12:34:56Z, a Thursday
+        let dt = DateTime::from_unix_secs(1_709_210_096);
+        assert_eq!((dt.year, dt.month, dt.day), (2024, 2, 29));
+        assert_eq!((dt.hour, dt.minute, dt.second), (12, 34, 56));
+        assert_eq!(dt.weekday, 4);
+        assert_eq!(dt.to_unix_secs(), 1_709_210_096);
+    }
+
+    #[test]
+    fn round_trip() {
+        // a day and a bit apart, across 2100 which is not a leap year
+        let mut secs = 0;
+        while secs < 4_200_000_000 {
+            assert_eq!(DateTime::from_unix_secs(secs).to_unix_secs(), secs);
+            secs += 90_061 * 7;
+        }
+    }
+
+    #[test]
+    fn month_lengths() {
+        assert_eq!(days_in_month(2023, 1), 31);
+        assert_eq!(days_in_month(2023, 2), 28);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2023, 4), 30);
+        assert_eq!(days_in_month(2023, 12), 31);
+    }
+
+    #[test]
+    fn encode() {
+        // 2024-02-29T12:34:56.500Z
+        let value = encode_current_time(1_709_210_096_500, ADJUST_EXTERNAL_REFERENCE);
+        assert_eq!(value, [0xE8, 0x07, 2, 29, 12, 34, 56, 4, 128, 0b10]);
+    }
+
+    #[test]
+    fn decode() {
+        let value = [0xE8, 0x07, 2, 29, 12, 34, 56, 4, 128, 0];
+        assert_eq!(decode_current_time(&value), Some(1_709_210_096_500));
+        // weekday, fractions and adjust reason are optional
+        assert_eq!(decode_current_time(&value[..7]), Some(1_709_210_096_000));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_dates() {
+        assert_eq!(decode_current_time(&[0xE8, 0x07, 2, 29]), None);
+        assert_eq!(decode_current_time(&[0xE7, 0x07, 2, 29, 0, 0, 0]), None);
+        assert_eq!(decode_current_time(&[0xE8, 0x07, 4, 31, 0, 0, 0]), None);
+        assert!(decode_current_time(&[0xE8, 0x07, 4, 30, 0, 0, 0]).is_some());
+        assert_eq!(decode_current_time(&[0xE8, 0x07, 13, 1, 0, 0, 0]), None);
+        assert_eq!(decode_current_time(&[0xE8, 0x07, 1, 0, 0, 0, 0]), None);
+        assert_eq!(decode_current_time(&[0xE8, 0x07, 1, 1, 24, 0, 0]), None);
+        assert_eq!(decode_current_time(&[0xB1, 0x07, 1, 1, 0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Display power policy and settings persistence tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::settings::DisplaySettings;
+
+#[cfg(test)]
+fn settings(dim_after_secs: u16, off_after_secs: u16) -> DisplaySettings {
+    DisplaySettings {
+        dim_after_secs,
+        off_after_secs,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::display_power::{DisplayPower, PowerState, SHIFT_PERIOD_SECS};
+    use coa_gatt::settings::{self, DisplaySettings, Settings, FLASH_OFFSET, RECORD_LEN};
+    use defmt::{assert, assert_eq};
+    use embedded_storage::{ReadStorage, Storage};
+
+    use super::settings;
+
+    /// Erased flash around the settings record.
+    struct RamFlash([u8; 64]);
+
+    impl ReadStorage for RamFlash {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+            let start = (offset - FLASH_OFFSET) as usize;
+            bytes.copy_from_slice(self.0.get(start..start + bytes.len()).ok_or(())?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            FLASH_OFFSET as usize + self.0.len()
+        }
+    }
+
+    impl Storage for RamFlash {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+            let start = (offset - FLASH_OFFSET) as usize;
+            self.0
+                .get_mut(start..start + bytes.len())
+                .ok_or(())?
+                .copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn dims_then_switches_off() {
+        let mut power = DisplayPower::new(settings(60, 300), 0);
+        assert_eq!(power.state(59), PowerState::On);
+        assert_eq!(power.state(60), PowerState::Dimmed);
+        assert_eq!(power.contrast(60), Some(power.settings().dim_contrast));
+        assert_eq!(power.state(300), PowerState::Off);
+        assert_eq!(power.contrast(300), None);
+
+        power.activity(400);
+        assert_eq!(power.state(400), PowerState::On);
+        assert_eq!(power.contrast(400), Some(power.settings().contrast));
+    }
+
+    #[test]
+    fn zero_timeouts_never_fire() {
+        let power = DisplayPower::new(settings(0, 0), 0);
+        assert_eq!(power.state(u32::MAX as u64), PowerState::On);
+    }
+
+    #[test]
+    fn connection_keeps_display_on() {
+        let mut power = DisplayPower::new(settings(60, 300), 0);
+        power.set_connected(true, 10);
+        assert_eq!(power.state(1000), PowerState::On);
+        // disconnecting restarts the timeouts
+        power.set_connected(false, 1000);
+        assert_eq!(power.state(1059), PowerState::On);
+        assert_eq!(power.state(1060), PowerState::Dimmed);
+
+        let mut power = DisplayPower::new(
+            DisplaySettings {
+                on_while_connected: false,
+                ..settings(60, 300)
+            },
+            0,
+        );
+        power.set_connected(true, 0);
+        assert_eq!(power.state(300), PowerState::Off);
+    }
+
+    #[test]
+    fn pixel_shift_stays_close_and_can_be_disabled() {
+        let power = DisplayPower::new(settings(60, 300), 0);
+        assert_eq!(power.shift(0), (0, 0));
+        assert!(power.shift(SHIFT_PERIOD_SECS) != (0, 0));
+        for step in 0..16 {
+            let (dx, dy) = power.shift(step * SHIFT_PERIOD_SECS);
+            assert!(dx.abs() <= 2 && dy.abs() <= 2);
+        }
+
+        let fixed = DisplayPower::new(
+            DisplaySettings {
+                pixel_shift: false,
+                ..settings(60, 300)
+            },
+            0,
+        );
+        assert_eq!(fixed.shift(SHIFT_PERIOD_SECS), (0, 0));
+    }
+
+    #[test]
+    fn display_settings_round_trip_and_validate() {
+        let display = DisplaySettings {
+            contrast: 0xFF,
+            dim_contrast: 0x10,
+            dim_after_secs: 30,
+            off_after_secs: 3600,
+            on_while_connected: false,
+            pixel_shift: true,
+        };
+        assert_eq!(DisplaySettings::decode(&display.encode()), Some(display));
+
+        // dimming after the display is already off
+        assert_eq!(DisplaySettings::decode(&settings(300, 60).encode()), None);
+        // unknown flag
+        let mut encoded = display.encode();
+        encoded[6] |= 0x80;
+        assert_eq!(DisplaySettings::decode(&encoded), None);
+        assert_eq!(DisplaySettings::decode(&encoded[..5]), None);
+    }
+
+    #[test]
+    fn settings_survive_a_store_and_load() {
+        let mut flash = RamFlash([0xFF; 64]);
+        assert_eq!(settings::load(&mut flash), None);
+
+        let saved = Settings {
+            display: settings(10, 20),
+            ..Default::default()
+        };
+        settings::store(&mut flash, &saved).unwrap();
+        assert_eq!(settings::load(&mut flash), Some(saved));
+
+        // a flipped bit fails the CRC
+        flash.0[RECORD_LEN / 2] ^= 0x01;
+        assert_eq!(settings::load(&mut flash), None);
+    }
+
+    #[test]
+    fn version_1_record_keeps_the_display_settings() {
+        let mut flash = RamFlash([0xFF; 64]);
+        // magic, version 1, display settings and their CRC-8, as written by older firmware
+        let display = settings(10, 20).encode();
+        let mut record = [0; 11];
+        record[..3].copy_from_slice(b"CS\x01");
+        record[3..10].copy_from_slice(&display);
+        record[10] = 0x4B;
+        flash.write(FLASH_OFFSET, &record).unwrap();
+        assert_eq!(
+            settings::load(&mut flash),
+            Some(Settings {
+                display: settings(10, 20),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn factory_reset_erases_the_record() {
+        let mut flash = RamFlash([0xFF; 64]);
+        settings::store(&mut flash, &Settings::default()).unwrap();
+        settings::erase(&mut flash).unwrap();
+        assert_eq!(settings::load(&mut flash), None);
+        assert!(flash.0.iter().all(|&byte| byte == 0xFF));
+    }
+}
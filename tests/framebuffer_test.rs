@@ -0,0 +1,165 @@
+//! Framebuffer diffing tests, flushing into the mock display.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[cfg(test)]
+use coa_gatt::framebuffer::Framebuffer;
+#[cfg(test)]
+use coa_gatt::mock::MockDisplay;
+#[cfg(test)]
+use embedded_graphics::pixelcolor::BinaryColor;
+#[cfg(test)]
+use embedded_graphics::prelude::*;
+
+/// A framebuffer whose first full flush has already gone out.
+#[cfg(test)]
+async fn synced() -> (Framebuffer, MockDisplay) {
+    let mut frame = Framebuffer::new();
+    let mut display = MockDisplay::new();
+    frame.flush(&mut display).await.unwrap();
+    display.take_writes();
+    (frame, display)
+}
+
+#[cfg(test)]
+fn set(frame: &mut Framebuffer, x: i32, y: i32) {
+    let Ok(()) = Pixel(Point::new(x, y), BinaryColor::On).draw(frame);
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::animation::{Library, COW, IDLE};
+    use coa_gatt::display::update_display;
+    use coa_gatt::framebuffer::{Framebuffer, Span, BUFFER_LEN, PAGES, WIDTH};
+    use coa_gatt::mock::{MockDisplay, MockWrite};
+    use defmt::{assert, assert_eq};
+    use embedded_graphics::mono_font::ascii::FONT_6X9;
+    use embedded_graphics::mono_font::MonoTextStyleBuilder;
+    use embedded_graphics::pixelcolor::BinaryColor;
+    use embedded_graphics::prelude::*;
+    use esp_hal::timer::systimer::SystemTimer;
+
+    use super::{set, synced};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        esp_alloc::heap_allocator!(size: 64 * 1024);
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    async fn first_flush_sends_every_page() {
+        let mut frame = Framebuffer::new();
+        let mut display = MockDisplay::new();
+        assert_eq!(frame.flush(&mut display).await, Ok(BUFFER_LEN));
+
+        let writes = display.take_writes();
+        assert_eq!(writes.len(), PAGES);
+        for (page, write) in writes.iter().enumerate() {
+            assert_eq!(write.page as usize, page);
+            assert_eq!(write.column, 0);
+            assert_eq!(write.data.len(), WIDTH);
+        }
+    }
+
+    #[test]
+    async fn unchanged_frame_sends_nothing() {
+        let (mut frame, mut display) = synced().await;
+        let Ok(()) = frame.clear(BinaryColor::Off);
+        assert_eq!(frame.flush(&mut display).await, Ok(0));
+        assert!(display.take_writes().is_empty());
+    }
+
+    #[test]
+    async fn single_pixel_sends_single_byte() {
+        let (mut frame, mut display) = synced().await;
+        // page 2, bit 3
+        set(&mut frame, 40, 19);
+        assert_eq!(frame.flush(&mut display).await, Ok(1));
+        // MockWrite holds a Vec and has no defmt::Format, compare without printing
+        assert!(
+            display.take_writes().as_slice()
+                == [MockWrite {
+                    page: 2,
+                    column: 40,
+                    data: alloc::vec![0x08],
+                }]
+        );
+    }
+
+    #[test]
+    async fn close_changes_merge_and_distant_ones_split() {
+        let (mut frame, _) = synced().await;
+        // five unchanged columns between them: cheaper to resend than to re-address
+        set(&mut frame, 10, 0);
+        set(&mut frame, 16, 0);
+        // six unchanged columns: split
+        set(&mut frame, 23, 0);
+        let mut spans = frame.spans();
+        assert_eq!(
+            spans.next(),
+            Some(Span {
+                page: 0,
+                start: 10,
+                end: 17
+            })
+        );
+        assert_eq!(
+            spans.next(),
+            Some(Span {
+                page: 0,
+                start: 23,
+                end: 24
+            })
+        );
+        assert_eq!(spans.next(), None);
+    }
+
+    #[test]
+    async fn spans_never_cross_pages() {
+        let (mut frame, mut display) = synced().await;
+        set(&mut frame, 127, 0);
+        set(&mut frame, 0, 8);
+        assert_eq!(frame.flush(&mut display).await, Ok(2));
+        let writes = display.take_writes();
+        assert_eq!(writes.len(), 2);
+        assert_eq!((writes[0].page, writes[0].column), (0, 127));
+        assert_eq!((writes[1].page, writes[1].column), (1, 0));
+    }
+
+    #[test]
+    async fn invalidate_resends_everything() {
+        let (mut frame, mut display) = synced().await;
+        frame.invalidate();
+        assert_eq!(frame.flush(&mut display).await, Ok(BUFFER_LEN));
+    }
+
+    #[test]
+    async fn counter_tick_sends_less_than_a_frame() {
+        let text_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X9)
+            .text_color(BinaryColor::On)
+            .build();
+        let idle = Library::parse(COW).unwrap().animation(IDLE).unwrap();
+        let mut cows = idle.frames().skip(1);
+        let (mut frame, mut display) = synced().await;
+        let Ok(()) = update_display(&mut frame, &cows.next().unwrap(), 30, 22, text_style);
+        frame.flush(&mut display).await.unwrap();
+
+        let Ok(()) = update_display(&mut frame, &cows.next().unwrap(), 30, 22, text_style);
+        let sent = frame.flush(&mut display).await.unwrap();
+        assert!(sent > 0 && sent < BUFFER_LEN / 4);
+        // the panel ends up with exactly the drawn frame
+        assert_eq!(display.buffer().as_slice(), frame.pixels().as_slice());
+    }
+}
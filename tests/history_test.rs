@@ -0,0 +1,172 @@
+//! Temperature history and record access control point tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::history::History;
+
+/// A history with one sample per 60 s interval, `value` in interval `i` is `i`.
+#[cfg(test)]
+fn filled<const N: usize>(intervals: u32) -> History<N> {
+    let mut history = History::new(60);
+    for i in 0..=intervals {
+        history.record(i * 60, i as i16);
+    }
+    history
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::history::{
+        centi_celsius, racp, racp_count, racp_response, History, RecordRequest, Summary,
+    };
+    use defmt::{assert, assert_eq};
+
+    use super::filled;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn centi_celsius_saturates() {
+        assert_eq!(centi_celsius(21.5), 2150);
+        assert_eq!(centi_celsius(-5.25), -525);
+        assert_eq!(centi_celsius(400.0), i16::MAX);
+        assert_eq!(centi_celsius(-400.0), i16::MIN);
+    }
+
+    #[test]
+    fn summary_encoding() {
+        let summary = Summary {
+            start: 60,
+            min: -100,
+            avg: 250,
+            max: 600,
+            count: 30,
+        };
+
+        assert_eq!(
+            summary.encode(0x1234_5678),
+            [0x78, 0x56, 0x34, 0x12, 0x9C, 0xFF, 0xFA, 0x00, 0x58, 0x02, 30, 0]
+        );
+    }
+
+    #[test]
+    fn intervals_close_on_the_next_one() {
+        let mut history: History<4> = History::new(60);
+        history.record(0, 100);
+        history.record(20, 300);
+        history.record(59, 200);
+        assert!(history.is_empty());
+        history.record(61, 0);
+        assert_eq!(history.len(), 1);
+        let summary = history.first_in_range(0, u32::MAX).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                start: 0,
+                min: 100,
+                avg: 200,
+                max: 300,
+                count: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn oldest_intervals_are_dropped() {
+        // intervals 0 to 9 are complete, 4 fit
+        let history: History<4> = filled(10);
+        assert_eq!(history.len(), 4);
+        let starts = history.range(0, u32::MAX).map(|s| s.start);
+        assert!(starts.eq([360, 420, 480, 540]));
+    }
+
+    #[test]
+    fn range_is_inclusive() {
+        let history: History<8> = filled(6);
+        assert_eq!(history.range(60, 180).count(), 3);
+        assert_eq!(history.range(61, 179).count(), 1);
+        assert_eq!(history.range(1000, 2000).count(), 0);
+    }
+
+    #[test]
+    fn first_in_range_steps_through_a_wrapped_buffer() {
+        // the ring buffer has wrapped, so the records span both of its halves
+        let history: History<4> = filled(6);
+        let mut next = 0;
+        let mut values = heapless::Vec::<i16, 4>::new();
+        while let Some(summary) = history.first_in_range(next, 300) {
+            values.push(summary.avg).unwrap();
+            next = summary.start + 1;
+        }
+        assert_eq!(values, [2, 3, 4, 5]);
+        assert_eq!(history.first_in_range(150, 230).map(|s| s.start), Some(180));
+        assert_eq!(history.first_in_range(301, u32::MAX), None);
+        assert_eq!(history.first_in_range(0, 100), None);
+    }
+
+    #[test]
+    fn parse_report_and_count() {
+        assert_eq!(
+            RecordRequest::parse(&[racp::REPORT_RECORDS, racp::OPERATOR_ALL]),
+            Ok(RecordRequest::Report {
+                from: 0,
+                to: u32::MAX
+            })
+        );
+        let range = [0x01, 0x04, 0x02, 10, 0, 0, 0, 20, 0, 0, 0];
+        assert_eq!(
+            RecordRequest::parse(&range),
+            Ok(RecordRequest::Report { from: 10, to: 20 })
+        );
+        let count = [0x04, 0x04, 0x02, 10, 0, 0, 0, 20, 0, 0, 0];
+        assert_eq!(
+            RecordRequest::parse(&count),
+            Ok(RecordRequest::Count { from: 10, to: 20 })
+        );
+        assert_eq!(
+            RecordRequest::parse(&[racp::ABORT, 0]),
+            Ok(RecordRequest::Abort)
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(RecordRequest::parse(&[]), Err(racp::INVALID_OPERAND));
+        assert_eq!(
+            RecordRequest::parse(&[0x02, 0x01]),
+            Err(racp::OP_CODE_NOT_SUPPORTED)
+        );
+        assert_eq!(
+            RecordRequest::parse(&[0x01, 0x02]),
+            Err(racp::INVALID_OPERATOR)
+        );
+        // from after to
+        let reversed = [0x01, 0x04, 0x02, 20, 0, 0, 0, 10, 0, 0, 0];
+        assert_eq!(RecordRequest::parse(&reversed), Err(racp::INVALID_OPERAND));
+        // sequence number filter
+        let sequence = [0x01, 0x04, 0x01, 10, 0, 0, 0, 20, 0, 0, 0];
+        assert_eq!(RecordRequest::parse(&sequence), Err(racp::INVALID_OPERAND));
+        assert_eq!(
+            RecordRequest::parse(&[0x01, 0x04, 0x02, 10]),
+            Err(racp::INVALID_OPERAND)
+        );
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(
+            racp_response(racp::REPORT_RECORDS, racp::NO_RECORDS),
+            [0x06, 0x00, 0x01, 0x06]
+        );
+        assert_eq!(racp_count(0x0102), [0x05, 0x00, 0x02, 0x01]);
+    }
+}
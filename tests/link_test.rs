@@ -0,0 +1,105 @@
+//! Connection parameter policy and link diagnostics tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::link::{Link, LinkPolicy, Profile, FAST, IDLE, IDLE_AFTER_MS};
+    use coa_gatt::proximity::Proximity;
+    use defmt::{assert, assert_eq};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn profiles_are_valid() {
+        assert!(FAST.is_valid());
+        assert!(IDLE.is_valid());
+
+        let invalid = [
+            // below 7.5 ms
+            Profile {
+                interval_min_us: 5_000,
+                ..FAST
+            },
+            Profile {
+                interval_min_us: 40_000,
+                ..FAST
+            },
+            Profile {
+                latency: 500,
+                ..IDLE
+            },
+            // the timeout has to cover two effective intervals: 2 * 5 * 400 ms
+            Profile {
+                timeout_ms: 4_000,
+                ..IDLE
+            },
+            Profile {
+                timeout_ms: 40_000,
+                ..IDLE
+            },
+        ];
+        for profile in invalid {
+            assert!(!profile.is_valid());
+        }
+    }
+
+    #[test]
+    fn fast_while_active_then_idle() {
+        let mut policy = LinkPolicy::new(1_000);
+        assert_eq!(policy.poll(1_000), Some(FAST));
+        assert_eq!(policy.poll(2_000), None);
+        assert_eq!(policy.due_in(2_000), Some(IDLE_AFTER_MS - 1_000));
+
+        policy.activity(5_000);
+        assert_eq!(policy.poll(5_000 + IDLE_AFTER_MS - 1), None);
+        assert_eq!(policy.poll(5_000 + IDLE_AFTER_MS), Some(IDLE));
+        assert_eq!(policy.due_in(5_000 + IDLE_AFTER_MS), None);
+        assert_eq!(policy.poll(100_000), None);
+
+        // a transfer brings the fast parameters back
+        policy.activity(100_000);
+        assert_eq!(policy.due_in(100_000), Some(0));
+        assert_eq!(policy.poll(100_000), Some(FAST));
+    }
+
+    #[test]
+    fn late_activity_reports_do_not_go_back_in_time() {
+        let mut policy = LinkPolicy::new(0);
+        policy.poll(0);
+        policy.activity(8_000);
+        policy.activity(3_000);
+        assert_eq!(policy.due_in(8_000), Some(IDLE_AFTER_MS));
+    }
+
+    #[test]
+    fn link_encoding() {
+        let link = Link {
+            interval_us: 30_000,
+            latency: 4,
+            timeout_ms: 6_000,
+            mtu: 247,
+            rssi: Some(-60),
+            proximity: Proximity::Near,
+        };
+        assert_eq!(
+            link.encode(),
+            [
+                0x18, 0x00, // 24 * 1.25 ms
+                0x04, 0x00, // latency
+                0x58, 0x02, // 600 * 10 ms
+                0xF7, 0x00, // MTU
+                0xC4, // -60 dBm
+            ]
+        );
+        assert_eq!(Link::default().encode()[8], 0x7F);
+    }
+}
@@ -0,0 +1,112 @@
+//! mDNS responder tests against captured query packets.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::mdns::{Responder, SERVICE_PORT};
+
+#[cfg(test)]
+fn responder() -> Responder<'static> {
+    Responder {
+        hostname: "cow-gatt-e4ff",
+        instance: "COW GATT e4ff",
+        ipv4: [192, 168, 1, 42],
+        port: SERVICE_PORT,
+        txt: &["ver=0.1.0", "mac=ff:8f:1a:05:e4:ff"],
+    }
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::mdns::hostname_from_mac;
+    use defmt::assert_eq;
+
+    use super::responder;
+
+    /// `dns-sd -B _coagatt._tcp` from macOS: PTR query for `_coagatt._tcp.local`, QU bit set.
+    const BROWSE_QUERY: [u8; 37] = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, b'_', b'c',
+        b'o', b'a', b'g', b'a', b't', b't', 0x04, b'_', b't', b'c', b'p', 0x05, b'l', b'o', b'c',
+        b'a', b'l', 0x00, 0x00, 0x0c, 0x80, 0x01,
+    ];
+
+    /// `ping cow-gatt-e4ff.local`: A query for the host, name in upper case.
+    const HOST_QUERY: [u8; 37] = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0d, b'C', b'O',
+        b'W', b'-', b'G', b'A', b'T', b'T', b'-', b'E', b'4', b'F', b'F', 0x05, b'l', b'o', b'c',
+        b'a', b'l', 0x00, 0x00, 0x01, 0x00, 0x01,
+    ];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn hostname_uses_last_mac_bytes() {
+        let name = hostname_from_mac(&[0xff, 0x8f, 0x1a, 0x05, 0xe4, 0xff]);
+        assert_eq!(name.as_str(), "cow-gatt-e4ff");
+    }
+
+    #[test]
+    fn browse_query_gets_ptr_and_additional_address() {
+        let answers = responder().match_query(&BROWSE_QUERY);
+        assert!(answers.ptr);
+        assert!(!answers.a);
+        assert!(!answers.srv);
+        let mut out = [0u8; 512];
+        let len = responder().respond(&BROWSE_QUERY, &mut out).unwrap();
+        // the PTR answer, then the A record in the additional section
+        assert_eq!(&out[6..12], &[0, 1, 0, 0, 0, 1]);
+        assert_eq!(&out[len - 4..len], &[192, 168, 1, 42]);
+    }
+
+    #[test]
+    fn host_query_is_case_insensitive() {
+        let mut out = [0u8; 512];
+        let len = responder().respond(&HOST_QUERY, &mut out).unwrap();
+        // one answer, ending with the A record data
+        assert_eq!(&out[6..8], &[0, 1]);
+        assert_eq!(&out[len - 4..len], &[192, 168, 1, 42]);
+    }
+
+    #[test]
+    fn legacy_unicast_reply_repeats_id_and_question() {
+        // `dig -p 5353 @224.0.0.251 cow-gatt-e4ff.local` sends from a random port with an ID
+        let mut query = HOST_QUERY;
+        query[..2].copy_from_slice(&[0x12, 0x34]);
+        let mut out = [0u8; 512];
+        let len = responder().respond_unicast(&query, &mut out).unwrap();
+        assert_eq!(&out[..2], &[0x12, 0x34]);
+        // one question, one answer
+        assert_eq!(&out[4..8], &[0, 1, 0, 1]);
+        assert_eq!(&out[12..37], &query[12..37]);
+        // A record: same name as the question, class IN without cache flush, TTL 10 s
+        assert_eq!(&out[37 + 21..37 + 25], &[0, 1, 0, 1]);
+        assert_eq!(&out[37 + 25..37 + 29], &[0, 0, 0, 10]);
+        assert_eq!(&out[len - 4..len], &[192, 168, 1, 42]);
+        assert_eq!(len, 37 + 21 + 14);
+    }
+
+    #[test]
+    fn multicast_reply_has_no_id_or_question() {
+        let mut query = HOST_QUERY;
+        query[..2].copy_from_slice(&[0x12, 0x34]);
+        let mut out = [0u8; 512];
+        responder().respond(&query, &mut out).unwrap();
+        assert_eq!(&out[..6], &[0, 0, 0x84, 0, 0, 0]);
+    }
+
+    #[test]
+    fn responses_are_ignored() {
+        let mut response = HOST_QUERY;
+        response[2] = 0x84;
+        let mut out = [0u8; 512];
+        assert!(responder().respond(&response, &mut out).is_none());
+    }
+}
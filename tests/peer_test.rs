@@ -0,0 +1,188 @@
+//! Peripheral target list, reconnection, subscription and value tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::mock::{MockGattError, MockPeripheral};
+#[cfg(test)]
+use coa_gatt::peer::{heart_rate, listen, Backoff, Target};
+
+#[cfg(test)]
+const STRAP: &str = "C8:5C:A2:11:22:33/180D/2A37";
+
+/// Connect to a strap that already failed twice, record every heart rate it reports.
+#[cfg(test)]
+async fn listen_to(
+    peripheral: &MockPeripheral,
+) -> (Result<(), MockGattError>, heapless::Vec<u16, 4>, Backoff) {
+    let mut backoff = Backoff::new();
+    backoff.next_delay();
+    backoff.next_delay();
+    let mut rates = heapless::Vec::new();
+    let target = Target::parse(STRAP).unwrap();
+    let result = listen(peripheral, &target, &mut backoff, |data| {
+        let _ = rates.push(heart_rate(data).unwrap());
+    })
+    .await;
+    (result, rates, backoff)
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::mock::{MockGattError, MockPeripheral};
+    use coa_gatt::peer::{
+        entries, heart_rate, AddressKind, Backoff, PeerUuid, PeerValue, Target, PEER_RECORD_LEN,
+        PEER_VALUE_LEN, RETRY_MAX_MS, RETRY_MIN_MS,
+    };
+    use defmt::{assert, assert_eq};
+
+    use super::{listen_to, STRAP};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn heart_rate_strap_target() {
+        let target = Target::parse("C8:5C:A2:11:22:33/180D/2A37").unwrap();
+        assert_eq!(target.address, [0x33, 0x22, 0x11, 0xA2, 0x5C, 0xC8]);
+        assert_eq!(target.kind, AddressKind::Public);
+        assert_eq!(target.service, PeerUuid::Short(0x180D));
+        assert_eq!(target.characteristic, PeerUuid::Short(0x2A37));
+    }
+
+    #[test]
+    fn cow_board_target_with_long_uuids() {
+        let target = Target::parse(
+            "FF:E4:05:1A:8F:FF/FD2B4448-AA0F-4A15-A62F-EB0BE77A0000/\
+             408813df-5dd4-1f87-ec11-cdb001100000/random",
+        )
+        .unwrap();
+        assert_eq!(target.kind, AddressKind::Random);
+        assert_eq!(
+            target.characteristic,
+            PeerUuid::Long([
+                0x00, 0x00, 0x10, 0x01, 0xB0, 0xCD, 0x11, 0xEC, 0x87, 0x1F, 0xD4, 0x5D, 0xDF, 0x13,
+                0x88, 0x40,
+            ])
+        );
+    }
+
+    #[test]
+    fn malformed_targets() {
+        let invalid = [
+            "",
+            "C8:5C:A2:11:22/180D/2A37",
+            "C8:5C:A2:11:22:33:44/180D/2A37",
+            "C8:5C:A2:11:22:3/180D/2A37",
+            "C8:5C:A2:11:22:33/180D",
+            "C8:5C:A2:11:22:33/18D/2A37",
+            "C8:5C:A2:11:22:33/180D/2A37/static",
+            "C8:5C:A2:11:22:33/180D/2A37/random/more",
+            // dashes in the wrong place
+            "C8:5C:A2:11:22:33/180D/408813df5-dd4-1f87-ec11-cdb001100000",
+            "C8:5C:A2:11:22:33/180D/408813dg-5dd4-1f87-ec11-cdb001100000",
+        ];
+        for entry in invalid {
+            assert_eq!(Target::parse(entry), None);
+        }
+    }
+
+    #[test]
+    fn target_list_entries() {
+        let mut list = entries(" A/1/2, B/3/4  C/5/6,,");
+        assert_eq!(list.next(), Some("A/1/2"));
+        assert_eq!(list.next(), Some("B/3/4"));
+        assert_eq!(list.next(), Some("C/5/6"));
+        assert_eq!(list.next(), None);
+        assert_eq!(entries("").count(), 0);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.next_delay(), RETRY_MIN_MS);
+        assert_eq!(backoff.next_delay(), 2 * RETRY_MIN_MS);
+        assert_eq!(backoff.next_delay(), 4 * RETRY_MIN_MS);
+        for _ in 0..10 {
+            assert!(backoff.next_delay() <= RETRY_MAX_MS);
+        }
+        assert_eq!(backoff.next_delay(), RETRY_MAX_MS);
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), RETRY_MIN_MS);
+    }
+
+    #[test]
+    fn heart_rate_measurement() {
+        assert_eq!(heart_rate(&[0x00, 72]), Some(72));
+        // 16 bit value, with energy expended and RR intervals after it
+        assert_eq!(heart_rate(&[0x19, 0x2C, 0x01, 0x10, 0x00]), Some(300));
+        assert_eq!(heart_rate(&[0x01, 0x2C]), None);
+        assert_eq!(heart_rate(&[]), None);
+    }
+
+    #[test]
+    fn peer_value_record() {
+        let mut value = PeerValue::new();
+        value.connected = true;
+        value.set(&[0x00, 72]);
+        let mut expected = [0u8; PEER_RECORD_LEN];
+        expected[..5].copy_from_slice(&[1, 1, 2, 0x00, 72]);
+        assert_eq!(value.encode(1), expected);
+
+        // long values are cut to fit one notification
+        value.set(&[0xAB; 40]);
+        assert_eq!(value.value.len(), PEER_VALUE_LEN);
+        let record = value.encode(0);
+        assert_eq!(record[2] as usize, PEER_VALUE_LEN);
+        assert!(record[3..].iter().all(|&byte| byte == 0xAB));
+    }
+
+    #[test]
+    async fn listen_reads_then_records_notifications() {
+        let mut peripheral = MockPeripheral::new(&Target::parse(STRAP).unwrap());
+        peripheral.value = Some(&[0x00, 60]);
+        peripheral.notifications = &[&[0x00, 72], &[0x01, 0x2C, 0x01]];
+
+        let (result, rates, mut backoff) = listen_to(&peripheral).await;
+        assert_eq!(result, Err(MockGattError::Disconnected));
+        assert_eq!(rates.as_slice(), &[60, 72, 300]);
+        assert!(peripheral.subscribed.get());
+        // subscribing counts as a connection that worked
+        assert_eq!(backoff.next_delay(), RETRY_MIN_MS);
+    }
+
+    #[test]
+    async fn listen_to_notify_only_characteristic() {
+        let mut peripheral = MockPeripheral::new(&Target::parse(STRAP).unwrap());
+        peripheral.notifications = &[&[0x00, 72]];
+
+        let (result, rates, _) = listen_to(&peripheral).await;
+        assert_eq!(result, Err(MockGattError::Disconnected));
+        assert_eq!(rates.as_slice(), &[72]);
+    }
+
+    #[test]
+    async fn listen_without_service_or_characteristic() {
+        let mut peripheral = MockPeripheral::new(&Target::parse(STRAP).unwrap());
+        peripheral.service = PeerUuid::Short(0x180F);
+        let (result, rates, mut backoff) = listen_to(&peripheral).await;
+        assert_eq!(result, Ok(()));
+        assert!(rates.is_empty());
+        assert_eq!(backoff.next_delay(), 4 * RETRY_MIN_MS);
+
+        let mut peripheral = MockPeripheral::new(&Target::parse(STRAP).unwrap());
+        peripheral.characteristic = PeerUuid::Short(0x2A38);
+        let (result, rates, _) = listen_to(&peripheral).await;
+        assert_eq!(result, Err(MockGattError::NotFound));
+        assert!(rates.is_empty());
+        assert!(!peripheral.subscribed.get());
+    }
+}
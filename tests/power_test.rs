@@ -0,0 +1,104 @@
+//! Board power policy tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::power::{
+        Mode, PowerConfig, PowerPolicy, ACTIVE_SAMPLE_SECS, IDLE_AFTER_SECS, IDLE_SAMPLE_SECS,
+    };
+    use defmt::assert_eq;
+
+    const CONFIG: PowerConfig = PowerConfig {
+        sleep_after_secs: 300,
+        wake_after_secs: 600,
+    };
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn parse_config() {
+        assert_eq!(PowerConfig::parse("300", "600"), Some(CONFIG));
+        assert_eq!(
+            PowerConfig::parse("0", "0"),
+            Some(PowerConfig {
+                sleep_after_secs: 0,
+                wake_after_secs: 0
+            })
+        );
+        // sleeping without ever waking up
+        assert_eq!(PowerConfig::parse("300", "0"), None);
+        assert_eq!(PowerConfig::parse("", "600"), None);
+        assert_eq!(PowerConfig::parse("5m", "600"), None);
+    }
+
+    #[test]
+    fn idle_then_sleep_without_a_connection() {
+        let policy = PowerPolicy::new(CONFIG, 100);
+        assert_eq!(policy.mode(100), Mode::Active);
+        assert_eq!(policy.mode(100 + IDLE_AFTER_SECS - 1), Mode::Active);
+        assert_eq!(policy.mode(100 + IDLE_AFTER_SECS), Mode::Idle);
+        assert_eq!(policy.mode(399), Mode::Idle);
+        assert_eq!(policy.mode(400), Mode::Sleep);
+    }
+
+    #[test]
+    fn never_sleeps_when_disabled() {
+        let config = PowerConfig {
+            sleep_after_secs: 0,
+            ..CONFIG
+        };
+        let policy = PowerPolicy::new(config, 0);
+        assert_eq!(policy.mode(1_000_000), Mode::Idle);
+    }
+
+    #[test]
+    fn connection_keeps_it_active() {
+        let mut policy = PowerPolicy::new(CONFIG, 0);
+        policy.set_connected(true, 10);
+        assert_eq!(policy.mode(10_000), Mode::Active);
+        // the sleep timeout starts over at the disconnect
+        policy.set_connected(false, 10_000);
+        assert_eq!(policy.mode(10_000 + IDLE_AFTER_SECS), Mode::Idle);
+        assert_eq!(policy.mode(10_299), Mode::Idle);
+        assert_eq!(policy.mode(10_300), Mode::Sleep);
+    }
+
+    #[test]
+    fn activity_wakes_from_idle() {
+        let mut policy = PowerPolicy::new(CONFIG, 0);
+        assert_eq!(policy.mode(200), Mode::Idle);
+        policy.activity(200);
+        assert_eq!(policy.mode(200), Mode::Active);
+        assert_eq!(policy.mode(499), Mode::Idle);
+        // a late report does not go back in time
+        policy.activity(100);
+        assert_eq!(policy.mode(500), Mode::Sleep);
+    }
+
+    #[test]
+    fn busy_holds_off_sleep() {
+        let mut policy = PowerPolicy::new(CONFIG, 0);
+        policy.set_busy(true, 100);
+        assert_eq!(policy.mode(1_000), Mode::Active);
+        // idle and sleep times count from the end
+        policy.set_busy(false, 1_000);
+        assert_eq!(policy.mode(1_000), Mode::Active);
+        assert_eq!(policy.mode(1_000 + IDLE_AFTER_SECS), Mode::Idle);
+        assert_eq!(policy.mode(1_300), Mode::Sleep);
+    }
+
+    #[test]
+    fn sample_interval() {
+        assert_eq!(Mode::Active.sample_secs(), ACTIVE_SAMPLE_SECS);
+        assert_eq!(Mode::Idle.sample_secs(), IDLE_SAMPLE_SECS);
+    }
+}
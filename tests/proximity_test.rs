@@ -0,0 +1,139 @@
+//! RSSI smoothing, proximity and leash tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::proximity::{
+        encode, Leash, Proximity, Rssi, HYSTERESIS_DB, IMMEDIATE_DBM, LEASH_AFTER_MS, NEAR_DBM,
+    };
+    use defmt::{assert, assert_eq};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn first_sample_is_taken_as_is() {
+        let mut rssi = Rssi::new();
+        assert_eq!(rssi.value(), None);
+        assert_eq!(rssi.update(-60), Some(-60));
+    }
+
+    #[test]
+    fn smoothing_damps_outliers() {
+        let mut rssi = Rssi::new();
+        rssi.update(-60);
+        // a single dropout moves the average by a quarter
+        assert_eq!(rssi.update(-80), Some(-65));
+        assert_eq!(rssi.update(-60), Some(-64));
+        // a steady level is reached eventually
+        for _ in 0..30 {
+            rssi.update(-70);
+        }
+        assert_eq!(rssi.value(), Some(-70));
+    }
+
+    #[test]
+    fn unavailable_samples_are_skipped() {
+        let mut rssi = Rssi::new();
+        assert_eq!(rssi.update(127), None);
+        rssi.update(-50);
+        assert_eq!(rssi.update(127), Some(-50));
+    }
+
+    #[test]
+    fn classify_without_history() {
+        assert_eq!(
+            Proximity::classify(IMMEDIATE_DBM, Proximity::Unknown),
+            Proximity::Immediate
+        );
+        assert_eq!(
+            Proximity::classify(IMMEDIATE_DBM - 1, Proximity::Unknown),
+            Proximity::Near
+        );
+        assert_eq!(
+            Proximity::classify(NEAR_DBM, Proximity::Unknown),
+            Proximity::Near
+        );
+        assert_eq!(
+            Proximity::classify(NEAR_DBM - 1, Proximity::Unknown),
+            Proximity::Far
+        );
+    }
+
+    #[test]
+    fn hysteresis_keeps_the_zone() {
+        // just past the boundary is not enough to leave
+        assert_eq!(
+            Proximity::classify(NEAR_DBM - 1, Proximity::Near),
+            Proximity::Near
+        );
+        assert_eq!(
+            Proximity::classify(NEAR_DBM - HYSTERESIS_DB - 1, Proximity::Near),
+            Proximity::Far
+        );
+        assert_eq!(
+            Proximity::classify(NEAR_DBM, Proximity::Far),
+            Proximity::Far
+        );
+        assert_eq!(
+            Proximity::classify(NEAR_DBM + HYSTERESIS_DB, Proximity::Far),
+            Proximity::Near
+        );
+        assert_eq!(
+            Proximity::classify(IMMEDIATE_DBM - HYSTERESIS_DB, Proximity::Immediate),
+            Proximity::Immediate
+        );
+        assert_eq!(
+            Proximity::classify(IMMEDIATE_DBM, Proximity::Near),
+            Proximity::Near
+        );
+    }
+
+    #[test]
+    fn leash_trips_after_staying_far() {
+        let mut leash = Leash::new();
+        assert!(!leash.update(Proximity::Near, 0));
+        assert!(!leash.update(Proximity::Far, 1_000));
+        assert!(!leash.update(Proximity::Far, LEASH_AFTER_MS));
+        assert!(leash.update(Proximity::Far, 1_000 + LEASH_AFTER_MS));
+        // coming back clears it
+        assert!(!leash.update(Proximity::Near, 20_000));
+    }
+
+    #[test]
+    fn leash_timer_restarts_when_close_again() {
+        let mut leash = Leash::new();
+        leash.update(Proximity::Far, 0);
+        leash.update(Proximity::Immediate, LEASH_AFTER_MS - 1);
+        assert!(!leash.update(Proximity::Far, LEASH_AFTER_MS));
+        assert!(leash.update(Proximity::Far, 2 * LEASH_AFTER_MS));
+    }
+
+    #[test]
+    fn lost_link_trips_until_acknowledged() {
+        let mut leash = Leash::new();
+        leash.lost();
+        assert!(leash.is_tripped());
+        // a reconnect without an RSSI yet changes nothing
+        assert!(leash.update(Proximity::Unknown, 0));
+        leash.acknowledge();
+        assert!(!leash.is_tripped());
+        // still far after acknowledging: goes off again later
+        assert!(!leash.update(Proximity::Far, 1_000));
+        assert!(leash.update(Proximity::Far, 1_000 + LEASH_AFTER_MS));
+    }
+
+    #[test]
+    fn encode_proximity() {
+        assert_eq!(encode(Some(-62), Proximity::Near, false), [0xC2, 2, 0]);
+        assert_eq!(encode(None, Proximity::Unknown, true), [0x7F, 0, 1]);
+    }
+}
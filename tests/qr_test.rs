@@ -0,0 +1,230 @@
+//! Pairing QR code tests: render into the framebuffer, flush to the mock display and read
+//! the code back from what the panel received.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[cfg(test)]
+use alloc::vec::Vec;
+#[cfg(test)]
+use coa_gatt::framebuffer::WIDTH;
+#[cfg(test)]
+use coa_gatt::qr::Layout;
+#[cfg(test)]
+use defmt::assert_eq;
+
+/// Data codewords per block and number of blocks for versions 1 to 3, by the ECC
+/// level's format bits (M, L, H, Q).
+#[cfg(test)]
+const BLOCKS: [[(usize, usize); 4]; 3] = [
+    [(16, 1), (19, 1), (9, 1), (13, 1)],
+    [(28, 1), (34, 1), (16, 1), (22, 1)],
+    [(44, 1), (55, 1), (13, 2), (17, 2)],
+];
+
+/// Module grid read back from panel memory.
+#[cfg(test)]
+struct Grid {
+    size: usize,
+    dark: Vec<bool>,
+}
+
+#[cfg(test)]
+impl Grid {
+    /// Sample the centre of every module. Dark modules are unlit pixels.
+    fn read(panel: &[u8], layout: &Layout) -> Self {
+        let size = layout.modules as usize;
+        let mut dark = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                let px = layout.top_left.x as usize
+                    + x * layout.scale as usize
+                    + layout.scale as usize / 2;
+                let py = layout.top_left.y as usize
+                    + y * layout.scale as usize
+                    + layout.scale as usize / 2;
+                dark.push(panel[(py / 8) * WIDTH + px] & (1 << (py % 8)) == 0);
+            }
+        }
+        Self { size, dark }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.size + x]
+    }
+
+    /// Finders with separators and format info, timing patterns and, from version 2,
+    /// the single alignment pattern.
+    fn is_function(&self, x: usize, y: usize) -> bool {
+        let n = self.size;
+        let alignment = n > 21 && x.abs_diff(n - 7) <= 2 && y.abs_diff(n - 7) <= 2;
+        let finder = (y < 9 && (x < 9 || x >= n - 8)) || (x < 9 && y >= n - 8);
+        finder || x == 6 || y == 6 || alignment
+    }
+
+    /// ECC level format bits and mask from the copy next to the top left finder.
+    fn format(&self) -> (usize, usize) {
+        let mut bits = 0u32;
+        let mut set = |i: usize, dark: bool| bits |= (dark as u32) << i;
+        for i in 0..6 {
+            set(i, self.get(8, i));
+        }
+        set(6, self.get(8, 7));
+        set(7, self.get(8, 8));
+        set(8, self.get(7, 8));
+        for i in 9..15 {
+            set(i, self.get(14 - i, 8));
+        }
+        let data = (bits ^ 0x5412) >> 10;
+        ((data >> 3) as usize, (data & 7) as usize)
+    }
+
+    /// All codewords in placement order, unmasked.
+    fn codewords(&self, mask: usize) -> Vec<u8> {
+        let n = self.size;
+        let mut bits = Vec::new();
+        let mut right = n - 1;
+        loop {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..n {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { n - 1 - vert } else { vert };
+                    if !self.is_function(x, y) {
+                        bits.push(self.get(x, y) ^ masked(mask, x, y));
+                    }
+                }
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+        bits.chunks_exact(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| (acc << 1) | bit as u8))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn masked(mask: usize, x: usize, y: usize) -> bool {
+    match mask {
+        0 => (x + y) % 2 == 0,
+        1 => y % 2 == 0,
+        2 => x % 3 == 0,
+        3 => (x + y) % 3 == 0,
+        4 => (x / 3 + y / 2) % 2 == 0,
+        5 => x * y % 2 + x * y % 3 == 0,
+        6 => (x * y % 2 + x * y % 3) % 2 == 0,
+        _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+    }
+}
+
+/// Decode a byte mode code without errors, versions 1 to 3 only.
+#[cfg(test)]
+fn decode(grid: &Grid) -> Vec<u8> {
+    let version = (grid.size - 17) / 4;
+    let (ecl, mask) = grid.format();
+    let (block_len, blocks) = BLOCKS[version - 1][ecl];
+    let codewords = grid.codewords(mask);
+
+    // data codewords are interleaved across equally long blocks
+    let mut data = Vec::new();
+    for block in 0..blocks {
+        for i in 0..block_len {
+            data.push(codewords[i * blocks + block]);
+        }
+    }
+
+    let bit = |i: usize| (data[i / 8] >> (7 - i % 8)) & 1;
+    let read =
+        |from: usize, len: usize| (from..from + len).fold(0, |acc, i| (acc << 1) | bit(i) as usize);
+    assert_eq!(read(0, 4), 0b0100, "not byte mode");
+    let len = read(4, 8);
+    (0..len).map(|i| read(12 + i * 8, 8) as u8).collect()
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::framebuffer::Framebuffer;
+    use coa_gatt::mock::MockDisplay;
+    use coa_gatt::qr::{self, QrError, QUIET_ZONE};
+    use defmt::{assert, assert_eq};
+    use embedded_graphics::prelude::*;
+    use esp_hal::timer::systimer::SystemTimer;
+
+    use super::{decode, Grid};
+
+    const ADDRESS: [u8; 6] = [0xF5, 0xF4, 0xF3, 0xF2, 0xF1, 0xF0];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        esp_alloc::heap_allocator!(size: 64 * 1024);
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn payload_encodes_name_address_and_nonce() {
+        let payload = qr::pairing_payload("COW GATT", &ADDRESS, 0x1A2B3C4D).unwrap();
+        assert_eq!(
+            payload.as_str(),
+            "cowgatt://p?n=COW%20GATT&a=F0F1F2F3F4F5&k=1A2B3C4D"
+        );
+    }
+
+    #[test]
+    async fn code_on_the_panel_decodes_to_the_payload() {
+        let payload = qr::pairing_payload("COW GATT", &ADDRESS, 0xDEADBEEF).unwrap();
+        let mut frame = Framebuffer::new();
+        let mut display = MockDisplay::new();
+        let layout = qr::draw(&mut frame, &payload, Point::zero(), 64).unwrap();
+        frame.flush(&mut display).await.unwrap();
+
+        // two pixels per module, quiet zone included, within the 64 px height
+        assert_eq!(layout.scale, 2);
+        assert!((layout.modules + 2 * QUIET_ZONE) * layout.scale <= 64);
+
+        let grid = Grid::read(&display.buffer(), &layout);
+        assert_eq!(decode(&grid).as_slice(), payload.as_bytes());
+    }
+
+    #[test]
+    async fn short_text_decodes_at_every_scale() {
+        for size in [25, 48, 64] {
+            let mut frame = Framebuffer::new();
+            let mut display = MockDisplay::new();
+            let layout = qr::draw(&mut frame, "moo", Point::new(30, 0), size).unwrap();
+            frame.flush(&mut display).await.unwrap();
+
+            let grid = Grid::read(&display.buffer(), &layout);
+            assert_eq!(decode(&grid).as_slice(), b"moo");
+        }
+    }
+
+    #[test]
+    fn too_long_text_is_rejected() {
+        let mut frame = Framebuffer::new();
+        let text = "cowgatt://p?n=a-device-name-that-is-far-too-long-for-version-3&a=F0F1F2F3F4F5";
+        assert!(matches!(
+            qr::draw(&mut frame, text, Point::zero(), 64),
+            Err(QrError::TooLong)
+        ));
+        // fits the version, but not in 20 px
+        assert!(matches!(
+            qr::draw(&mut frame, "moo", Point::zero(), 20),
+            Err(QrError::TooLong)
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! Relay frame, dedup and hop limit tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::advertising::Status;
+#[cfg(test)]
+use coa_gatt::alarm::AlarmState;
+#[cfg(test)]
+use coa_gatt::relay::Frame;
+
+#[cfg(test)]
+fn status(temperature: i16) -> Status {
+    Status {
+        temperature: Some(temperature),
+        battery: None,
+        alarm: AlarmState::Normal,
+        fault: false,
+    }
+}
+
+#[cfg(test)]
+fn frame(origin: [u8; 6], hops: u8, temperature: i16) -> Frame {
+    Frame {
+        origin,
+        hops,
+        status: status(temperature),
+    }
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::advertising::{ad_type, advertising_data, COMPANY_ID};
+    use coa_gatt::relay::{heard, Frame, Relay, DEDUP_SECS, FRAME_VERSION, MAX_HOPS, QUEUE_LEN};
+    use coa_gatt::scan::Kind;
+    use coa_gatt::settings::AdvertisedFields;
+    use defmt::{assert, assert_eq};
+
+    use super::{frame, status};
+
+    const OWN: [u8; 6] = [0x10, 0x11, 0x12, 0x13, 0x14, 0x15];
+    const A: [u8; 6] = [0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5];
+    const B: [u8; 6] = [0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn frame_layout_and_round_trip() {
+        let frame = frame(A, 2, 21_50);
+        let encoded = frame.encode();
+        assert_eq!(encoded[0], FRAME_VERSION);
+        assert_eq!(&encoded[1..7], &A);
+        assert_eq!(encoded[7], 2);
+        assert_eq!(&encoded[8..], &status(21_50).encode());
+        assert_eq!(Frame::decode(&encoded), Some(frame));
+
+        assert_eq!(Frame::decode(&encoded[..12]), None);
+        let mut status_record = encoded;
+        status_record[0] = 0x01;
+        assert_eq!(Frame::decode(&status_record), None);
+
+        let data = frame.advertising_data();
+        let bytes = data.as_bytes();
+        assert_eq!(bytes.len(), 3 + 2 + 2 + encoded.len());
+        assert_eq!(bytes[4], ad_type::MANUFACTURER_SPECIFIC_DATA);
+        assert_eq!(&bytes[5..7], &COMPANY_ID.to_le_bytes());
+    }
+
+    #[test]
+    fn status_records_and_frames_are_heard() {
+        // a board's own status record comes from the address it was heard from
+        let data = advertising_data(&AdvertisedFields::default(), &[0xAA; 16], &status(21_50));
+        let direct = heard(data.as_bytes(), A).unwrap();
+        assert_eq!(direct, frame(A, 0, 21_50));
+        assert_eq!(direct.reading().kind, Kind::Cow);
+
+        // a relayed one keeps its origin
+        let relayed = frame(A, 1, 21_50);
+        let heard_relayed = heard(relayed.advertising_data().as_bytes(), B).unwrap();
+        assert_eq!(heard_relayed, relayed);
+        let reading = heard_relayed.reading();
+        assert_eq!(reading.kind, Kind::Relayed);
+        assert_eq!(reading.temperature, Some(21_50));
+
+        assert_eq!(heard(&[0x02, 0x01, 0x06], A), None);
+        assert_eq!(heard(&[0x04, 0xFF, 0x4C, 0x00, 0x02], A), None);
+    }
+
+    #[test]
+    fn relays_once_with_one_more_hop() {
+        let mut relay: Relay<4> = Relay::new(OWN);
+        assert!(relay.accept(frame(A, 0, 21_50), 0));
+        assert_eq!(relay.pop(), Some(frame(A, 1, 21_50)));
+        assert_eq!(relay.pop(), None);
+
+        // heard again, directly or back from another relay
+        assert!(!relay.accept(frame(A, 0, 21_50), 5));
+        assert!(!relay.accept(frame(A, 2, 21_50), 10));
+        assert_eq!(relay.pending(), 0);
+
+        // a new reading is news
+        assert!(relay.accept(frame(A, 0, 22_00), 15));
+        // and an unchanged one is refreshed once the dedup window has passed
+        assert!(relay.accept(frame(A, 0, 21_50), DEDUP_SECS));
+        assert_eq!(relay.pending(), 2);
+    }
+
+    #[test]
+    fn own_records_and_far_travellers_are_dropped() {
+        let mut relay: Relay<4> = Relay::new(OWN);
+        assert!(!relay.accept(frame(OWN, 0, 21_50), 0));
+        assert!(!relay.accept(frame(OWN, 1, 21_50), 0));
+        assert!(!relay.accept(frame(A, MAX_HOPS, 21_50), 0));
+        assert!(relay.accept(frame(A, MAX_HOPS - 1, 21_50), 0));
+        assert_eq!(relay.pop().unwrap().hops, MAX_HOPS);
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest() {
+        let mut relay: Relay<16> = Relay::new(OWN);
+        for i in 0..QUEUE_LEN as i16 + 1 {
+            assert!(relay.accept(frame(A, 0, i), 0));
+        }
+        assert_eq!(relay.pending(), QUEUE_LEN);
+        assert_eq!(relay.pop().unwrap().status, status(1));
+    }
+
+    #[test]
+    fn full_cache_forgets_the_oldest() {
+        let mut relay: Relay<2> = Relay::new(OWN);
+        assert!(relay.accept(frame(A, 0, 21_50), 0));
+        assert!(relay.accept(frame(B, 0, 21_50), 1));
+        assert!(relay.accept(frame(A, 0, 22_00), 2));
+        // A at 21.50 °C made room, B is still remembered
+        assert!(!relay.accept(frame(B, 0, 21_50), 3));
+        assert!(relay.accept(frame(A, 0, 21_50), 4));
+    }
+}
@@ -0,0 +1,179 @@
+//! Nearby sensor parsing and bookkeeping tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::scan::{Kind, Reading};
+
+#[cfg(test)]
+fn reading(temperature: i16) -> Reading {
+    Reading {
+        kind: Kind::BtHome,
+        temperature: Some(temperature),
+        humidity: None,
+        pressure: None,
+        battery: None,
+        battery_mv: None,
+    }
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::advertising::{advertising_data, Status};
+    use coa_gatt::alarm::AlarmState;
+    use coa_gatt::scan::{parse, Kind, Neighbors, MAX_AGE_SECS};
+    use coa_gatt::settings::AdvertisedFields;
+    use defmt::{assert, assert_eq};
+
+    use super::reading;
+
+    const A: [u8; 6] = [0x01, 0x00, 0x00, 0x38, 0xC1, 0xA4];
+    const B: [u8; 6] = [0x02, 0x00, 0x00, 0x38, 0xC1, 0xA4];
+    const C: [u8; 6] = [0x03, 0x00, 0x00, 0x38, 0xC1, 0xA4];
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn bthome_spec_example() {
+        // bthome.io example: 25.00 °C and 50.55 %
+        let data = [
+            0x02, 0x01, 0x06, 0x0A, 0x16, 0xD2, 0xFC, 0x40, 0x02, 0xC4, 0x09, 0x03, 0xBF, 0x13,
+        ];
+        let reading = parse(&data).unwrap();
+        assert_eq!(reading.kind, Kind::BtHome);
+        assert_eq!(reading.temperature, Some(25_00));
+        assert_eq!(reading.humidity, Some(50_55));
+        assert_eq!(reading.pressure, None);
+    }
+
+    #[test]
+    fn bthome_other_objects() {
+        // packet id, battery 97 %, 1008.83 hPa, 3.074 V, 27.3 °C in 0.1 °C, 35 % in whole percent
+        let data = [
+            0x14, 0x16, 0xD2, 0xFC, 0x40, 0x00, 0x07, 0x01, 0x61, 0x04, 0x13, 0x8A, 0x01, 0x0C,
+            0x02, 0x0C, 0x45, 0x11, 0x01, 0x2E, 0x23,
+        ];
+        let reading = parse(&data).unwrap();
+        assert_eq!(reading.battery, Some(97));
+        assert_eq!(reading.pressure, Some(1_008_830));
+        assert_eq!(reading.battery_mv, Some(3074));
+        assert_eq!(reading.temperature, Some(27_30));
+        assert_eq!(reading.humidity, Some(35_00));
+    }
+
+    #[test]
+    fn bthome_keeps_objects_before_an_unknown_one() {
+        let data = [0x09, 0x16, 0xD2, 0xFC, 0x40, 0x02, 0xC4, 0x09, 0xEE, 0x01];
+        assert_eq!(parse(&data).unwrap().temperature, Some(25_00));
+
+        // encrypted or another version
+        let mut encrypted = data;
+        encrypted[4] = 0x41;
+        assert_eq!(parse(&encrypted), None);
+        let mut version_1 = data;
+        version_1[4] = 0x20;
+        assert_eq!(parse(&version_1), None);
+    }
+
+    #[test]
+    fn custom_xiaomi_formats() {
+        // ATC1441: 21.5 °C, 45 %, 85 %, 3000 mV, big-endian
+        let atc = [
+            0x10, 0x16, 0x1A, 0x18, 0xA4, 0xC1, 0x38, 0x00, 0x00, 0x01, 0x00, 0xD7, 0x2D, 0x55,
+            0x0B, 0xB8, 0x07,
+        ];
+        let reading = parse(&atc).unwrap();
+        assert_eq!(reading.kind, Kind::Atc1441);
+        assert_eq!(reading.temperature, Some(21_50));
+        assert_eq!(reading.humidity, Some(45_00));
+        assert_eq!(reading.battery, Some(85));
+        assert_eq!(reading.battery_mv, Some(3000));
+
+        // pvvx: 21.50 °C, 45.50 %, 3000 mV, 85 %, little-endian
+        let pvvx = [
+            0x12, 0x16, 0x1A, 0x18, 0x01, 0x00, 0x00, 0x38, 0xC1, 0xA4, 0x66, 0x08, 0xC6, 0x11,
+            0xB8, 0x0B, 0x55, 0x07, 0x00,
+        ];
+        let reading = parse(&pvvx).unwrap();
+        assert_eq!(reading.kind, Kind::Pvvx);
+        assert_eq!(reading.temperature, Some(21_50));
+        assert_eq!(reading.humidity, Some(45_50));
+        assert_eq!(reading.battery, Some(85));
+        assert_eq!(reading.battery_mv, Some(3000));
+    }
+
+    #[test]
+    fn other_cow_boards() {
+        let status = Status {
+            temperature: Some(21_50),
+            battery: Some(87),
+            alarm: AlarmState::Normal,
+            fault: false,
+        };
+        let data = advertising_data(&AdvertisedFields::default(), &[0xAA; 16], &status);
+        let reading = parse(data.as_bytes()).unwrap();
+        assert_eq!(reading.kind, Kind::Cow);
+        assert_eq!(reading.temperature, Some(21_50));
+        assert_eq!(reading.battery, Some(87));
+    }
+
+    #[test]
+    fn unknown_and_malformed_data() {
+        assert_eq!(parse(&[]), None);
+        assert_eq!(parse(&[0x02, 0x01, 0x06]), None);
+        // another company's manufacturer data
+        assert_eq!(parse(&[0x04, 0xFF, 0x4C, 0x00, 0x02]), None);
+        // a length running past the end
+        assert_eq!(parse(&[0x0A, 0x16, 0xD2, 0xFC, 0x40]), None);
+    }
+
+    #[test]
+    fn neighbors_replace_evict_and_expire() {
+        let mut neighbors: Neighbors<2> = Neighbors::new();
+        neighbors.update(A, -60, reading(20_00), 10);
+        neighbors.update(B, -70, reading(21_00), 20);
+        neighbors.update(A, -65, reading(22_00), 30);
+        assert_eq!(neighbors.len(), 2);
+        let a = neighbors.iter().find(|n| n.address == A).unwrap();
+        assert_eq!((a.rssi, a.seen), (-65, 30));
+        assert_eq!(a.reading.temperature, Some(22_00));
+
+        // full, B was heard least recently
+        neighbors.update(C, -80, reading(23_00), 40);
+        assert_eq!(neighbors.len(), 2);
+        assert!(neighbors.iter().all(|n| n.address != B));
+
+        neighbors.expire(30 + MAX_AGE_SECS);
+        assert_eq!(neighbors.len(), 1);
+        assert!(neighbors.iter().all(|n| n.address == C));
+        neighbors.expire(40 + MAX_AGE_SECS);
+        assert!(neighbors.is_empty());
+    }
+
+    #[test]
+    fn neighbor_encoding() {
+        let mut neighbors: Neighbors<1> = Neighbors::new();
+        neighbors.update(A, -60, reading(21_50), 100);
+        let neighbor = neighbors.iter().next().unwrap();
+        assert_eq!(
+            neighbor.encode(130),
+            [
+                0x01, 0x00, 0x00, 0x38, 0xC1, 0xA4, // address
+                0x01, // BTHome
+                0xC4, // -60 dBm
+                0x1E, 0x00, // heard 30 s ago
+                0x66, 0x08, // 21.50 °C
+                0xFF, 0xFF, // no humidity
+                0xFF, // no battery
+            ]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Sensor driver and I2C bus tests against mock sensors and a mock I2C bus.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::sensor::sht::crc8;
+
+/// 25 °C / 56.5 %RH as an SHT4x reply, each word followed by its CRC.
+#[cfg(test)]
+fn sht4x_reply() -> [u8; 6] {
+    let t = 0x6666u16.to_be_bytes();
+    let rh = 0x8000u16.to_be_bytes();
+    [t[0], t[1], crc8(&t), rh[0], rh[1], crc8(&rh)]
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::i2c_bus::scan;
+    use coa_gatt::sensor::bme280::Bme280;
+    use coa_gatt::sensor::mock::{MockI2c, MockSensor};
+    use coa_gatt::sensor::sht::{crc8, Sht4x};
+    use coa_gatt::sensor::{
+        find, measures, names, Measurement, Metadata, Quantity, Sensor, SensorError,
+    };
+    use defmt::{assert, assert_eq};
+    use esp_hal::timer::systimer::SystemTimer;
+
+    use super::sht4x_reply;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn sensirion_crc_matches_datasheet() {
+        assert_eq!(crc8(&[0xBE, 0xEF]), 0x92);
+    }
+
+    #[test]
+    async fn sht4x_converts_raw_values() {
+        let reply = sht4x_reply();
+        let mut sensor = Sht4x::probe(MockI2c::new(0x44, &reply), 0x44)
+            .await
+            .unwrap();
+        let readings = sensor.sample().await.unwrap();
+
+        let t = find(&readings, Quantity::Temperature).unwrap();
+        let rh = find(&readings, Quantity::Humidity).unwrap();
+        assert!((t - 25.0).abs() < 0.01);
+        assert!((rh - 56.5).abs() < 0.01);
+    }
+
+    #[test]
+    async fn sht4x_rejects_bad_crc() {
+        let mut reply = sht4x_reply();
+        reply[2] ^= 0xFF;
+        let result = Sht4x::probe(MockI2c::new(0x44, &reply), 0x44).await;
+        assert!(matches!(result, Err(SensorError::NotDetected)));
+    }
+
+    #[test]
+    async fn bme280_checks_chip_id() {
+        // a BMP280 answers with chip ID 0x58 and has no humidity sensor
+        let result = Bme280::probe(MockI2c::new(0x76, &[0x58]), 0x76).await;
+        assert!(matches!(result, Err(SensorError::NotDetected)));
+        // nothing at the alternate address
+        let result = Bme280::probe(MockI2c::new(0x76, &[0x60]), 0x77).await;
+        assert!(matches!(result, Err(SensorError::I2c(_))));
+    }
+
+    #[test]
+    async fn mock_sensor_replays_samples() {
+        static SAMPLES: [&[Measurement]; 2] = [
+            &[Measurement {
+                quantity: Quantity::Temperature,
+                value: 21.0,
+            }],
+            &[Measurement {
+                quantity: Quantity::Temperature,
+                value: 22.5,
+            }],
+        ];
+        let mut sensor = MockSensor::temperature(&SAMPLES);
+        assert!(sensor.metadata().ambient);
+
+        let values = [21.0, 22.5, 22.5];
+        for expected in values {
+            let readings = sensor.sample().await.unwrap();
+            assert_eq!(find(&readings, Quantity::Temperature), Some(expected));
+        }
+    }
+
+    #[test]
+    fn registry_records_address_quantities_and_ambient() {
+        let bme280 = Metadata {
+            name: "BME280",
+            quantities: &[
+                Quantity::Temperature,
+                Quantity::Humidity,
+                Quantity::Pressure,
+            ],
+            address: Some(0x76),
+            ambient: true,
+        };
+        let die = Metadata {
+            name: "die",
+            quantities: &[Quantity::Temperature],
+            address: None,
+            ambient: false,
+        };
+        assert_eq!(bme280.record(), [0x76, 0b111, 1]);
+        assert_eq!(die.record(), [0x00, 0b001, 0]);
+
+        assert!(measures(&[bme280], Quantity::Pressure));
+        assert!(!measures(&[die], Quantity::Humidity));
+        assert_eq!(names(&[bme280, die]).as_str(), "BME280,die");
+        assert_eq!(names(&[]).as_str(), "");
+    }
+
+    #[test]
+    async fn scan_finds_only_present_device() {
+        // an SSD1306 at its usual address
+        let mut bus = MockI2c::new(0x3C, &[]);
+        let found = scan(&mut bus).await;
+        assert!(found.contains(0x3C));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found.iter().next(), Some(0x3C));
+    }
+}
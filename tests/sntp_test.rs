@@ -0,0 +1,78 @@
+//! SNTP packet tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+use coa_gatt::sntp::PACKET_LEN;
+
+/// A server reply (version 4, mode 4, stratum 2) with the given transmit timestamp.
+#[cfg(test)]
+fn reply(secs: u32, fraction: u32) -> [u8; PACKET_LEN] {
+    let mut buf = [0; PACKET_LEN];
+    buf[0] = (4 << 3) | 4;
+    buf[1] = 2;
+    buf[40..44].copy_from_slice(&secs.to_be_bytes());
+    buf[44..48].copy_from_slice(&fraction.to_be_bytes());
+    buf
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::sntp::{decode_reply, encode_request, SntpError, PACKET_LEN};
+    use defmt::assert_eq;
+
+    use super::reply;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn request() {
+        let mut buf = [0xAA; PACKET_LEN];
+        encode_request(&mut buf);
+        assert_eq!(buf[0], 0x23);
+        assert_eq!(buf[1..], [0; PACKET_LEN - 1]);
+    }
+
+    #[test]
+    fn transmit_timestamp() {
+        // 2024-01-01T00:00:00.500Z
+        let secs = (1_704_067_200u64 + 2_208_988_800) as u32;
+        assert_eq!(decode_reply(&reply(secs, 1 << 31)), Ok(1_704_067_200_500));
+        // the Unix epoch itself
+        assert_eq!(decode_reply(&reply(2_208_988_800, 0)), Ok(0));
+    }
+
+    #[test]
+    fn era_rollover() {
+        // 2036-02-07T06:28:16Z wraps the 32-bit seconds to 0, one second later is 1
+        assert_eq!(decode_reply(&reply(1, 0)), Ok(2_085_978_497_000));
+    }
+
+    #[test]
+    fn rejected_replies() {
+        assert_eq!(decode_reply(&[0x24; 47]), Err(SntpError::TooShort));
+
+        let mut client = reply(3_913_056_000, 0);
+        client[0] = 0x23;
+        assert_eq!(decode_reply(&client), Err(SntpError::NotAServerReply));
+
+        // kiss-o'-death
+        let mut kod = reply(3_913_056_000, 0);
+        kod[1] = 0;
+        assert_eq!(decode_reply(&kod), Err(SntpError::Unsynchronized));
+
+        let mut unsynced = reply(3_913_056_000, 0);
+        unsynced[0] |= 3 << 6;
+        assert_eq!(decode_reply(&unsynced), Err(SntpError::Unsynchronized));
+
+        assert_eq!(decode_reply(&reply(0, 0)), Err(SntpError::ZeroTimestamp));
+    }
+}
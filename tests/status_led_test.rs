@@ -0,0 +1,130 @@
+//! Status LED pattern tests, recording what the sequencer writes to a mock LED.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[cfg(test)]
+use alloc::vec::Vec;
+#[cfg(test)]
+use coa_gatt::mock::MockLed;
+#[cfg(test)]
+use coa_gatt::status_led::{Color, Sequencer};
+
+/// Run `sequencer` from `from` until `until` ms, waking up when it asks to, and return
+/// the colours written with their times.
+#[cfg(test)]
+async fn record(sequencer: &mut Sequencer, from: u64, until: u64) -> Vec<(u64, Color)> {
+    let mut led = MockLed::new();
+    let mut writes = Vec::new();
+    let mut now = from;
+    while now < until {
+        let remaining = sequencer.update(&mut led, now).await.unwrap();
+        writes.extend(led.take_writes().into_iter().map(|color| (now, color)));
+        now += remaining;
+    }
+    writes
+}
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::status_led::{
+        Color, Indicators, Pattern, Sequencer, Status, ADVERTISING, CONNECTED, ERROR, LEASH, OTA,
+        PAIRING,
+    };
+    use defmt::{assert, assert_eq};
+    use esp_hal::timer::systimer::SystemTimer;
+
+    use super::record;
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        esp_alloc::heap_allocator!(size: 64 * 1024);
+
+        let timer0 = SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn most_important_indicator_wins() {
+        let mut indicators = Indicators::default();
+        assert_eq!(indicators.status(), Status::Advertising);
+        indicators.connected = true;
+        assert_eq!(indicators.status(), Status::Connected);
+        indicators.pairing = true;
+        assert_eq!(indicators.status(), Status::Pairing);
+        indicators.ota = true;
+        assert_eq!(indicators.status(), Status::Ota);
+        indicators.leash = true;
+        assert_eq!(indicators.status(), Status::Leash);
+        indicators.error = true;
+        assert_eq!(indicators.status(), Status::Error);
+    }
+
+    #[test]
+    fn patterns_are_playable() {
+        let patterns: [Pattern; 6] = [ADVERTISING, CONNECTED, PAIRING, OTA, LEASH, ERROR];
+        for pattern in patterns {
+            assert!(!pattern.is_empty());
+            assert!(pattern.iter().all(|step| step.ms > 0));
+        }
+    }
+
+    #[test]
+    async fn blinking_pattern_writes_each_change() {
+        let mut sequencer = Sequencer::new(ADVERTISING, 0);
+        let writes = record(&mut sequencer, 0, 4100).await;
+        assert_eq!(
+            writes.as_slice(),
+            &[
+                (0, Color::BLUE),
+                (50, Color::OFF),
+                (2000, Color::BLUE),
+                (2050, Color::OFF),
+                (4000, Color::BLUE),
+                (4050, Color::OFF),
+            ]
+        );
+    }
+
+    #[test]
+    async fn steady_pattern_writes_once() {
+        let mut sequencer = Sequencer::new(CONNECTED, 0);
+        let writes = record(&mut sequencer, 0, 10_000).await;
+        assert_eq!(writes.as_slice(), &[(0, Color::GREEN)]);
+    }
+
+    #[test]
+    async fn error_flashes_three_times_per_period() {
+        let mut sequencer = Sequencer::new(ERROR, 0);
+        let writes = record(&mut sequencer, 0, 1500).await;
+        let flashes = writes
+            .iter()
+            .filter(|(_, color)| *color == Color::RED)
+            .count();
+        assert_eq!(flashes, 3);
+    }
+
+    #[test]
+    async fn new_pattern_starts_from_its_first_step() {
+        let mut sequencer = Sequencer::new(ADVERTISING, 0);
+        record(&mut sequencer, 0, 1000).await;
+
+        // the running pattern keeps its phase
+        sequencer.set_pattern(ADVERTISING, 1000);
+        assert_eq!(sequencer.color(1000), (Color::OFF, 1000));
+
+        sequencer.set_pattern(PAIRING, 1000);
+        let writes = record(&mut sequencer, 1000, 1400).await;
+        assert_eq!(
+            writes.as_slice(),
+            &[(1000, Color::BLUE), (1150, Color::OFF), (1300, Color::BLUE)]
+        );
+    }
+}
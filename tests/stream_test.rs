@@ -0,0 +1,132 @@
+//! L2CAP stream tests, with the test standing in for the channel side.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::stream::{parse_psm, Stream, PSM_MAX, PSM_MIN, SDU_MAX};
+    use defmt::{assert, assert_eq};
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn parse_psm_values() {
+        assert_eq!(parse_psm("0x0080"), Some(PSM_MIN));
+        assert_eq!(parse_psm("0XFF"), Some(PSM_MAX));
+        assert_eq!(parse_psm("200"), Some(200));
+    }
+
+    #[test]
+    fn reject_invalid_psm() {
+        // fixed and SIG assigned PSMs
+        assert_eq!(parse_psm("0x0025"), None);
+        assert_eq!(parse_psm("127"), None);
+        assert_eq!(parse_psm("0x0100"), None);
+        assert_eq!(parse_psm(""), None);
+        assert_eq!(parse_psm("0x"), None);
+        assert_eq!(parse_psm("psm"), None);
+    }
+
+    #[test]
+    async fn central_to_application() {
+        let stream: Stream<64> = Stream::new();
+        stream.set_open(true);
+        assert!(stream.is_open());
+        stream.deliver(&[1, 2, 3]).await;
+        stream.deliver(&[4, 5]).await;
+
+        let mut buf = [0u8; 16];
+        let len = stream.read(&mut buf).await;
+        assert_eq!(&buf[..len], &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    async fn application_to_central_in_sdus() {
+        let stream: Stream<1024> = Stream::new();
+        stream.set_open(true);
+        let data = [0xA5u8; SDU_MAX + 10];
+        stream.write(&data).await;
+
+        let mut sdu = [0u8; SDU_MAX];
+        assert_eq!(stream.next_sdu(&mut sdu).await, SDU_MAX);
+        assert_eq!(stream.next_sdu(&mut sdu).await, 10);
+    }
+
+    #[test]
+    async fn slow_reader_holds_back_the_channel() {
+        // more than the buffer: delivering only finishes as the application reads
+        let stream: Stream<8> = Stream::new();
+        stream.set_open(true);
+        let deliver = async {
+            stream.deliver(&[7; 20]).await;
+        };
+        let read = async {
+            let mut total = 0;
+            let mut buf = [0u8; 3];
+            while total < 20 {
+                let len = stream.read(&mut buf).await;
+                assert!(buf[..len].iter().all(|&b| b == 7));
+                total += len;
+            }
+            total
+        };
+        let ((), total) = join(deliver, read).await;
+        assert_eq!(total, 20);
+    }
+
+    #[test]
+    async fn closing_drops_buffered_data() {
+        let stream: Stream<64> = Stream::new();
+        stream.set_open(true);
+        stream.write(&[1, 2, 3]).await;
+        stream.deliver(&[4, 5, 6]).await;
+        stream.set_open(false);
+        assert!(!stream.is_open());
+
+        // only what comes after the next open gets through
+        stream.set_open(true);
+        stream.write(&[9]).await;
+        let mut sdu = [0u8; SDU_MAX];
+        let len = stream.next_sdu(&mut sdu).await;
+        assert_eq!(&sdu[..len], &[9]);
+    }
+
+    #[test]
+    async fn echo_returns_what_the_central_sends() {
+        // more than the buffer, so echoing has to keep up with the central
+        let stream: Stream<64> = Stream::new();
+        stream.set_open(true);
+        let central = async {
+            let sent = [0x3Cu8; 300];
+            let deliver = async {
+                for chunk in sent.chunks(SDU_MAX) {
+                    stream.deliver(chunk).await;
+                }
+            };
+            let receive = async {
+                let mut total = 0;
+                let mut sdu = [0u8; SDU_MAX];
+                while total < sent.len() {
+                    let len = stream.next_sdu(&mut sdu).await;
+                    assert!(sdu[..len].iter().all(|&b| b == 0x3C));
+                    total += len;
+                }
+                total
+            };
+            join(deliver, receive).await.1
+        };
+        match select(stream.echo(), central).await {
+            Either::Second(total) => assert_eq!(total, 300),
+        }
+    }
+}
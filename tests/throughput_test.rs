@@ -0,0 +1,84 @@
+//! Throughput test request and result tests.
+
+#![no_std]
+#![no_main]
+
+#[cfg(test)]
+#[embedded_test::tests(executor = esp_hal_embassy::Executor::new())]
+mod tests {
+    use coa_gatt::throughput::{Direction, Meter, Request, SECS_MAX};
+    use defmt::{assert, assert_eq};
+
+    #[init]
+    fn init() {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+        let timer0 = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER);
+        esp_hal_embassy::init(timer0.alarm0);
+        rtt_target::rtt_init_defmt!();
+    }
+
+    #[test]
+    fn parse_request() {
+        assert_eq!(
+            Request::parse(&[1, 10]),
+            Some(Request {
+                direction: Direction::Send,
+                secs: 10
+            })
+        );
+        assert_eq!(
+            Request::parse(&[2, SECS_MAX]),
+            Some(Request {
+                direction: Direction::Receive,
+                secs: SECS_MAX
+            })
+        );
+    }
+
+    #[test]
+    fn reject_invalid_request() {
+        assert_eq!(Request::parse(&[]), None);
+        assert_eq!(Request::parse(&[1]), None);
+        assert_eq!(Request::parse(&[1, 10, 0]), None);
+        assert_eq!(Request::parse(&[0, 10]), None);
+        assert_eq!(Request::parse(&[3, 10]), None);
+        assert_eq!(Request::parse(&[1, 0]), None);
+        assert_eq!(Request::parse(&[1, SECS_MAX + 1]), None);
+    }
+
+    #[test]
+    fn meter_rate() {
+        let mut meter = Meter::new(Direction::Send, 1_000);
+        assert_eq!(meter.rate(1_000), 0);
+        for _ in 0..100 {
+            meter.add(244);
+        }
+        assert_eq!(meter.bytes, 24_400);
+        assert_eq!(meter.packets, 100);
+        assert_eq!(meter.rate(3_000), 12_200);
+        // a clock running backwards does not divide by zero
+        assert_eq!(meter.rate(500), 0);
+    }
+
+    #[test]
+    fn meter_saturates() {
+        let mut meter = Meter {
+            bytes: u32::MAX - 1,
+            ..Meter::new(Direction::Receive, 0)
+        };
+        meter.add(244);
+        assert_eq!(meter.bytes, u32::MAX);
+        assert!(meter.rate(1) == u32::MAX);
+    }
+
+    #[test]
+    fn encode_result() {
+        let mut meter = Meter::new(Direction::Receive, 0);
+        meter.add(200);
+        meter.add(300);
+        assert_eq!(
+            meter.encode(2_000),
+            [2, 0xF4, 0x01, 0, 0, 2, 0, 0, 0, 0xFA, 0, 0, 0]
+        );
+    }
+}